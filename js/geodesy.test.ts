@@ -1,5 +1,6 @@
 import {test, describe, expect} from 'bun:test';
 import {Geodesy, Coord2D, Coord3D} from './geodesy';
+import {registerGridSync} from '@geodesy-wasm';
 ``;
 const gsbPipelineDefinition = `
       | tmerc inv lat_0=49 lon_0=-2 k_0=0.9996012717 x_0=400000 y_0=-100000 ellps=airy
@@ -165,5 +166,19 @@ describe('Geodesy', () => {
       ).toThrow();
       ctx['ctx'].free();
     });
+
+    test('Should error if grid options have the wrong types', () => {
+      const grid = new DataView(new ArrayBuffer(0));
+      for (const options of [
+        {format: 1},
+        {onProgress: 'progress'},
+        {headers: {Authorization: 1}},
+      ]) {
+        // @ts-ignore
+        expect(() => registerGridSync('bad-options', grid, options)).toThrow(
+          /must be|Invalid request header/,
+        );
+      }
+    });
   });
 });
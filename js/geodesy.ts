@@ -192,6 +192,51 @@ function tidyProjString(projString: string): string {
 
 export * as GeodesyWasm from '@geodesy-wasm';
 
+// ----- Errors -----
+
+/**
+ * Stable codes carried by errors thrown from the wasm bindings.
+ */
+export type GeodesyErrorCode =
+  | 'RG_ERROR'
+  | 'MISSING_GRID'
+  | 'INVALID'
+  | 'NETWORK';
+
+/**
+ * The shape of errors thrown from the wasm bindings.
+ * The `name` identifies the kind of error, eg `MissingGridError`.
+ */
+export interface GeodesyError extends Error {
+  code: GeodesyErrorCode;
+  /** Index of the failing step in the definition, when known */
+  step?: number;
+  /** The grid key which could not be found for `MISSING_GRID` errors */
+  gridKey?: string;
+  /** The message of the underlying error */
+  cause?: string;
+}
+
+export function isGeodesyError(error: unknown): error is GeodesyError {
+  return (
+    error instanceof Error && typeof (error as GeodesyError).code === 'string'
+  );
+}
+
+// ----- Logging -----
+
+export type GeodesyLogLevel = 'ERROR' | 'WARN' | 'INFO' | 'DEBUG' | 'TRACE';
+
+/**
+ * The record passed to the callback given to `GeodesyWasm.initLogger`.
+ */
+export interface GeodesyLogRecord {
+  level: GeodesyLogLevel;
+  /** The Rust module which emitted the record, eg `geodesy_wasm::geodesy::grids` */
+  target: string;
+  message: string;
+}
+
 // ----- Coordinates -----
 export type CoordTuple2D = [number, number];
 export type CoordTuple3D = [number, number, number];
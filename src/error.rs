@@ -1,5 +1,13 @@
-use wasm_bindgen::JsError;
-pub type WasmResult<T> = std::result::Result<T, JsError>;
+use js_sys::Reflect;
+use wasm_bindgen::JsValue;
+
+/// Errors surfaced to JS are `Error` objects whose `name` identifies the kind of error
+/// (`GeodesyError`, `MissingGridError`, `InvalidError`, `NetworkError`) and which carry:
+/// - `code`: a stable, machine readable code (`RG_ERROR`, `MISSING_GRID`, `INVALID`, `NETWORK`)
+/// - `step`: the index of the failing step in the definition, when known
+/// - `gridKey`: the key of the missing grid for `MISSING_GRID`
+/// - `cause`: the message of the underlying error, when there is one
+pub type WasmResult<T> = std::result::Result<T, Error>;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -11,5 +19,104 @@ pub enum Error {
 
     #[error("Invalid: {0}")]
     Invalid(String),
+
+    #[error("Network: {0}")]
+    Network(String),
+
+    #[error("Step {step}: {source}")]
+    Step {
+        step: usize,
+        #[source]
+        source: Box<Error>,
+    },
 }
 pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Attach the index of the failing step in a definition
+    pub fn in_step(self, step: usize) -> Error {
+        match self {
+            Error::Step { source, .. } => Error::Step { step, source },
+            error => Error::Step {
+                step,
+                source: Box::new(error),
+            },
+        }
+    }
+
+    /// A stable code identifying the kind of error
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::RgError(_) => "RG_ERROR",
+            Error::MissingGrid(_) => "MISSING_GRID",
+            Error::Invalid(_) => "INVALID",
+            Error::Network(_) => "NETWORK",
+            Error::Step { source, .. } => source.code(),
+        }
+    }
+
+    /// The name of the JS error class
+    pub fn name(&self) -> &'static str {
+        match self {
+            Error::RgError(_) => "GeodesyError",
+            Error::MissingGrid(_) => "MissingGridError",
+            Error::Invalid(_) => "InvalidError",
+            Error::Network(_) => "NetworkError",
+            Error::Step { source, .. } => source.name(),
+        }
+    }
+
+    pub fn step(&self) -> Option<usize> {
+        match self {
+            Error::Step { step, .. } => Some(*step),
+            _ => None,
+        }
+    }
+
+    pub fn grid_key(&self) -> Option<&str> {
+        match self {
+            Error::MissingGrid(key) => Some(key),
+            Error::Step { source, .. } => source.grid_key(),
+            _ => None,
+        }
+    }
+
+    fn cause(&self) -> Option<String> {
+        match self {
+            Error::RgError(e) => Some(e.to_string()),
+            Error::Step { source, .. } => source.cause(),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for JsValue {
+    fn from(error: Error) -> JsValue {
+        let js_error = js_sys::Error::new(&error.to_string());
+        js_error.set_name(error.name());
+
+        let mut properties = vec![("code", JsValue::from_str(error.code()))];
+        if let Some(step) = error.step() {
+            properties.push(("step", JsValue::from(step as u32)));
+        }
+        if let Some(key) = error.grid_key() {
+            properties.push(("gridKey", JsValue::from_str(key)));
+        }
+        if let Some(cause) = error.cause() {
+            properties.push(("cause", JsValue::from(cause)));
+        }
+
+        for (key, value) in properties {
+            // Setting a property on a fresh Error object cannot fail
+            let _ = Reflect::set(&js_error, &JsValue::from_str(key), &value);
+        }
+
+        js_error.into()
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Error::Network(error.to_string())
+    }
+}
@@ -0,0 +1,112 @@
+//! Linear least squares adjustment by normal equations.
+//!
+//! Used to fit transformation parameters to points measured in two systems. The problems are
+//! small (a handful of unknowns), so the normal equations are solved directly by Gaussian
+//! elimination with partial pivoting.
+use crate::error::{Error, Result};
+
+/// Accumulates observation equations `row · x = observation` and solves them for `x`.
+pub(crate) struct Adjustment {
+    unknowns: usize,
+    // The normal matrix, row-major
+    normal: Vec<f64>,
+    rhs: Vec<f64>,
+    observations: usize,
+}
+
+impl Adjustment {
+    pub(crate) fn new(unknowns: usize) -> Adjustment {
+        Adjustment {
+            unknowns,
+            normal: vec![0.; unknowns * unknowns],
+            rhs: vec![0.; unknowns],
+            observations: 0,
+        }
+    }
+
+    /// Add an observation of unit weight
+    pub(crate) fn observe(&mut self, row: &[f64], observation: f64) {
+        let n = self.unknowns;
+        debug_assert_eq!(row.len(), n);
+        for i in 0..n {
+            for j in 0..n {
+                self.normal[i * n + j] += row[i] * row[j];
+            }
+            self.rhs[i] += row[i] * observation;
+        }
+        self.observations += 1;
+    }
+
+    /// The least squares estimate of the unknowns. Fails for fewer observations than unknowns
+    /// and for observations that do not determine all of them, eg points on a line.
+    pub(crate) fn solve(&self) -> Result<Vec<f64>> {
+        let n = self.unknowns;
+        if self.observations < n {
+            return Err(Error::Invalid(format!(
+                "{} observations can not determine {} unknowns",
+                self.observations, n
+            )));
+        }
+        let (mut a, mut b) = (self.normal.clone(), self.rhs.clone());
+        // Pivots this much smaller than the largest diagonal element are taken as zero
+        let tolerance = 1e-12 * (0..n).map(|i| a[i * n + i]).fold(0., f64::max);
+
+        for col in 0..n {
+            let pivot = (col..n)
+                .max_by(|&i, &j| a[i * n + col].abs().total_cmp(&a[j * n + col].abs()))
+                .unwrap_or(col);
+            if a[pivot * n + col].is_nan() || a[pivot * n + col].abs() <= tolerance {
+                return Err(Error::Invalid(
+                    "The observations do not determine all unknowns".to_string(),
+                ));
+            }
+            for k in 0..n {
+                a.swap(col * n + k, pivot * n + k);
+            }
+            b.swap(col, pivot);
+
+            for row in col + 1..n {
+                let factor = a[row * n + col] / a[col * n + col];
+                for k in col..n {
+                    a[row * n + k] -= factor * a[col * n + k];
+                }
+                b[row] -= factor * b[col];
+            }
+        }
+
+        let mut x = vec![0.; n];
+        for row in (0..n).rev() {
+            let sum: f64 = (row + 1..n).map(|k| a[row * n + k] * x[k]).sum();
+            x[row] = (b[row] - sum) / a[row * n + row];
+        }
+        Ok(x)
+    }
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_eq::assert_float_eq;
+
+    #[test]
+    fn straight_line() -> Result<()> {
+        // y = 2 + 3x, with the middle point off by 0.3
+        let mut adjustment = Adjustment::new(2);
+        for (x, y) in [(0., 2.), (1., 5.3), (2., 8.)] {
+            adjustment.observe(&[1., x], y);
+        }
+        let x = adjustment.solve()?;
+        assert_float_eq!(x[0], 2.1, abs <= 1e-12);
+        assert_float_eq!(x[1], 3., abs <= 1e-12);
+
+        // Too few and degenerate observations
+        let mut adjustment = Adjustment::new(2);
+        adjustment.observe(&[1., 1.], 5.);
+        assert!(adjustment.solve().is_err());
+        adjustment.observe(&[2., 2.], 10.);
+        assert!(adjustment.solve().is_err());
+        Ok(())
+    }
+}
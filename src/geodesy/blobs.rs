@@ -0,0 +1,218 @@
+//! Auxiliary data for operators, eg lookup tables, deformation models or polynomial coefficients.
+//!
+//! Operators read blobs by key through [geodesy_rs::Context::get_blob] when they are instantiated.
+//! Blobs are registered globally like grids, or in a [GridNamespace](super::grids::GridNamespace)
+//! which is searched first by the [Geo](crate::geodesy::context::Geo) instances using it.
+use super::grids::{self, GridOptions, JsGridOptions};
+use crate::error::WasmResult;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+};
+use wasm_bindgen::prelude::*;
+
+/// Blobs by key
+#[derive(Debug, Default)]
+pub(crate) struct Blobs {
+    blobs: Mutex<BTreeMap<String, Vec<u8>>>,
+    // Bumped on every change so instantiated definitions know to pick up the changes
+    generation: AtomicUsize,
+}
+
+impl Blobs {
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.blobs.lock().unwrap().get(key).cloned()
+    }
+
+    /// Add a blob, replacing any blob with the same key
+    pub fn insert(&self, key: &str, bytes: Vec<u8>) {
+        let size = bytes.len();
+        self.blobs.lock().unwrap().insert(key.to_string(), bytes);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        log::info!("Registered blob `{}` ({} bytes)", key, size);
+    }
+
+    /// Download a blob, through the cache set with `setGridCache` if any
+    pub async fn fetch(&self, key: &str, url: &str, options: &GridOptions) -> WasmResult<()> {
+        log::debug!("Fetching blob `{}` from {}", key, url);
+        let bytes = grids::download(url, options).await?;
+        self.insert(key, bytes);
+        Ok(())
+    }
+
+    /// Remove a blob, returning whether it was registered
+    pub fn remove(&self, key: &str) -> bool {
+        let removed = self.blobs.lock().unwrap().remove(key).is_some();
+        if removed {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            log::info!("Unregistered blob `{}`", key);
+        }
+        removed
+    }
+
+    pub fn clear(&self) {
+        self.blobs.lock().unwrap().clear();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        log::info!("Cleared all blobs");
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.blobs.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// The size of all blobs in bytes
+    pub fn byte_size(&self) -> usize {
+        self.blobs.lock().unwrap().values().map(Vec::len).sum()
+    }
+}
+
+// A single store on the heap for all global blobs
+static BLOBS: OnceLock<Blobs> = OnceLock::new();
+
+/// The blobs registered with `registerBlob` and friends
+pub(crate) fn global() -> &'static Blobs {
+    BLOBS.get_or_init(Blobs::default)
+}
+
+/// Register the bytes of a blob for the operators in [Geo](crate::geodesy::context::Geo)
+/// definitions reading it by key. Registering an existing key replaces the blob.
+#[wasm_bindgen(js_name = registerBlobSync)]
+pub fn register_blob_sync(key: &str, bytes: Vec<u8>) {
+    global().insert(key, bytes);
+}
+
+/// [Unstable] Register a blob from a URL, see `registerBlobSync`.
+///
+/// The request options and progress callback of `options` are used as for `registerGrid`,
+/// and the blob is cached with the grids when `setGridCache` is set. Responses other than
+/// 2xx fail with a `NetworkError`.
+#[wasm_bindgen(js_name = registerBlob)]
+pub async fn register_blob(key: &str, url: &str, options: Option<JsGridOptions>) -> WasmResult<()> {
+    let options = GridOptions::from_js(options)?;
+    global().fetch(key, url, &options).await
+}
+
+/// The keys of all registered blobs
+#[wasm_bindgen(js_name = listBlobs)]
+pub fn list_blobs() -> Vec<String> {
+    global().keys()
+}
+
+/// Remove a blob, returning whether it was registered.
+///
+/// [Geo](crate::geodesy::context::Geo) instances using the blob are instantiated again
+/// on their next transformation.
+#[wasm_bindgen(js_name = unregisterBlob)]
+pub fn unregister_blob(key: &str) -> bool {
+    global().remove(key)
+}
+
+/// Remove all blobs
+#[wasm_bindgen(js_name = clearBlobs)]
+pub fn clear_blobs() {
+    global().clear();
+}
+
+/// The memory used by all registered blobs, in bytes
+#[wasm_bindgen(js_name = blobMemoryUsage)]
+pub fn blob_memory_usage() -> usize {
+    global().byte_size()
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geodesy::wasmcontext::WasmContext;
+    use float_eq::assert_float_eq;
+    use geodesy_rs::{authoring::*, Error as RgError};
+    use std::sync::Arc;
+
+    // Adds the `f64` stored in the blob `table` to the first coordinate
+    fn offset(parameters: &RawParameters, ctx: &dyn Context) -> Result<Op, RgError> {
+        let gamut = [OpParameter::Text {
+            key: "table",
+            default: None,
+        }];
+        let mut params = ParsedParameters::new(parameters, &gamut)?;
+        let key = params.text("table")?;
+        let bytes = <[u8; 8]>::try_from(ctx.get_blob(&key)?.as_slice())
+            .map_err(|_| RgError::BadParam("table".to_string(), key))?;
+        params.real.insert("offset", f64::from_le_bytes(bytes));
+
+        Ok(Op {
+            descriptor: OpDescriptor::new(&parameters.definition, InnerOp(fwd), None),
+            params,
+            steps: Vec::new(),
+            id: OpHandle::new(),
+        })
+    }
+
+    fn fwd(op: &Op, _ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
+        let offset = op.params.real("offset").unwrap_or(0.);
+        for i in 0..operands.len() {
+            let mut coord = operands.get_coord(i);
+            coord[0] += offset;
+            operands.set_coord(i, &coord);
+        }
+        operands.len()
+    }
+
+    // The first coordinate of the origin after the `offset` operator reading `table`
+    fn apply(ctx: &mut WasmContext, table: &str) -> Result<f64, RgError> {
+        ctx.register_op("offset", OpConstructor(offset));
+        let op = ctx.op(&format!("offset table={}", table))?;
+        let mut operands = [Coor4D::origin()].to_vec();
+        ctx.apply(op, Fwd, &mut operands)?;
+        Ok(operands[0][0])
+    }
+
+    #[test]
+    fn registry() {
+        let before = global().generation();
+        register_blob_sync("registry-table", vec![1, 2, 3]);
+        assert!(global().generation() > before);
+        assert!(list_blobs().contains(&"registry-table".to_string()));
+        assert!(blob_memory_usage() >= 3);
+
+        let ctx = WasmContext::new();
+        assert_eq!(ctx.get_blob("registry-table").unwrap(), vec![1, 2, 3]);
+
+        assert!(unregister_blob("registry-table"));
+        assert!(!unregister_blob("registry-table"));
+        assert!(ctx.get_blob("registry-table").is_err());
+    }
+
+    #[test]
+    fn operators() -> Result<(), RgError> {
+        register_blob_sync("operators-table", 2.5_f64.to_le_bytes().to_vec());
+        let mut ctx = WasmContext::new();
+        assert_float_eq!(apply(&mut ctx, "operators-table")?, 2.5, abs <= 1e-12);
+
+        // Blobs the operator can not read fail the instantiation
+        register_blob_sync("operators-short", vec![1, 2, 3]);
+        assert!(apply(&mut ctx, "operators-short").is_err());
+        assert!(apply(&mut ctx, "operators-missing").is_err());
+
+        // Namespaced blobs shadow the global ones
+        let namespace = Arc::new(Blobs::default());
+        namespace.insert("operators-table", (-1_f64).to_le_bytes().to_vec());
+        ctx.set_blobs(namespace.clone());
+        assert_float_eq!(apply(&mut ctx, "operators-table")?, -1., abs <= 1e-12);
+        namespace.remove("operators-table");
+        assert_float_eq!(apply(&mut ctx, "operators-table")?, 2.5, abs <= 1e-12);
+
+        unregister_blob("operators-table");
+        unregister_blob("operators-short");
+        Ok(())
+    }
+}
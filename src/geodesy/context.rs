@@ -1,6 +1,12 @@
-use super::{coordinate::Coordinates, wasmcontext::WasmContext};
+use super::{
+    coordinate::Coordinates,
+    grids::{self, GridNamespace},
+    measure::{polygon_area_wasm, PolygonMeasure},
+    operators::record_grids,
+    wasmcontext::{WasmContext, GRID_RESOURCE},
+};
 use crate::error::{Error, WasmResult};
-use geodesy_rs::{authoring::*, ctx::OpHandle};
+use geodesy_rs::{authoring::*, ctx::OpHandle, Error as RgError};
 use wasm_bindgen::prelude::*;
 
 /// A wrapper around a [geodesy_rs::Context]
@@ -10,6 +16,12 @@ pub struct Geo {
     context: WasmContext,
     definition: String,
     op_handle: Option<OpHandle>,
+    // The grids the definition refers to
+    grid_keys: Vec<String>,
+    // The resource generation of the context the op handle was instantiated with
+    resource_generation: usize,
+    // The grid applied to each coordinate by the last transformation, when recorded
+    grid_report: Option<Vec<Option<String>>>,
 }
 
 #[wasm_bindgen]
@@ -19,72 +31,453 @@ impl Geo {
         let mut geodesy_def = definition.to_owned();
         if definition.contains("+proj=") {
             geodesy_def = parse_proj(definition)?;
+            log::debug!(
+                "Parsed PROJ definition `{}` as `{}`",
+                definition,
+                geodesy_def
+            );
         }
 
         Ok(Self {
             context: WasmContext::new(),
+            grid_keys: grid_keys(&geodesy_def),
             definition: geodesy_def.to_string(),
             // We lazily initialize the op handle on first use
             op_handle: None,
+            resource_generation: 0,
+            grid_report: None,
         })
     }
 
     /// A forward transformation of the coordinates in the buffer.
     #[wasm_bindgen]
     pub fn forward(&mut self, operands: &mut Coordinates) -> WasmResult<usize> {
-        let handle = self.op_handle()?;
-        let converted = self.context.apply(handle, Fwd, operands);
-
-        match converted {
-            Ok(c) => Ok(c),
-            Err(e) => Err(JsError::new(&format!("{}", e))),
-        }
+        self.apply(Fwd, operands)
     }
 
     /// An inverse transformation of the coordinates in the buffer.
     #[wasm_bindgen]
     pub fn inverse(&mut self, operands: &mut Coordinates) -> WasmResult<usize> {
-        let handle = self.op_handle()?;
-        let converted = self.context.apply(handle, Inv, operands);
-
-        match converted {
-            Ok(c) => Ok(c),
-            Err(e) => Err(JsError::new(&format!("{}", e))),
-        }
+        self.apply(Inv, operands)
     }
 
     /// A convenience method for testing that a forward and inverse transformation
     #[wasm_bindgen(js_name = roundTrip)]
     pub fn round_trip(&mut self, operands: &mut Coordinates) -> WasmResult<usize> {
-        let handle = self.op_handle()?;
-        let fwd_count = self.context.apply(handle, Fwd, operands);
-        let inv_count = self.context.apply(handle, Inv, operands);
-
-        match (fwd_count, inv_count) {
-            (Ok(fc), Ok(ic)) => {
-                if fc != ic {
-                    return Err(JsError::new(&format!(
-                        "Forward and Inverse counts do not match: {} != {}",
-                        fc, ic
-                    )));
-                }
-                Ok(fc)
+        let fwd_count = self.apply(Fwd, operands)?;
+        let inv_count = self.apply(Inv, operands)?;
+
+        if fwd_count != inv_count {
+            return Err(Error::Invalid(format!(
+                "Forward and Inverse counts do not match: {} != {}",
+                fwd_count, inv_count
+            )));
+        }
+        Ok(fwd_count)
+    }
+
+    /// The ellipsoidal area and perimeter of a polygon given in the projected (output) coordinates
+    /// of this definition. The coordinates are copied and inverted to geographic coordinates first,
+    /// so the inverse of the definition MUST produce (longitude, latitude) in radians.
+    ///
+    /// See [polygon_area_wasm](crate::geodesy::measure::polygon_area_wasm) for the ring layout.
+    #[wasm_bindgen(js_name = polygonArea)]
+    pub fn polygon_area(
+        &mut self,
+        operands: &Coordinates,
+        ring_offsets: Vec<u32>,
+        ellps: Option<String>,
+    ) -> WasmResult<PolygonMeasure> {
+        let mut geographic = operands.clone();
+        self.inverse(&mut geographic)?;
+
+        polygon_area_wasm(&geographic, ring_offsets, ellps)
+    }
+
+    /// Record which grid `gridshift` steps apply to each coordinate, read with [Geo::grid_report]
+    /// after a transformation. Off by default as it slows transformations down.
+    /// Fails for definitions without a `gridshift` step.
+    #[wasm_bindgen(js_name = setReportGrids)]
+    pub fn set_report_grids(&mut self, enabled: bool) -> WasmResult<()> {
+        if enabled {
+            let handle = self.op_handle()?;
+            if !self.context.has_step(handle, "gridshift") {
+                return Err(Error::Invalid(format!(
+                    "`{}` has no gridshift step to report the grids of",
+                    self.definition
+                )));
             }
-            (Err(fe), _) => Err(JsError::new(&format!("{}", fe))),
-            (_, Err(ie)) => Err(JsError::new(&format!("{}", ie))),
         }
+        self.grid_report = enabled.then(Vec::new);
+        Ok(())
+    }
+
+    /// The key of the grid applied to each coordinate by the last `gridshift` step of the latest
+    /// transformation, or `undefined` where no grid covered the coordinate.
+    /// Empty unless enabled with `setReportGrids`.
+    #[wasm_bindgen(js_name = gridReport)]
+    pub fn grid_report(&self) -> Vec<JsValue> {
+        self.grid_report
+            .iter()
+            .flatten()
+            .map(|key| match key {
+                Some(key) => JsValue::from_str(key),
+                None => JsValue::UNDEFINED,
+            })
+            .collect()
+    }
+
+    /// Look grids and blobs up in `grids` before the ones registered globally, so different projects
+    /// can use the same keys for different grids. The namespace is shared, not copied,
+    /// and later changes to it are picked up on the next transformation.
+    #[wasm_bindgen(js_name = useGrids)]
+    pub fn use_grids(&mut self, grids: &GridNamespace) {
+        self.context.set_grids(grids.registry().clone());
+        self.context.set_blobs(grids.blobs().clone());
+        if let Some(op_handle) = self.op_handle.take() {
+            self.context.remove_op(op_handle);
+        }
+    }
+
+    /// Sample the forward transformation of this definition every `resolution` radians over the
+    /// `[west, south, east, north]` extent in radians, and register the offsets between its input
+    /// and output as the horizontal grid `key`, in the namespace set with `useGrids` if any.
+    /// Inside the extent `gridshift grids=<key>` then replaces the whole definition, which MUST
+    /// take (longitude, latitude) in radians and leave heights unchanged.
+    ///
+    /// Returns the largest difference between the two found halfway between the nodes,
+    /// in the units of the output coordinates.
+    #[wasm_bindgen(js_name = bakeGrid)]
+    pub fn bake_grid(&mut self, key: &str, bbox: Vec<f64>, resolution: f64) -> WasmResult<f64> {
+        let extent = grids::extent(bbox)?;
+        let handle = self.op_handle()?;
+        let context = &mut self.context;
+        let (grid, max_error) = grids::bake(extent, resolution, |nodes| {
+            context.apply(handle, Fwd, nodes)?;
+            Ok(())
+        })?;
+
+        log::info!(
+            "Baked `{}` into grid `{}` with a maximum error of {}",
+            self.definition,
+            key,
+            max_error
+        );
+        self.context.grids().add_baked_grid(key, grid);
+        Ok(max_error)
+    }
+
+    fn apply(&mut self, direction: Direction, operands: &mut Coordinates) -> WasmResult<usize> {
+        let handle = self.op_handle()?;
+        if self.grid_report.is_none() {
+            return Ok(self.context.apply(handle, direction, operands)?);
+        }
+
+        let (count, report) = record_grids(operands.len(), || {
+            self.context.apply(handle, direction, operands)
+        });
+        self.grid_report = Some(report);
+        Ok(count?)
     }
 
     // For lazy initialization of the op handle
     // Primarily so we can load grids after the context is created
     fn op_handle(&mut self) -> Result<OpHandle, Error> {
+        // Re-instantiate when the grids it uses or blobs were (un)registered since, so changes
+        // are noticed
+        let generation = self.context.resource_generation(&self.grid_keys);
+        if let Some(op_handle) = self.op_handle {
+            if self.resource_generation != generation {
+                self.context.remove_op(op_handle);
+                self.op_handle = None;
+            }
+        }
+
         match self.op_handle {
             Some(op_handle) => Ok(op_handle),
             None => {
-                let op_handle = self.context.op(self.definition.as_str())?;
+                let op_handle = self.context.op(self.definition.as_str()).map_err(|e| {
+                    let error = self.describe_error(e);
+                    log::warn!("Failed to instantiate `{}`: {}", self.definition, error);
+                    error
+                })?;
+                log::debug!("Instantiated `{}`", self.definition);
                 self.op_handle = Some(op_handle);
+                self.resource_generation = generation;
                 Ok(op_handle)
             }
         }
     }
+
+    // Turn an error from instantiating the definition into one that
+    // points at the failing step and, for grids, the missing key.
+    fn describe_error(&self, error: RgError) -> Error {
+        let error = match error {
+            RgError::NotFound(key, resource) if resource == GRID_RESOURCE => {
+                Error::MissingGrid(key)
+            }
+            error => Error::RgError(error),
+        };
+
+        let steps: Vec<&str> = self
+            .definition
+            .split('|')
+            .map(str::trim)
+            .filter(|step| !step.is_empty())
+            .collect();
+        if steps.len() < 2 {
+            return error;
+        }
+
+        let step = match &error {
+            Error::MissingGrid(key) => steps.iter().position(|step| step_uses_grid(step, key)),
+            _ => {
+                let mut ctx = WasmContext::new();
+                steps.iter().position(|step| ctx.op(step).is_err())
+            }
+        };
+
+        match step {
+            Some(step) => error.in_step(step),
+            None => error,
+        }
+    }
+}
+
+fn step_uses_grid(step: &str, key: &str) -> bool {
+    grid_keys(step).iter().any(|grid| grid == key)
+}
+
+// The keys of the `grids` of all steps, without the `@` of optional ones
+fn grid_keys(definition: &str) -> Vec<String> {
+    definition
+        .split_whitespace()
+        .filter_map(|param| param.strip_prefix("grids="))
+        .flat_map(|grids| grids.split(','))
+        .map(|grid| grid.trim().trim_start_matches('@').to_string())
+        .filter(|grid| !grid.is_empty())
+        .collect()
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geodesy::grids::{
+        self,
+        tests::{ntv2_file, register, GEOID, OFFSETS},
+    };
+    use float_eq::assert_float_eq;
+    use std::sync::Arc;
+
+    #[test]
+    fn describe_errors() {
+        let mut geo = Geo::new("senmerc | gridshift grids=@optional,missing").unwrap();
+        let error = geo.op_handle().unwrap_err();
+        assert_eq!(error.code(), "MISSING_GRID");
+        assert_eq!(error.name(), "MissingGridError");
+        assert_eq!(error.step(), Some(1));
+        assert_eq!(error.grid_key(), Some("missing"));
+
+        let mut geo = Geo::new("senmerc | not_an_operator | senmerc inv").unwrap();
+        let error = geo.op_handle().unwrap_err();
+        assert_eq!(error.code(), "RG_ERROR");
+        assert_eq!(error.step(), Some(1));
+        assert_eq!(error.grid_key(), None);
+
+        let mut geo = Geo::new("not_an_operator").unwrap();
+        assert_eq!(geo.op_handle().unwrap_err().step(), None);
+    }
+
+    #[test]
+    fn unregistered_grids() -> Result<(), Error> {
+        let geoid = GEOID.to_vec();
+        grids::global().add_grid("unregistered-geoid", geoid.clone(), &Default::default())?;
+
+        let mut geo = Geo::new("gridshift grids=unregistered-geoid")?;
+        let mut coordinates =
+            Coordinates::new(vec![9_f64.to_radians(), 54.5_f64.to_radians(), 0., 0.])?;
+        assert_eq!(geo.forward(&mut coordinates)?, 1);
+
+        // Unrelated grids leave the instantiated definition alone
+        let handle = geo.op_handle;
+        drop(register("unrelated-geoid", GEOID)?);
+        geo.forward(&mut coordinates)?;
+        assert_eq!(geo.op_handle, handle);
+
+        // while replacing the grid it uses instantiates it again
+        grids::global().add_grid("unregistered-geoid", geoid, &Default::default())?;
+        geo.forward(&mut coordinates)?;
+        assert_ne!(geo.op_handle, handle);
+
+        grids::unregister_grid("unregistered-geoid");
+        let error = geo.forward(&mut coordinates).unwrap_err();
+        assert_eq!(error.code(), "MISSING_GRID");
+        assert_eq!(error.grid_key(), Some("unregistered-geoid"));
+        Ok(())
+    }
+
+    #[test]
+    fn grid_namespaces() -> Result<(), Error> {
+        let geoid = |offset: f64| {
+            let values = [40., 41., 42., 30., 31., 32.].map(|v: f64| (v + offset).to_string());
+            format!("54 55 8 10 1 1\n{}\n", values.join(" ")).into_bytes()
+        };
+        let _geoid = register("namespaced-geoid", GEOID)?;
+        let tenant = GridNamespace::new();
+        tenant
+            .registry()
+            .add_grid("namespaced-geoid", geoid(100.), &Default::default())?;
+
+        let point = || Coordinates::new(vec![9_f64.to_radians(), 54.5_f64.to_radians(), 0., 0.]);
+        let mut global = Geo::new("gridshift grids=namespaced-geoid")?;
+        let mut coordinates = point()?;
+        global.forward(&mut coordinates)?;
+        assert_float_eq!(coordinates.get_coord(0)[2], -36., abs <= 1e-9);
+
+        // The namespace shadows the global grid with the same key
+        let mut namespaced = Geo::new("gridshift grids=namespaced-geoid")?;
+        namespaced.use_grids(&tenant);
+        let mut coordinates = point()?;
+        namespaced.forward(&mut coordinates)?;
+        assert_float_eq!(coordinates.get_coord(0)[2], -136., abs <= 1e-9);
+
+        // and global grids are used when the namespace does not have the key
+        tenant.unregister_grid("namespaced-geoid");
+        let mut coordinates = point()?;
+        namespaced.forward(&mut coordinates)?;
+        assert_float_eq!(coordinates.get_coord(0)[2], -36., abs <= 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn grid_reports() -> Result<(), Error> {
+        // A geoid over 8E..10E, and a coarser one over 8E..20E as a fallback
+        let _fine = register("report-fine", GEOID)?;
+        let _coarse = register("report-coarse", b"54 55 8 20 1 6\n 40 41 42\n 30 31 32\n")?;
+
+        let mut geo = Geo::new("gridshift grids=report-fine,@report-coarse")?;
+        let points = [9_f64, 15., 30.]
+            .into_iter()
+            .flat_map(|lon| [lon.to_radians(), 54.5_f64.to_radians(), 0., 0.])
+            .collect();
+        let mut coordinates = Coordinates::new(points)?;
+        geo.forward(&mut coordinates)?;
+        assert_eq!(geo.grid_report, None);
+
+        geo.set_report_grids(true)?;
+        geo.inverse(&mut coordinates)?;
+        let expected = [Some("report-fine"), Some("report-coarse"), None];
+        assert_eq!(
+            geo.grid_report,
+            Some(expected.map(|key| key.map(str::to_string)).to_vec())
+        );
+
+        // Also inside pipelines, but not without a gridshift step to record them
+        let mut geo = Geo::new("noop | gridshift grids=report-fine | noop")?;
+        geo.set_report_grids(true)?;
+        let mut coordinates = Coordinates::new(vec![9_f64.to_radians(), 0.95, 0., 0.])?;
+        geo.forward(&mut coordinates)?;
+        assert_eq!(geo.grid_report, Some(vec![Some("report-fine".to_string())]));
+        assert!(Geo::new("noop")?.set_report_grids(true).is_err());
+        assert!(Geo::new("noop")?.set_report_grids(false).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn bake_grid() -> Result<(), Error> {
+        // Offsets in arc seconds over 54N..55N, 8E..10E
+        let _source = register("bake-source", OFFSETS)?;
+
+        let mut geo = Geo::new("gridshift grids=bake-source")?;
+        let extent = [8_f64, 54., 10., 55.].map(f64::to_radians).to_vec();
+        let max_error = geo.bake_grid("bake-baked", extent.clone(), 0.5_f64.to_radians())?;
+        // Nodes on the source nodes reproduce its bilinear interpolation
+        assert!(max_error < 1e-10, "{}", max_error);
+        assert_eq!(grids::grid_info("bake-baked")?.format(), "Baked");
+
+        let point = || {
+            let point = [9.3_f64.to_radians(), 54.7_f64.to_radians(), 0., 0.];
+            Coordinates::new(point.to_vec())
+        };
+        let (mut expected, mut baked) = (point()?, point()?);
+        geo.forward(&mut expected)?;
+        Geo::new("gridshift grids=bake-baked")?.forward(&mut baked)?;
+        for i in 0..2 {
+            let (e, b) = (expected.get_coord(0)[i], baked.get_coord(0)[i]);
+            assert!((e - b).abs() < 1e-10);
+        }
+
+        // Baked into the namespace the definition uses
+        let tenant = GridNamespace::new();
+        geo.use_grids(&tenant);
+        geo.bake_grid("bake-tenant", extent.clone(), 0.5_f64.to_radians())?;
+        assert_eq!(tenant.list_grids(), ["bake-tenant"]);
+        assert!(grids::grid_info("bake-tenant").is_err());
+
+        // Heights can not be baked
+        let _geoid = register("bake-geoid", GEOID)?;
+        let mut vertical = Geo::new("gridshift grids=bake-geoid")?;
+        assert!(vertical
+            .bake_grid("bake-vertical", extent, 0.5_f64.to_radians())
+            .is_err());
+
+        assert!(geo
+            .bake_grid("bake-baked", vec![1., 0., 0., 1.], 0.1)
+            .is_err());
+        grids::unregister_grid("bake-baked");
+        Ok(())
+    }
+
+    #[test]
+    fn grid_readers() -> Result<(), Error> {
+        // The NTv2 and Gravsoft readers shift coordinates as the ones of geodesy_rs
+        let ntv2 = ntv2_file(&[
+            ("PARENT", "NONE", [180_000., 183_600., -3600., 3600.], 3600.),
+            ("CHILD", "PARENT", [180_000., 181_800., -1800., 0.], 1800.),
+        ]);
+        // The key and bytes of a grid, geodesy_rs reading them, and an extent to test over
+        type Case<'a> = (&'a str, Vec<u8>, Arc<dyn Grid>, [f64; 4]);
+        let cases: [Case; 2] = [
+            (
+                "readers-ntv2",
+                ntv2.clone(),
+                Arc::new(Ntv2Grid::new(&ntv2)?),
+                [-1., 50., 1., 51.],
+            ),
+            (
+                "readers-gravsoft",
+                OFFSETS.to_vec(),
+                Arc::new(BaseGrid::gravsoft(OFFSETS)?),
+                [8., 54., 10., 55.],
+            ),
+        ];
+        for (key, bytes, reference, [west, south, east, north]) in cases {
+            let _grid = register(key, &bytes)?;
+            let mut ours = Geo::new(&format!("gridshift grids={}", key))?;
+            let mut theirs = Geo::new("gridshift grids=reference")?;
+            theirs.context.add_reference_grid("reference", reference);
+
+            // Nodes, edges and cells, including the child subgrid of the NTv2 file
+            let points: Vec<f64> = (0..=8 * 8)
+                .flat_map(|i| {
+                    let lon = west + (east - west) * (i % 9) as f64 / 8.;
+                    let lat = south + (north - south) * (i / 9) as f64 / 8.;
+                    [lon.to_radians(), lat.to_radians(), 0., 0.]
+                })
+                .collect();
+            let (mut expected, mut shifted) =
+                (Coordinates::new(points.clone())?, Coordinates::new(points)?);
+            assert_eq!(theirs.forward(&mut expected)?, 65);
+            assert_eq!(ours.forward(&mut shifted)?, 65);
+            for i in 0..65 {
+                let (e, s) = (expected.get_coord(i), shifted.get_coord(i));
+                // Up to the rounding of offsets stored as `f32`, far below the arc seconds
+                // a wrong sign, corner or band order would be off by
+                assert_float_eq!(s.0, e.0, abs_all <= 1e-10, "{} point {}", key, i);
+            }
+        }
+        Ok(())
+    }
 }
@@ -1,9 +1,10 @@
-use crate::error::WasmResult;
+use crate::error::{Error, WasmResult};
 use geodesy_rs::prelude::*;
 use wasm_bindgen::prelude::*;
 
 /// A flat array of 4D coordinates.
 #[wasm_bindgen]
+#[derive(Debug, Clone)]
 pub struct Coordinates(Vec<f64>);
 
 #[wasm_bindgen]
@@ -16,7 +17,9 @@ impl Coordinates {
     #[wasm_bindgen(constructor)]
     pub fn new(buffer: Vec<f64>) -> WasmResult<Coordinates> {
         if buffer.len() % 4 != 0 {
-            return Err(JsError::new("Buffer length must be a multiple of 4"));
+            return Err(Error::Invalid(
+                "Buffer length must be a multiple of 4".to_string(),
+            ));
         }
         Ok(Coordinates(buffer))
     }
@@ -0,0 +1,346 @@
+//! Named ellipsoids.
+//!
+//! The built in names mirror those accepted by `ellps=` in Rust Geodesy definitions.
+//! Custom ellipsoids can be registered by name and are expanded to their
+//! `semimajor_axis,inverse_flattening` form when a definition is instantiated.
+use crate::error::{Error, Result, WasmResult};
+use geodesy_rs::prelude::*;
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, OnceLock},
+};
+use wasm_bindgen::prelude::*;
+
+// A single store on the heap for all user defined ellipsoids, as (semimajor axis, inverse flattening)
+static ELLIPSOIDS: OnceLock<Mutex<BTreeMap<String, (f64, f64)>>> = OnceLock::new();
+
+fn init_ellipsoids() -> Mutex<BTreeMap<String, (f64, f64)>> {
+    Mutex::new(BTreeMap::<String, (f64, f64)>::new())
+}
+
+// (name, semimajor axis, inverse flattening)
+#[rustfmt::skip]
+const BUILTIN_ELLIPSOIDS: [(&str, f64, f64); 17] = [
+    ("GRS80",     6_378_137.0,     298.257_222_100_882_7),
+    ("WGS84",     6_378_137.0,     298.257_223_563),
+    ("WGS72",     6_378_135.0,     298.26),
+    ("WGS66",     6_378_145.0,     298.25),
+    ("GRS67",     6_378_160.0,     298.247_167_427),
+    ("intl",      6_378_388.0,     297.0),
+    ("airy",      6_377_563.396,   299.324_964_6),
+    ("mod_airy",  6_377_340.189,   299.324_937_365_482_4),
+    ("bessel",    6_377_397.155,   299.152_812_8),
+    ("bess_nam",  6_377_483.865,   299.152_812_8),
+    ("clrk66",    6_378_206.4,     294.978_698_213_898_2),
+    ("clrk80",    6_378_249.145,   293.466_3),
+    ("krass",     6_378_245.0,     298.3),
+    ("evrst30",   6_377_276.345,   300.801_7),
+    ("helmert",   6_378_200.0,     298.3),
+    ("aust_SA",   6_378_160.0,     298.25),
+    ("sphere",    6_370_997.0,     0.0),
+];
+
+/// The parameters of a named ellipsoid.
+/// Latitudes are in radians, lengths in metres.
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct EllipsoidParameters {
+    name: String,
+    ellps: Ellipsoid,
+}
+
+#[wasm_bindgen]
+impl EllipsoidParameters {
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    #[wasm_bindgen(getter, js_name = semimajorAxis)]
+    pub fn semimajor_axis(&self) -> f64 {
+        self.ellps.semimajor_axis()
+    }
+
+    #[wasm_bindgen(getter, js_name = semiminorAxis)]
+    pub fn semiminor_axis(&self) -> f64 {
+        self.ellps.semiminor_axis()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn flattening(&self) -> f64 {
+        self.ellps.flattening()
+    }
+
+    /// Zero for a sphere
+    #[wasm_bindgen(getter, js_name = inverseFlattening)]
+    pub fn inverse_flattening(&self) -> f64 {
+        let f = self.ellps.flattening();
+        if f == 0.0 {
+            0.0
+        } else {
+            1.0 / f
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn eccentricity(&self) -> f64 {
+        self.ellps.eccentricity()
+    }
+
+    #[wasm_bindgen(getter, js_name = eccentricitySquared)]
+    pub fn eccentricity_squared(&self) -> f64 {
+        self.ellps.eccentricity_squared()
+    }
+
+    #[wasm_bindgen(getter, js_name = secondEccentricity)]
+    pub fn second_eccentricity(&self) -> f64 {
+        self.ellps.second_eccentricity()
+    }
+
+    #[wasm_bindgen(getter, js_name = secondEccentricitySquared)]
+    pub fn second_eccentricity_squared(&self) -> f64 {
+        self.ellps.second_eccentricity_squared()
+    }
+
+    /// The radius of curvature in the meridian at `latitude`.
+    #[wasm_bindgen(js_name = meridianRadiusOfCurvature)]
+    pub fn meridian_radius_of_curvature(&self, latitude: f64) -> f64 {
+        self.ellps.meridian_radius_of_curvature(latitude)
+    }
+
+    /// The radius of curvature in the prime vertical at `latitude`.
+    #[wasm_bindgen(js_name = primeVerticalRadiusOfCurvature)]
+    pub fn prime_vertical_radius_of_curvature(&self, latitude: f64) -> f64 {
+        self.ellps.prime_vertical_radius_of_curvature(latitude)
+    }
+}
+
+/// The names of all built in and registered ellipsoids.
+#[wasm_bindgen(js_name = listEllipsoids)]
+pub fn list_ellipsoids() -> Vec<String> {
+    let mut names: Vec<String> = BUILTIN_ELLIPSOIDS.iter().map(|e| e.0.to_string()).collect();
+
+    if let Some(custom) = ELLIPSOIDS.get() {
+        names.extend(custom.lock().unwrap().keys().cloned());
+    }
+    names
+}
+
+/// Look up an ellipsoid by name.
+/// Also accepts the `semimajor_axis, inverse_flattening` form used in definitions.
+#[wasm_bindgen(js_name = ellipsoid)]
+pub fn ellipsoid_wasm(name: &str) -> WasmResult<EllipsoidParameters> {
+    ellipsoid(name)
+}
+
+/// Register a custom ellipsoid which can then be used as `ellps=<name>` in [Geo](crate::geodesy::context::Geo) definitions.
+/// Registering an existing custom name replaces it. Built in names cannot be replaced.
+#[wasm_bindgen(js_name = registerEllipsoid)]
+pub fn register_ellipsoid_wasm(
+    name: &str,
+    semimajor_axis: f64,
+    inverse_flattening: f64,
+) -> WasmResult<()> {
+    register_ellipsoid(name, semimajor_axis, inverse_flattening)
+}
+
+pub fn ellipsoid(name: &str) -> Result<EllipsoidParameters> {
+    let name = name.trim();
+    Ok(EllipsoidParameters {
+        name: name.to_string(),
+        ellps: named(name)?,
+    })
+}
+
+/// Like [Ellipsoid::named] but aware of registered custom ellipsoids.
+pub(crate) fn named(name: &str) -> Result<Ellipsoid> {
+    match lookup(name.trim()) {
+        Some((a, rf)) => Ok(new_ellipsoid(a, rf)),
+        None => Ok(Ellipsoid::named(name)?),
+    }
+}
+
+pub fn register_ellipsoid(name: &str, semimajor_axis: f64, inverse_flattening: f64) -> Result<()> {
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || ",=|".contains(c)) {
+        return Err(Error::Invalid(format!(
+            "Ellipsoid names cannot be empty or contain whitespace, ',', '=' or '|': `{}`",
+            name
+        )));
+    }
+    if BUILTIN_ELLIPSOIDS.iter().any(|e| e.0 == name) {
+        return Err(Error::Invalid(format!(
+            "`{}` is a built in ellipsoid and cannot be replaced",
+            name
+        )));
+    }
+    if !(semimajor_axis.is_finite() && semimajor_axis > 0.0) {
+        return Err(Error::Invalid(format!(
+            "The semimajor axis must be positive: {}",
+            semimajor_axis
+        )));
+    }
+    // An inverse flattening of 0 denotes a sphere, otherwise it must give a flattening in (0, 1)
+    if !(inverse_flattening == 0.0 || inverse_flattening.is_finite() && inverse_flattening > 1.0) {
+        return Err(Error::Invalid(format!(
+            "The inverse flattening must be 0 (a sphere) or greater than 1: {}",
+            inverse_flattening
+        )));
+    }
+
+    ELLIPSOIDS
+        .get_or_init(init_ellipsoids)
+        .lock()
+        .unwrap()
+        .insert(name.to_string(), (semimajor_axis, inverse_flattening));
+    log::info!(
+        "Registered ellipsoid `{}` (a={}, rf={})",
+        name,
+        semimajor_axis,
+        inverse_flattening
+    );
+    Ok(())
+}
+
+/// Replace any registered custom ellipsoid names in the `ellps` parameters of
+/// a definition with their `semimajor_axis,inverse_flattening` form.
+pub(crate) fn expand_custom_ellipsoids(definition: &str) -> String {
+    let custom = match ELLIPSOIDS.get() {
+        Some(custom) => custom.lock().unwrap().clone(),
+        None => return definition.to_string(),
+    };
+    if custom.is_empty() {
+        return definition.to_string();
+    }
+
+    let mut expanded = String::with_capacity(definition.len());
+    let mut token = String::new();
+    let flush = |token: &mut String, expanded: &mut String| {
+        let mut replaced = false;
+        if let Some((key, value)) = token.split_once('=') {
+            if matches!(key, "ellps" | "ellps_0" | "ellps_1") {
+                if let Some((a, rf)) = custom.get(value) {
+                    expanded.push_str(&format!("{}={},{}", key, a, rf));
+                    replaced = true;
+                }
+            }
+        }
+        if !replaced {
+            expanded.push_str(token);
+        }
+        token.clear();
+    };
+
+    // geodesy_rs reads `ellps = name` as `ellps=name`, so do the same here
+    let mut chars = definition.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '=' {
+            token.push(c);
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        } else if c.is_whitespace() {
+            let rest = chars.clone().find(|c| !c.is_whitespace());
+            if rest == Some('=') {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            } else {
+                flush(&mut token, &mut expanded);
+                expanded.push(c);
+            }
+        } else if c == '|' {
+            flush(&mut token, &mut expanded);
+            expanded.push(c);
+        } else {
+            token.push(c);
+        }
+    }
+    flush(&mut token, &mut expanded);
+
+    expanded
+}
+
+fn lookup(name: &str) -> Option<(f64, f64)> {
+    if let Some(e) = BUILTIN_ELLIPSOIDS.iter().find(|e| e.0 == name) {
+        return Some((e.1, e.2));
+    }
+    ELLIPSOIDS
+        .get()
+        .and_then(|custom| custom.lock().unwrap().get(name).copied())
+}
+
+fn new_ellipsoid(semimajor_axis: f64, inverse_flattening: f64) -> Ellipsoid {
+    let flattening = if inverse_flattening == 0.0 {
+        0.0
+    } else {
+        1.0 / inverse_flattening
+    };
+    Ellipsoid::new(semimajor_axis, flattening)
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_eq::assert_float_eq;
+
+    #[test]
+    fn builtin() -> Result<()> {
+        assert!(list_ellipsoids().contains(&"airy".to_string()));
+
+        let grs80 = ellipsoid("GRS80")?;
+        assert_eq!(grs80.semimajor_axis(), 6_378_137.0);
+        assert_float_eq!(grs80.semiminor_axis(), 6_356_752.314_140_348, abs <= 1e-6);
+        assert_float_eq!(
+            grs80.eccentricity_squared(),
+            0.006_694_380_022_903_417,
+            abs <= 1e-15
+        );
+
+        // At the equator the meridian radius is b²/a and the prime vertical radius is a
+        assert_float_eq!(
+            grs80.meridian_radius_of_curvature(0.0),
+            6_335_439.327_083_86,
+            abs <= 1e-6
+        );
+        assert_float_eq!(
+            grs80.prime_vertical_radius_of_curvature(0.0),
+            6_378_137.0,
+            abs <= 1e-9
+        );
+
+        assert!(ellipsoid("not-an-ellipsoid").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn custom() -> Result<()> {
+        assert!(register_ellipsoid("airy", 6_377_563.396, 299.3).is_err());
+        assert!(register_ellipsoid("bad name", 6_377_563.396, 299.3).is_err());
+        assert!(register_ellipsoid("flat", 6_377_563.396, 0.5).is_err());
+
+        register_ellipsoid("site", 6_378_000.0, 300.0)?;
+        assert!(list_ellipsoids().contains(&"site".to_string()));
+        let site = ellipsoid("site")?;
+        assert_eq!(site.semimajor_axis(), 6_378_000.0);
+        assert_float_eq!(site.inverse_flattening(), 300.0, abs <= 1e-9);
+
+        assert_eq!(
+            expand_custom_ellipsoids("tmerc ellps=site|senmerc ellps=GRS80 ellps_1=site"),
+            "tmerc ellps=6378000,300|senmerc ellps=GRS80 ellps_1=6378000,300"
+        );
+        assert_eq!(
+            expand_custom_ellipsoids("tmerc ellps = site | utm zone=32 ellps_0 =site"),
+            "tmerc ellps=6378000,300 | utm zone=32 ellps_0=6378000,300"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn builtin_table_matches_geodesy() -> Result<()> {
+        for (name, a, rf) in BUILTIN_ELLIPSOIDS {
+            let theirs = Ellipsoid::named(name)?;
+            assert_eq!(theirs.semimajor_axis(), a, "{}", name);
+            let f = if rf == 0.0 { 0.0 } else { 1.0 / rf };
+            assert_float_eq!(theirs.flattening(), f, rmax <= 1e-12);
+        }
+        Ok(())
+    }
+}
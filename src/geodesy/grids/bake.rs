@@ -0,0 +1,224 @@
+//! Sampling transformations into horizontal offset grids.
+//!
+//! A slow pipeline used over a small area can be replaced by a single `gridshift` with
+//! the offsets between its input and output at regularly spaced nodes.
+use super::raster::{GridHeader, GridSet, RasterGrid, Trend};
+use crate::{
+    error::{Error, Result},
+    geodesy::adjustment::Adjustment,
+};
+use geodesy_rs::authoring::{Coor4D, Grid};
+
+/// The most nodes of a baked grid, 32 MB of offsets
+const MAX_NODES: usize = 4_000_000;
+
+/// The largest change of heights, in the units of the output, that a horizontal grid ignores
+const HEIGHT_TOLERANCE: f64 = 1e-9;
+
+/// Sample `transform` at nodes `resolution` radians apart covering the `[west, south, east, north]`
+/// extent in radians. Returns the grid of offsets and the largest difference between applying it
+/// and `transform` halfway between the nodes, where interpolation is furthest off.
+///
+/// Nodes where `transform` fails are null. The offsets are stored relative to the plane best
+/// fitting them, so large offsets keep their precision. Transformations changing heights fail,
+/// as the grid only holds the first two coordinates.
+pub(crate) fn bake(
+    extent: [f64; 4],
+    resolution: f64,
+    mut transform: impl FnMut(&mut Vec<Coor4D>) -> Result<()>,
+) -> Result<(GridSet, f64)> {
+    if resolution.is_nan() || resolution <= 0. {
+        return Err(Error::Invalid(format!(
+            "The resolution must be positive, not {}",
+            resolution
+        )));
+    }
+    let [west, south, east, north] = extent;
+    // Tolerating rounding, so spans of whole cells get no extra row or column
+    let steps = |span: f64| ((span / resolution - 1e-9).ceil() as usize).max(1);
+    let (rows, cols) = (steps(north - south) + 1, steps(east - west) + 1);
+    if rows * cols > MAX_NODES {
+        return Err(Error::Invalid(format!(
+            "A resolution of {} gives {} x {} nodes, more than the {} that can be baked",
+            resolution, rows, cols, MAX_NODES
+        )));
+    }
+
+    // Rows north to south, columns west to east, offset by `shift` cells
+    let positions = |rows: usize, cols: usize, shift: f64| -> Vec<Coor4D> {
+        (0..rows * cols)
+            .map(|i| {
+                let lon = west + ((i % cols) as f64 + shift) * resolution;
+                let lat = north - ((i / cols) as f64 + shift) * resolution;
+                Coor4D::raw(lon, lat, 0., 0.)
+            })
+            .collect()
+    };
+
+    let nodes = positions(rows, cols, 0.);
+    let mut transformed = nodes.clone();
+    transform(&mut transformed)?;
+    let height = transformed
+        .iter()
+        .map(|c| c[2].abs())
+        .filter(|dz| !dz.is_nan())
+        .fold(0., f64::max);
+    if height > HEIGHT_TOLERANCE {
+        return Err(Error::Invalid(format!(
+            "The transformation changes heights by up to {}, which a horizontal grid can not reproduce",
+            height
+        )));
+    }
+    let offsets: Vec<[f64; 2]> = nodes
+        .iter()
+        .zip(&transformed)
+        .map(|(from, to)| [to[1] - from[1], to[0] - from[0]])
+        .collect();
+
+    let origin = [(west + east) / 2., (south + north) / 2.];
+    let trend = Trend {
+        origin,
+        planes: (0..2)
+            .map(|band| plane(&nodes, &offsets, band, origin))
+            .collect(),
+    };
+    let header = GridHeader {
+        lat_n: north,
+        lon_w: west,
+        dlat: resolution,
+        dlon: resolution,
+        rows,
+        cols,
+        bands: 2,
+    };
+    let values = nodes
+        .iter()
+        .zip(&offsets)
+        .flat_map(|(node, offsets)| {
+            let residual = |band| (offsets[band] - trend.at(node[0], node[1], band)) as f32;
+            [residual(0), residual(1)]
+        })
+        .collect();
+    let grid = GridSet::single(RasterGrid::with_trend(header, values, trend)?);
+
+    let centres = positions(rows - 1, cols - 1, 0.5);
+    let mut expected = centres.clone();
+    transform(&mut expected)?;
+    let max_error = centres
+        .iter()
+        .zip(&expected)
+        .filter_map(|(position, expected)| {
+            let d = grid.at(None, position, 0.)?;
+            let dlon = position[0] + d[1] - expected[0];
+            let dlat = position[1] + d[0] - expected[1];
+            // The grid leaves heights unchanged
+            let dz = expected[2] - position[2];
+            // NaN where the transformation failed
+            Some(dlon.abs().max(dlat.abs()).max(dz.abs())).filter(|e| !e.is_nan())
+        })
+        .fold(0., f64::max);
+    Ok((grid, max_error))
+}
+
+// The plane best fitting the `band` offsets at the nodes where the transformation succeeded,
+// or their mean when they do not determine a plane
+fn plane(nodes: &[Coor4D], offsets: &[[f64; 2]], band: usize, origin: [f64; 2]) -> [f64; 3] {
+    let mut adjustment = Adjustment::new(3);
+    for (node, offsets) in nodes.iter().zip(offsets) {
+        if !offsets[band].is_nan() {
+            let row = [1., node[0] - origin[0], node[1] - origin[1]];
+            adjustment.observe(&row, offsets[band]);
+        }
+    }
+    match adjustment.solve() {
+        Ok(plane) => [plane[0], plane[1], plane[2]],
+        // Eg where the transformation only succeeded along a line
+        Err(_) => {
+            let valid: Vec<f64> = offsets
+                .iter()
+                .map(|o| o[band])
+                .filter(|o| !o.is_nan())
+                .collect();
+            [
+                valid.iter().sum::<f64>() / valid.len().max(1) as f64,
+                0.,
+                0.,
+            ]
+        }
+    }
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_eq::assert_float_eq;
+
+    #[test]
+    fn bake_transformations() -> Result<()> {
+        let extent = [0.1, 0.5, 0.2, 0.55];
+
+        // Linear transformations are reproduced exactly
+        let shift = |coords: &mut Vec<Coor4D>| {
+            for c in coords.iter_mut() {
+                *c = Coor4D::raw(c[0] + 1e-4 + 0.01 * c[1], c[1] - 2e-4, c[2], c[3]);
+            }
+            Ok(())
+        };
+        let (grid, max_error) = bake(extent, 0.01, shift)?;
+        assert!(max_error < 1e-9);
+        let header = grid.grids()[0].header();
+        assert_eq!((header.rows, header.cols), (6, 11));
+        let d = grid
+            .at(None, &Coor4D::raw(0.15, 0.525, 0., 0.), 0.)
+            .unwrap();
+        assert_float_eq!(d[0], -2e-4, abs <= 1e-9);
+        assert_float_eq!(d[1], 1e-4 + 0.00525, abs <= 1e-9);
+
+        // Curvature shows up as the error, which shrinks with the resolution
+        let square = |coords: &mut Vec<Coor4D>| {
+            for c in coords.iter_mut() {
+                c[0] += c[0] * c[0];
+            }
+            Ok(())
+        };
+        let (_, coarse) = bake(extent, 0.01, square)?;
+        let (_, fine) = bake(extent, 0.005, square)?;
+        // Halfway between nodes linear interpolation of x^2 is off by (h/2)^2
+        assert_float_eq!(coarse, 0.005_f64.powi(2), abs <= 1e-8);
+        assert!(fine < coarse / 3.);
+
+        // Large offsets, eg to projected coordinates, keep their precision
+        let project = |coords: &mut Vec<Coor4D>| {
+            for c in coords.iter_mut() {
+                let (x, y) = (5e5 + 6.4e6 * c[0] + 1e3 * c[0] * c[1], 6e6 + 6.4e6 * c[1]);
+                *c = Coor4D::raw(x, y, c[2], c[3]);
+            }
+            Ok(())
+        };
+        let (grid, max_error) = bake(extent, 0.01, project)?;
+        assert!(max_error < 1e-6, "{}", max_error);
+        let d = grid
+            .at(None, &Coor4D::raw(0.153, 0.521, 0., 0.), 0.)
+            .unwrap();
+        assert_float_eq!(
+            d[1],
+            5e5 + 6.4e6 * 0.153 + 1e3 * 0.153 * 0.521 - 0.153,
+            abs <= 1e-6
+        );
+
+        // Heights are not in the grid
+        let lift = |coords: &mut Vec<Coor4D>| {
+            for c in coords.iter_mut() {
+                c[2] += 1.;
+            }
+            Ok(())
+        };
+        assert!(bake(extent, 0.01, lift).is_err());
+
+        assert!(bake(extent, 0., shift).is_err());
+        assert!(bake([0., 0., 1., 1.], 1e-6, shift).is_err());
+        Ok(())
+    }
+}
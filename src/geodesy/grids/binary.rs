@@ -0,0 +1,73 @@
+//! Bounds checked reading of fixed layout binary grid files.
+use crate::error::{Error, Result};
+
+pub(crate) struct Binary<'a> {
+    bytes: &'a [u8],
+    little: bool,
+    /// Used in error messages
+    format: &'static str,
+}
+
+impl<'a> Binary<'a> {
+    pub fn new(bytes: &'a [u8], little: bool, format: &'static str) -> Binary<'a> {
+        Binary {
+            bytes,
+            little,
+            format,
+        }
+    }
+
+    pub fn invalid(&self, message: &str) -> Error {
+        Error::Invalid(format!("{}: {}", self.format, message))
+    }
+
+    pub fn slice(&self, offset: usize, length: usize) -> Result<&'a [u8]> {
+        offset
+            .checked_add(length)
+            .and_then(|end| self.bytes.get(offset..end))
+            .ok_or_else(|| self.invalid("unexpected end of file"))
+    }
+
+    /// The number of `rows` x `cols` nodes of `size` bytes each from `offset`, after checking
+    /// that the file holds them, so headers of truncated or bogus files allocate nothing
+    pub fn nodes(&self, offset: usize, rows: usize, cols: usize, size: usize) -> Result<usize> {
+        let nodes = rows.checked_mul(cols);
+        nodes
+            .and_then(|nodes| nodes.checked_mul(size))
+            .and_then(|length| length.checked_add(offset))
+            .filter(|end| *end <= self.bytes.len())
+            .and(nodes)
+            .ok_or_else(|| {
+                self.invalid(&format!(
+                    "the file is too short for the {} x {} nodes of its header",
+                    rows, cols
+                ))
+            })
+    }
+
+    fn array<const N: usize>(&self, offset: usize) -> Result<[u8; N]> {
+        let mut array: [u8; N] = self.slice(offset, N)?.try_into().unwrap();
+        if self.little != cfg!(target_endian = "little") {
+            array.reverse();
+        }
+        Ok(array)
+    }
+
+    pub fn i32(&self, offset: usize) -> Result<i32> {
+        Ok(i32::from_ne_bytes(self.array(offset)?))
+    }
+
+    pub fn f32(&self, offset: usize) -> Result<f32> {
+        Ok(f32::from_ne_bytes(self.array(offset)?))
+    }
+
+    pub fn f64(&self, offset: usize) -> Result<f64> {
+        Ok(f64::from_ne_bytes(self.array(offset)?))
+    }
+
+    /// Fixed width text, without trailing padding
+    pub fn text(&self, offset: usize, length: usize) -> Result<String> {
+        let text = String::from_utf8_lossy(self.slice(offset, length)?);
+        Ok(text.trim_end_matches(['\0', ' ']).to_string())
+    }
+}
@@ -0,0 +1,357 @@
+//! Caching downloaded grid files between visits.
+//!
+//! Grid files are stored by URL together with the `ETag` and `Last-Modified` validators of the
+//! response. Cached copies are revalidated with a conditional request, so unchanged grids are
+//! not downloaded again, and used as they are when the network or the server fails.
+//! Client errors such as 403 or 404 are reported, even with a cached copy.
+use super::fetch::{read_body, status_error, RequestOptions, Status};
+use crate::error::{Error, Result, WasmResult};
+use js_sys::{Function, Object, Promise, Reflect, Uint8Array};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode, Url,
+};
+use std::{cell::RefCell, collections::BTreeMap, future::Future, pin::Pin, rc::Rc, sync::Mutex};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+pub(crate) type Pending<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + 'a>>;
+
+/// A cached grid file and the validators of the response it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CachedGrid {
+    pub bytes: Vec<u8>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Storage for grid files, keyed by URL
+pub(crate) trait GridCache {
+    fn get(&self, url: &str) -> Pending<'_, Option<CachedGrid>>;
+    fn put(&self, url: &str, grid: CachedGrid) -> Pending<'_, ()>;
+}
+
+/// Keeps grid files for as long as the page
+#[derive(Debug, Default)]
+pub(crate) struct MemoryCache {
+    grids: Mutex<BTreeMap<String, CachedGrid>>,
+}
+
+impl GridCache for MemoryCache {
+    fn get(&self, url: &str) -> Pending<'_, Option<CachedGrid>> {
+        let grid = self.grids.lock().unwrap().get(url).cloned();
+        Box::pin(async move { Ok(grid) })
+    }
+
+    fn put(&self, url: &str, grid: CachedGrid) -> Pending<'_, ()> {
+        self.grids.lock().unwrap().insert(url.to_string(), grid);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const GRID_CACHE_BACKEND: &'static str = r#"
+export interface CachedGrid {
+    bytes: Uint8Array;
+    etag?: string;
+    lastModified?: string;
+}
+
+/** Storage for downloaded grid files, eg on top of IndexedDB or the Cache API. */
+export interface GridCacheBackend {
+    get(url: string): Promise<CachedGrid | undefined> | CachedGrid | undefined;
+    put(url: string, grid: CachedGrid): Promise<void> | void;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "GridCacheBackend | \"memory\" | undefined")]
+    pub type JsGridCacheBackend;
+}
+
+/// A cache backed by the `get` and `put` functions of a JS object
+#[derive(Debug)]
+pub(crate) struct JsCache {
+    backend: JsValue,
+}
+
+impl JsCache {
+    async fn call(&self, method: &str, args: &[JsValue]) -> Result<JsValue> {
+        let function = Reflect::get(&self.backend, &JsValue::from_str(method))
+            .ok()
+            .and_then(|f| f.dyn_into::<Function>().ok())
+            .ok_or_else(|| {
+                Error::Invalid(format!("The grid cache has no `{}` function", method))
+            })?;
+        let failed =
+            |e: JsValue| Error::Invalid(format!("Grid cache `{}` failed: {:?}", method, e));
+
+        let args: js_sys::Array = args.iter().collect();
+        let result = function.apply(&self.backend, &args).map_err(failed)?;
+        JsFuture::from(Promise::resolve(&result))
+            .await
+            .map_err(failed)
+    }
+}
+
+impl GridCache for JsCache {
+    fn get(&self, url: &str) -> Pending<'_, Option<CachedGrid>> {
+        let url = JsValue::from_str(url);
+        Box::pin(async move {
+            let grid = self.call("get", &[url]).await?;
+            if grid.is_undefined() || grid.is_null() {
+                return Ok(None);
+            }
+            let field = |name: &str| Reflect::get(&grid, &JsValue::from_str(name)).ok();
+            let bytes = field("bytes")
+                .and_then(|b| b.dyn_into::<Uint8Array>().ok())
+                .ok_or_else(|| {
+                    Error::Invalid("Cached grids must have `bytes` as a Uint8Array".to_string())
+                })?
+                .to_vec();
+            Ok(Some(CachedGrid {
+                bytes,
+                etag: field("etag").and_then(|e| e.as_string()),
+                last_modified: field("lastModified").and_then(|l| l.as_string()),
+            }))
+        })
+    }
+
+    fn put(&self, url: &str, grid: CachedGrid) -> Pending<'_, ()> {
+        let url = JsValue::from_str(url);
+        Box::pin(async move {
+            let object = Object::new();
+            let bytes = Uint8Array::from(grid.bytes.as_slice());
+            let _ = Reflect::set(&object, &JsValue::from_str("bytes"), &bytes);
+            if let Some(etag) = grid.etag {
+                let _ = Reflect::set(&object, &JsValue::from_str("etag"), &etag.into());
+            }
+            if let Some(modified) = grid.last_modified {
+                let _ = Reflect::set(
+                    &object,
+                    &JsValue::from_str("lastModified"),
+                    &modified.into(),
+                );
+            }
+            self.call("put", &[url, object.into()]).await?;
+            Ok(())
+        })
+    }
+}
+
+thread_local! {
+    // Wasm is single threaded, and JS backed caches can not be shared between threads anyway
+    static GRID_CACHE: RefCell<Option<Rc<dyn GridCache>>> = const { RefCell::new(None) };
+}
+
+/// The cache set with `setGridCache`, if any
+pub(crate) fn grid_cache() -> Option<Rc<dyn GridCache>> {
+    GRID_CACHE.with(|cache| cache.borrow().clone())
+}
+
+/// Cache grid files downloaded by `registerGrid` between visits.
+///
+/// `backend` is either `"memory"` to keep them for as long as the page, or an object with
+/// `get(url)` and `put(url, grid)` functions storing them elsewhere, eg in IndexedDB or the
+/// Cache API. `undefined` turns caching off.
+///
+/// Cached grids are revalidated with their `ETag` or `Last-Modified` header, so cross origin
+/// servers must expose `ETag` to avoid downloading unchanged grids again.
+#[wasm_bindgen(js_name = setGridCache)]
+pub fn set_grid_cache(backend: Option<JsGridCacheBackend>) -> WasmResult<()> {
+    let backend: Option<JsValue> = backend.map(Into::into);
+    let cache: Option<Rc<dyn GridCache>> = match backend {
+        None => None,
+        Some(backend) if backend.is_undefined() || backend.is_null() => None,
+        Some(backend) if backend.as_string().as_deref() == Some("memory") => {
+            Some(Rc::new(MemoryCache::default()))
+        }
+        Some(backend) if backend.is_object() => Some(Rc::new(JsCache { backend })),
+        Some(_) => {
+            return Err(Error::Invalid(
+                "The grid cache must be \"memory\" or an object with `get` and `put` functions"
+                    .to_string(),
+            ))
+        }
+    };
+    GRID_CACHE.with(|current| *current.borrow_mut() = cache);
+    Ok(())
+}
+
+/// Fetch a grid file, revalidating and updating the cached copy
+pub(crate) async fn fetch(
+    cache: &dyn GridCache,
+    url: &Url,
+    options: &RequestOptions,
+    on_progress: Option<&Function>,
+) -> Result<Vec<u8>> {
+    let cached = cache.get(url.as_str()).await.unwrap_or_else(|e| {
+        log::warn!("{}", e);
+        None
+    });
+
+    let mut request = options.clone();
+    if let Some(cached) = &cached {
+        request.headers.extend(conditional_headers(cached));
+    }
+    let response = match revalidate(url, request.send_with_retries(url, None).await, cached)? {
+        Revalidated::Cached(bytes) => return Ok(bytes),
+        Revalidated::Changed(response) => response,
+    };
+
+    let (etag, last_modified) = validators(response.headers());
+    let bytes = read_body(response, on_progress).await?;
+    let grid = CachedGrid {
+        bytes: bytes.clone(),
+        etag,
+        last_modified,
+    };
+    if let Err(e) = cache.put(url.as_str(), grid).await {
+        log::warn!("{}", e);
+    }
+    Ok(bytes)
+}
+
+// The outcome of requesting a file that may be cached
+#[derive(Debug, PartialEq)]
+enum Revalidated<T> {
+    Cached(Vec<u8>),
+    Changed(T),
+}
+
+// Use the cached copy if it has not changed, or when the network or the server fails
+fn revalidate<T: Status>(
+    url: &Url,
+    response: Result<T>,
+    cached: Option<CachedGrid>,
+) -> Result<Revalidated<T>> {
+    match (response, cached) {
+        (Ok(response), _) if response.status().is_success() => Ok(Revalidated::Changed(response)),
+        (Ok(response), Some(cached)) if response.status() == StatusCode::NOT_MODIFIED => {
+            log::info!("Using the cached copy of {}", url);
+            Ok(Revalidated::Cached(cached.bytes))
+        }
+        (Ok(response), Some(cached)) if response.status().is_server_error() => {
+            log::warn!(
+                "{}, using the cached copy of {}",
+                status_error(url, response.status()),
+                url
+            );
+            Ok(Revalidated::Cached(cached.bytes))
+        }
+        (Ok(response), _) => Err(status_error(url, response.status())),
+        (Err(e), Some(cached)) => {
+            log::warn!("{}, using the cached copy of {}", e, url);
+            Ok(Revalidated::Cached(cached.bytes))
+        }
+        (Err(e), None) => Err(e),
+    }
+}
+
+// Headers asking the server to only send the file if it changed
+fn conditional_headers(cached: &CachedGrid) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let value = |v: &Option<String>| v.as_deref().and_then(|v| HeaderValue::from_str(v).ok());
+    if let Some(etag) = value(&cached.etag) {
+        headers.insert(IF_NONE_MATCH, etag);
+    }
+    if let Some(modified) = value(&cached.last_modified) {
+        headers.insert(IF_MODIFIED_SINCE, modified);
+    }
+    headers
+}
+
+fn validators(headers: &HeaderMap) -> (Option<String>, Option<String>) {
+    let value = |name| {
+        headers
+            .get(name)
+            .and_then(|v: &HeaderValue| v.to_str().ok())
+            .map(str::to_string)
+    };
+    (value(ETAG), value(LAST_MODIFIED))
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geodesy::grids::lazy::tests::block_on;
+
+    #[test]
+    fn memory_cache() -> Result<()> {
+        let cache = MemoryCache::default();
+        let url = "https://cdn.proj.org/uk_os_OSTN15_NTv2_OSGBtoETRS.tif";
+        assert_eq!(block_on(cache.get(url))?, None);
+
+        let grid = CachedGrid {
+            bytes: vec![1, 2, 3],
+            etag: Some("\"abc\"".to_string()),
+            last_modified: None,
+        };
+        block_on(cache.put(url, grid.clone()))?;
+        assert_eq!(block_on(cache.get(url))?, Some(grid.clone()));
+
+        let headers = conditional_headers(&grid);
+        assert_eq!(headers.get(IF_NONE_MATCH).unwrap(), "\"abc\"");
+        assert!(headers.get(IF_MODIFIED_SINCE).is_none());
+
+        let mut response = HeaderMap::new();
+        response.insert(
+            LAST_MODIFIED,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(
+            validators(&response),
+            (None, Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn fallback() {
+        let url = Url::parse("https://cdn.proj.org/uk_os_OSTN15_NTv2_OSGBtoETRS.tif").unwrap();
+        let cached = || {
+            Some(CachedGrid {
+                bytes: vec![1, 2, 3],
+                etag: Some("\"abc\"".to_string()),
+                last_modified: None,
+            })
+        };
+        let revalidated = |response, cached| revalidate(&url, response, cached);
+
+        assert_eq!(
+            revalidated(Ok(StatusCode::OK), cached()).unwrap(),
+            Revalidated::Changed(StatusCode::OK)
+        );
+        assert_eq!(
+            revalidated(Ok(StatusCode::NOT_MODIFIED), cached()).unwrap(),
+            Revalidated::Cached(vec![1, 2, 3])
+        );
+        assert!(revalidated(Ok(StatusCode::NOT_MODIFIED), None).is_err());
+
+        // The network and server errors fall back to the cached copy
+        let offline = || Err(Error::Network("connection reset".to_string()));
+        assert_eq!(
+            revalidated(offline(), cached()).unwrap(),
+            Revalidated::Cached(vec![1, 2, 3])
+        );
+        assert!(revalidated(offline(), None).is_err());
+        assert_eq!(
+            revalidated(Ok(StatusCode::BAD_GATEWAY), cached()).unwrap(),
+            Revalidated::Cached(vec![1, 2, 3])
+        );
+
+        // Client errors are reported
+        for status in [
+            StatusCode::UNAUTHORIZED,
+            StatusCode::FORBIDDEN,
+            StatusCode::NOT_FOUND,
+        ] {
+            let error = revalidated(Ok(status), cached()).unwrap_err();
+            assert_eq!(error.name(), "NetworkError");
+            assert!(error.to_string().contains(status.as_str()));
+        }
+    }
+}
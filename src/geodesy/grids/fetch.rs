@@ -0,0 +1,306 @@
+//! Requesting grid files over HTTP.
+use crate::error::{Error, Result};
+use futures_core::Stream;
+use js_sys::Function;
+use reqwest::{
+    header::{HeaderMap, RANGE},
+    Response, StatusCode, Url,
+};
+use std::{
+    future::{poll_fn, Future},
+    ops::Range,
+    pin::pin,
+    str::FromStr,
+    time::Duration,
+};
+use wasm_bindgen::JsValue;
+
+/// Whether requests send cookies and HTTP authentication, as in the fetch API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Credentials {
+    Omit,
+    SameOrigin,
+    Include,
+}
+
+impl FromStr for Credentials {
+    type Err = Error;
+
+    fn from_str(credentials: &str) -> Result<Credentials> {
+        match credentials {
+            "omit" => Ok(Credentials::Omit),
+            "same-origin" => Ok(Credentials::SameOrigin),
+            "include" => Ok(Credentials::Include),
+            _ => Err(Error::Invalid(format!(
+                "Unknown credentials `{}`, expected one of omit, same-origin or include",
+                credentials
+            ))),
+        }
+    }
+}
+
+/// Options for the HTTP requests fetching a grid
+#[derive(Debug, Default, Clone)]
+pub struct RequestOptions {
+    pub headers: HeaderMap,
+    pub timeout: Option<Duration>,
+    /// Only used by the fetch API in the browser
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub credentials: Option<Credentials>,
+    /// Retries after network errors and responses that may succeed later
+    pub retries: u32,
+}
+
+/// The status of a response, so retries can be tested without a server
+pub(crate) trait Status {
+    fn status(&self) -> StatusCode;
+}
+
+impl Status for Response {
+    fn status(&self) -> StatusCode {
+        Response::status(self)
+    }
+}
+
+impl RequestOptions {
+    /// Send a GET request, for a byte range if given, retrying as configured.
+    /// Responses other than 2xx are errors.
+    pub async fn get(&self, url: &Url, range: Option<Range<u64>>) -> Result<Response> {
+        check(url, self.send_with_retries(url, range).await?)
+    }
+
+    /// Send a GET request, retrying as configured, and return the last response whatever
+    /// its status. Only failing to get a response is an error.
+    pub(crate) async fn send_with_retries(
+        &self,
+        url: &Url,
+        range: Option<Range<u64>>,
+    ) -> Result<Response> {
+        retry(url, self.retries, || self.send(url, range.clone())).await
+    }
+
+    async fn send(&self, url: &Url, range: Option<Range<u64>>) -> Result<Response> {
+        let mut request = reqwest::Client::new()
+            .get(url.clone())
+            .headers(self.headers.clone());
+        if let Some(range) = range {
+            let range = format!("bytes={}-{}", range.start, range.end.saturating_sub(1));
+            request = request.header(RANGE, range);
+        }
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            request = match self.credentials {
+                Some(Credentials::Omit) => request.fetch_credentials_omit(),
+                Some(Credentials::SameOrigin) => request.fetch_credentials_same_origin(),
+                Some(Credentials::Include) => request.fetch_credentials_include(),
+                None => request,
+            };
+        }
+        Ok(request.send().await?)
+    }
+}
+
+// Send requests until one gets a response that is not worth retrying or the retries run out,
+// returning the last response whatever its status
+async fn retry<T, F, R>(url: &Url, retries: u32, mut send: F) -> Result<T>
+where
+    T: Status,
+    F: FnMut() -> R,
+    R: Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        let result = send().await;
+        let reason = match &result {
+            Ok(response) if !is_transient(response.status()) => return result,
+            Ok(response) => status_error(url, response.status()).to_string(),
+            Err(e) => e.to_string(),
+        };
+        if attempt >= retries {
+            return result;
+        }
+
+        attempt += 1;
+        log::warn!("{}, retrying ({} of {})", reason, attempt, retries);
+        backoff(attempt).await;
+    }
+}
+
+// The response if it succeeded, otherwise an error naming the URL and status
+fn check<T: Status>(url: &Url, response: T) -> Result<T> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else {
+        Err(status_error(url, status))
+    }
+}
+
+pub(crate) fn status_error(url: &Url, status: StatusCode) -> Error {
+    Error::Network(format!("`{}` responded with {}", url, status))
+}
+
+// Responses worth retrying
+fn is_transient(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+// Milliseconds to wait before a retry, doubling from 250 ms up to 8 s
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+fn delay(attempt: u32) -> u32 {
+    250 * 2_u32.pow(attempt.clamp(1, 6) - 1)
+}
+
+// Wait a little longer after every failed attempt
+#[cfg(target_arch = "wasm32")]
+async fn backoff(attempt: u32) {
+    use wasm_bindgen::JsCast;
+
+    let delay = delay(attempt);
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let set_timeout = js_sys::Reflect::get(&js_sys::global(), &"setTimeout".into())
+            .ok()
+            .and_then(|f| f.dyn_into::<Function>().ok());
+        match set_timeout {
+            Some(set_timeout) => {
+                let _ = set_timeout.call2(&JsValue::NULL, &resolve, &delay.into());
+            }
+            None => {
+                let _ = resolve.call0(&JsValue::NULL);
+            }
+        }
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn backoff(_attempt: u32) {}
+
+/// Read the whole body, calling `on_progress(loaded, total)` after each chunk.
+/// `total` is `undefined` when the server does not send the length.
+pub(crate) async fn read_body(
+    response: Response,
+    on_progress: Option<&Function>,
+) -> Result<Vec<u8>> {
+    let total = response.content_length();
+    let mut body = Vec::with_capacity(total.unwrap_or(0) as usize);
+    let mut chunks = pin!(response.bytes_stream());
+    while let Some(chunk) = poll_fn(|cx| chunks.as_mut().poll_next(cx)).await {
+        body.extend_from_slice(&chunk?);
+        if let Some(on_progress) = on_progress {
+            let total = total.map_or(JsValue::UNDEFINED, |t| JsValue::from(t as f64));
+            if let Err(e) =
+                on_progress.call2(&JsValue::NULL, &JsValue::from(body.len() as f64), &total)
+            {
+                log::warn!("The progress callback failed: {:?}", e);
+            }
+        }
+    }
+    Ok(body)
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geodesy::grids::lazy::tests::block_on;
+    use std::{cell::RefCell, future::ready};
+
+    impl Status for StatusCode {
+        fn status(&self) -> StatusCode {
+            *self
+        }
+    }
+
+    // A stand-in for a server answering each request with the next response, counting them
+    struct Stub {
+        responses: RefCell<Vec<Result<StatusCode>>>,
+        requests: RefCell<usize>,
+    }
+
+    impl Stub {
+        fn new(mut responses: Vec<Result<StatusCode>>) -> Stub {
+            responses.reverse();
+            Stub {
+                responses: RefCell::new(responses),
+                requests: RefCell::new(0),
+            }
+        }
+
+        fn get(&self, url: &Url, retries: u32) -> Result<StatusCode> {
+            let send = || {
+                *self.requests.borrow_mut() += 1;
+                ready(self.responses.borrow_mut().pop().unwrap())
+            };
+            check(url, block_on(retry(url, retries, send))?)
+        }
+    }
+
+    #[test]
+    fn options() -> Result<()> {
+        assert_eq!("include".parse::<Credentials>()?, Credentials::Include);
+        assert!("everyone".parse::<Credentials>().is_err());
+
+        assert!(is_transient(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_transient(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_transient(StatusCode::FORBIDDEN));
+        assert!(!is_transient(StatusCode::NOT_FOUND));
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        let url = Url::parse("https://cdn.proj.org/uk_os_OSTN15_NTv2_OSGBtoETRS.tif").unwrap();
+
+        // Client errors are not retried
+        let stub = Stub::new(vec![Ok(StatusCode::FORBIDDEN)]);
+        let error = stub.get(&url, 3).unwrap_err();
+        assert_eq!(*stub.requests.borrow(), 1);
+        assert_eq!(error.name(), "NetworkError");
+        assert_eq!(error.code(), "NETWORK");
+        let message = error.to_string();
+        assert!(message.contains("403 Forbidden"), "{}", message);
+        assert!(message.contains(url.as_str()), "{}", message);
+
+        // Until the retries run out, with the status of the last response
+        let stub = Stub::new(
+            (0..3)
+                .map(|_| Ok(StatusCode::SERVICE_UNAVAILABLE))
+                .collect(),
+        );
+        let error = stub.get(&url, 2).unwrap_err();
+        assert_eq!(*stub.requests.borrow(), 3);
+        assert!(error.to_string().contains("503 Service Unavailable"));
+
+        // Not modified only makes sense when revalidating a cached copy
+        let stub = Stub::new(vec![Ok(StatusCode::NOT_MODIFIED)]);
+        assert!(matches!(stub.get(&url, 0), Err(Error::Network(_))));
+    }
+
+    #[test]
+    fn retries() -> Result<()> {
+        let url = Url::parse("https://cdn.proj.org/uk_os_OSTN15_NTv2_OSGBtoETRS.tif").unwrap();
+        let stub = Stub::new(vec![
+            Err(Error::Network("connection reset".to_string())),
+            Ok(StatusCode::TOO_MANY_REQUESTS),
+            Ok(StatusCode::BAD_GATEWAY),
+            Ok(StatusCode::PARTIAL_CONTENT),
+        ]);
+        assert_eq!(stub.get(&url, 3)?, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(*stub.requests.borrow(), 4);
+
+        let stub = Stub::new(vec![Err(Error::Network("connection reset".to_string()))]);
+        assert!(matches!(stub.get(&url, 0), Err(Error::Network(_))));
+
+        // Waiting twice as long after every failure, up to 8 s
+        let delays: Vec<u32> = (1..=8).map(delay).collect();
+        assert_eq!(delays, [250, 500, 1000, 2000, 4000, 8000, 8000, 8000]);
+        Ok(())
+    }
+}
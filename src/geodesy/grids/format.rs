@@ -0,0 +1,203 @@
+//! Grid file formats and detecting them from the file content.
+use crate::error::{Error, Result};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridFormat {
+    Ntv2,
+    Ntv1,
+    Gravsoft,
+    Gtx,
+    Isg,
+    GeoTiff,
+    /// A pair of `.las`/`.los` files, only registered with `registerNadconGridSync`
+    Nadcon,
+    /// Sampled from a transformation with `Geo.bakeGrid`
+    Baked,
+}
+
+impl GridFormat {
+    /// Detect the format from magic bytes or, for formats without any, a consistent header.
+    pub fn detect(bytes: &[u8]) -> Option<GridFormat> {
+        if is_tiff(bytes) {
+            Some(GridFormat::GeoTiff)
+        } else if bytes.starts_with(b"NUM_OREC") && bytes.get(16..24) == Some(b"NUM_SREC") {
+            Some(GridFormat::Ntv2)
+        } else if is_ntv1(bytes) {
+            Some(GridFormat::Ntv1)
+        } else if is_gtx(bytes) {
+            Some(GridFormat::Gtx)
+        } else if is_isg(bytes) {
+            Some(GridFormat::Isg)
+        } else if is_gravsoft(bytes) {
+            Some(GridFormat::Gravsoft)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GridFormat::Ntv2 => "NTv2",
+            GridFormat::Ntv1 => "NTv1",
+            GridFormat::Gravsoft => "Gravsoft",
+            GridFormat::Gtx => "GTX",
+            GridFormat::Isg => "ISG",
+            GridFormat::GeoTiff => "GeoTIFF",
+            GridFormat::Nadcon => "NADCON",
+            GridFormat::Baked => "Baked",
+        }
+    }
+}
+
+impl fmt::Display for GridFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for GridFormat {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<GridFormat> {
+        match format.trim().to_ascii_lowercase().as_str() {
+            "ntv2" | "gsb" => Ok(GridFormat::Ntv2),
+            "ntv1" => Ok(GridFormat::Ntv1),
+            "gravsoft" => Ok(GridFormat::Gravsoft),
+            "gtx" => Ok(GridFormat::Gtx),
+            "isg" => Ok(GridFormat::Isg),
+            "geotiff" | "tif" | "tiff" => Ok(GridFormat::GeoTiff),
+            _ => Err(Error::Invalid(format!(
+                "Unknown grid format `{}`, expected one of ntv2, ntv1, gravsoft, gtx, isg or geotiff",
+                format
+            ))),
+        }
+    }
+}
+
+fn is_tiff(bytes: &[u8]) -> bool {
+    matches!(
+        bytes.get(0..4),
+        Some(b"II*\0" | b"MM\0*" | b"II+\0" | b"MM\0+")
+    )
+}
+
+// The header records PROJ recognises NTv1 by: the record count, the longitude increment and
+// the target datum
+fn is_ntv1(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"HEADER")
+        && bytes.get(96..102) == Some(b"W GRID")
+        && bytes.get(144..160) == Some(b"TO      NAD83   ")
+}
+
+// A 40 byte big endian header followed by exactly the nodes it describes
+fn is_gtx(bytes: &[u8]) -> bool {
+    if bytes.len() < 40 {
+        return false;
+    }
+    let real = |offset: usize| f64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap());
+    let int = |offset: usize| i32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+    let (lat_s, dlat, dlon) = (real(0), real(16), real(24));
+    let (rows, cols) = (int(32) as i64, int(36) as i64);
+    (-90.0..=90.0).contains(&lat_s)
+        && dlat > 0.
+        && dlon > 0.
+        && rows > 0
+        && cols > 0
+        && bytes.len() as i64 == 40 + 4 * rows * cols
+}
+
+// Text with a `begin_of_head` line after the leading comments
+fn is_isg(bytes: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(&bytes[..bytes.len().min(4096)]);
+    text.lines()
+        .any(|line| line.trim_start().starts_with("begin_of_head"))
+}
+
+// Text starting with the six numbers of the header, after any comments
+fn is_gravsoft(bytes: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+    let tokens: Vec<&str> = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(str::split_whitespace)
+        .take(6)
+        .collect();
+    tokens.len() == 6 && tokens.iter().all(|t| t.parse::<f64>().is_ok())
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(key: &[u8; 8]) -> Vec<u8> {
+        let mut record = key.to_vec();
+        record.extend([0; 8]);
+        record
+    }
+
+    #[test]
+    fn detect() {
+        let mut ntv2 = record(b"NUM_OREC");
+        ntv2.extend(record(b"NUM_SREC"));
+        assert_eq!(GridFormat::detect(&ntv2), Some(GridFormat::Ntv2));
+
+        // The records of a real NTv1 header, without their values
+        let mut ntv1 = Vec::new();
+        for key in [
+            b"HEADER  ",
+            b"S LAT   ",
+            b"N LAT   ",
+            b"E LONG  ",
+            b"W LONG  ",
+            b"N GRID  ",
+            b"W GRID  ",
+            b"TYPE    ",
+            b"FROM    ",
+            b"TO      ",
+            b"VERSION ",
+            b"        ",
+        ] {
+            ntv1.extend(record(key));
+        }
+        ntv1[152..160].copy_from_slice(b"NAD83   ");
+        assert_eq!(GridFormat::detect(&ntv1), Some(GridFormat::Ntv1));
+        // NTv2 files start with their own record count
+        assert_eq!(GridFormat::detect(&ntv2[..16]), None);
+
+        assert_eq!(
+            GridFormat::detect(b"II*\0\x08\0\0\0"),
+            Some(GridFormat::GeoTiff)
+        );
+
+        // 2x3 nodes
+        let mut gtx = Vec::new();
+        for v in [50., -1., 1., 1.] {
+            gtx.extend(f64::to_be_bytes(v));
+        }
+        gtx.extend(2_i32.to_be_bytes());
+        gtx.extend(3_i32.to_be_bytes());
+        gtx.extend([0; 24]);
+        assert_eq!(GridFormat::detect(&gtx), Some(GridFormat::Gtx));
+
+        let isg = b"comment\nbegin_of_head ====\nnrows : 2\nend_of_head ====\n";
+        assert_eq!(GridFormat::detect(isg), Some(GridFormat::Isg));
+
+        let gravsoft = b"# comment\n 54.0 58.0 8.0 16.0 0.25 0.5\n 0.1 0.2";
+        assert_eq!(GridFormat::detect(gravsoft), Some(GridFormat::Gravsoft));
+
+        assert_eq!(GridFormat::detect(b"\x00\x01\x02"), None);
+    }
+
+    #[test]
+    fn parse() -> Result<()> {
+        assert_eq!("NTv2".parse::<GridFormat>()?, GridFormat::Ntv2);
+        assert_eq!("tif".parse::<GridFormat>()?, GridFormat::GeoTiff);
+        assert!("shapefile".parse::<GridFormat>().is_err());
+        assert!("byn".parse::<GridFormat>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,1526 @@
+//! GeoTIFF grids as distributed on the PROJ CDN (<https://cdn.proj.org>).
+//!
+//! Follows the [PROJ GeoTIFF grid specification](https://proj.org/specifications/geodeticgrids.html):
+//! classic or BigTIFF files holding geographic rasters, stripped or tiled, uncompressed or
+//! compressed with LZW or Deflate (with horizontal and floating point predictors).
+//! The meaning and units of each band come from the GDAL metadata tag.
+//!
+//! Every full resolution image in the file is a subgrid, nested through the `grid_name`
+//! and `parent_grid_name` metadata items. Overviews and masks are skipped.
+//!
+//! Grids are written as classic, uncompressed TIFF files with one image per subgrid.
+use super::raster::{GridHeader, GridSet, RasterGrid};
+use crate::error::{Error, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+};
+
+// TIFF tags
+const NEW_SUBFILE_TYPE: u16 = 254;
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const PLANAR_CONFIGURATION: u16 = 284;
+const PREDICTOR: u16 = 317;
+const TILE_WIDTH: u16 = 322;
+const TILE_LENGTH: u16 = 323;
+const TILE_OFFSETS: u16 = 324;
+const TILE_BYTE_COUNTS: u16 = 325;
+const SAMPLE_FORMAT: u16 = 339;
+const MODEL_PIXEL_SCALE: u16 = 33550;
+const MODEL_TIEPOINT: u16 = 33922;
+const GEO_KEY_DIRECTORY: u16 = 34735;
+const GDAL_METADATA: u16 = 42112;
+const GDAL_NODATA: u16 = 42113;
+
+// GeoKeys
+const GT_MODEL_TYPE: u16 = 1024;
+const GT_RASTER_TYPE: u16 = 1025;
+const MODEL_TYPE_GEOGRAPHIC: u64 = 2;
+const RASTER_PIXEL_IS_POINT: u64 = 2;
+
+// NewSubfileType flags
+const REDUCED_RESOLUTION: u64 = 1;
+const TRANSPARENCY_MASK: u64 = 4;
+
+/// Read all subgrids of a GeoTIFF grid
+pub fn read(bytes: &[u8]) -> Result<GridSet> {
+    let tiff = Tiff::new(bytes)?;
+    let directories = directories(bytes)?;
+
+    let mut grids = Vec::with_capacity(directories.len());
+    for directory in &directories {
+        let range = &directory.data;
+        let data = tiff.slice(range.start, range.end - range.start)?;
+        grids.push(read_directory(bytes, directory, data)?);
+    }
+
+    let parents = directories.iter().map(|d| d.parent).collect();
+    let names = directories.into_iter().map(|d| d.name).collect();
+    Ok(GridSet::new(grids, parents)?.with_names(names))
+}
+
+/// Write all subgrids of a grid as a little endian GeoTIFF of `f32` bands.
+/// Two band grids are written as horizontal offsets in arc seconds, three band grids
+/// as velocities in metres per year and others as they are.
+pub fn write(grid: &GridSet) -> Result<Vec<u8>> {
+    let names = grid.unique_names(usize::MAX);
+    let mut file = b"II*\0".to_vec();
+    // Where to put the offset of the next image directory
+    let mut next = file.len();
+    file.extend(0_u32.to_le_bytes());
+    for (i, subgrid) in grid.grids().iter().enumerate() {
+        let h = subgrid.header();
+        let parent = grid.parents()[i].map(|parent| names[parent].as_str());
+        let metadata = metadata_xml(h.bands, &names[i], parent);
+
+        let spp = h.bands as u16;
+        let tags = vec![
+            (IMAGE_WIDTH, 4, (h.cols as u32).to_le_bytes().to_vec()),
+            (IMAGE_LENGTH, 4, (h.rows as u32).to_le_bytes().to_vec()),
+            (BITS_PER_SAMPLE, 3, repeat(32, spp)),
+            (COMPRESSION, 3, repeat(1, 1)),
+            (PHOTOMETRIC_INTERPRETATION, 3, repeat(1, 1)),
+            (SAMPLES_PER_PIXEL, 3, repeat(spp, 1)),
+            (ROWS_PER_STRIP, 4, (h.rows as u32).to_le_bytes().to_vec()),
+            (PLANAR_CONFIGURATION, 3, repeat(1, 1)),
+            (SAMPLE_FORMAT, 3, repeat(3, spp)),
+            (
+                MODEL_PIXEL_SCALE,
+                12,
+                doubles(&[h.dlon.to_degrees(), h.dlat.to_degrees(), 0.]),
+            ),
+            (
+                MODEL_TIEPOINT,
+                12,
+                doubles(&[0., 0., 0., h.lon_w.to_degrees(), h.lat_n.to_degrees(), 0.]),
+            ),
+            (
+                GEO_KEY_DIRECTORY,
+                3,
+                // Version 1.1.0 with two keys: geographic, PixelIsPoint
+                [1, 1, 0, 2, GT_MODEL_TYPE, 0, 1, 2, GT_RASTER_TYPE, 0, 1, 2]
+                    .iter()
+                    .flat_map(|v: &u16| v.to_le_bytes())
+                    .collect(),
+            ),
+            (GDAL_METADATA, 2, format!("{}\0", metadata).into_bytes()),
+            (GDAL_NODATA, 2, b"nan\0".to_vec()),
+        ];
+
+        let arcsec = (1. / 3600_f64).to_radians();
+        let image: Vec<u8> = subgrid
+            .nodes()
+            .iter()
+            .map(|v| {
+                if h.bands == 2 {
+                    (*v as f64 / arcsec) as f32
+                } else {
+                    *v
+                }
+            })
+            .flat_map(f32::to_le_bytes)
+            .collect();
+
+        let ifd = offset(file.len())?;
+        file[next..next + 4].copy_from_slice(&ifd.to_le_bytes());
+        next = write_directory(&mut file, tags, &image)?;
+    }
+    Ok(file)
+}
+
+// The GDAL metadata of a subgrid with `bands` bands
+fn metadata_xml(bands: usize, name: &str, parent: Option<&str>) -> String {
+    let (kind, descriptions): (Option<&str>, &[(&str, &str)]) = match bands {
+        2 => (
+            Some("HORIZONTAL_OFFSET"),
+            &[
+                ("latitude_offset", "arc-second"),
+                ("longitude_offset", "arc-second"),
+            ],
+        ),
+        3 => (
+            Some("VELOCITY"),
+            &[
+                ("east_velocity", "metres per year"),
+                ("north_velocity", "metres per year"),
+                ("up_velocity", "metres per year"),
+            ],
+        ),
+        _ => (None, &[]),
+    };
+
+    let mut items = Vec::new();
+    let mut item = |attributes: String, value: &str| {
+        items.push(format!("  <Item {}>{}</Item>", attributes, escape(value)));
+    };
+    let band = |name: &str, band: usize| format!("name=\"{}\" sample=\"{}\"", name, band);
+    if let Some(kind) = kind {
+        item("name=\"TYPE\"".to_string(), kind);
+    }
+    item("name=\"grid_name\"".to_string(), name);
+    if let Some(parent) = parent {
+        item("name=\"parent_grid_name\"".to_string(), parent);
+    }
+    for (i, (description, units)) in descriptions.iter().enumerate() {
+        item(
+            band("DESCRIPTION", i) + " role=\"description\"",
+            description,
+        );
+        item(band("UNITTYPE", i) + " role=\"unittype\"", units);
+    }
+    if bands == 2 {
+        item(band("positive_value", 1), "east");
+    }
+    format!("<GDALMetadata>\n{}\n</GDALMetadata>", items.join("\n"))
+}
+
+// Append an image directory and its image, returning where the offset of the next one goes
+fn write_directory(
+    file: &mut Vec<u8>,
+    mut tags: Vec<(u16, u16, Vec<u8>)>,
+    image: &[u8],
+) -> Result<usize> {
+    // Values of more than 4 bytes follow the directory, each starting on a word boundary
+    let start = file.len();
+    let values_at = start + 2 + 12 * (tags.len() + 2) + 4;
+    let values_size: usize = tags
+        .iter()
+        .filter(|(_, _, data)| data.len() > 4)
+        .map(|(_, _, data)| data.len() + data.len() % 2)
+        .sum();
+    tags.push((
+        STRIP_OFFSETS,
+        4,
+        offset(values_at + values_size)?.to_le_bytes().to_vec(),
+    ));
+    tags.push((
+        STRIP_BYTE_COUNTS,
+        4,
+        offset(image.len())?.to_le_bytes().to_vec(),
+    ));
+    tags.sort_by_key(|t| t.0);
+
+    let mut values = Vec::with_capacity(values_size);
+    file.extend((tags.len() as u16).to_le_bytes());
+    for (tag, kind, data) in &tags {
+        let count = data.len() as u64 / type_size(*kind).unwrap_or(1);
+        file.extend(tag.to_le_bytes());
+        file.extend(kind.to_le_bytes());
+        file.extend((count as u32).to_le_bytes());
+        if data.len() <= 4 {
+            let mut inline = data.clone();
+            inline.resize(4, 0);
+            file.extend(inline);
+        } else {
+            file.extend(offset(values_at + values.len())?.to_le_bytes());
+            values.extend(data);
+            if data.len() % 2 == 1 {
+                values.push(0);
+            }
+        }
+    }
+    let next = file.len();
+    file.extend(0_u32.to_le_bytes());
+    file.extend(values);
+    file.extend(image);
+    if image.len() % 2 == 1 {
+        file.push(0);
+    }
+    Ok(next)
+}
+
+// An offset into a classic TIFF file
+fn offset(offset: usize) -> Result<u32> {
+    u32::try_from(offset).map_err(|_| invalid("grids of 4 GB or more can not be written"))
+}
+
+fn repeat(value: u16, count: u16) -> Vec<u8> {
+    (0..count).flat_map(|_| value.to_le_bytes()).collect()
+}
+
+fn doubles(values: &[f64]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// A subgrid of a GeoTIFF grid, described without reading its values
+#[derive(Debug, Clone)]
+pub(crate) struct Directory {
+    /// The offset of the image directory in the file
+    offset: u64,
+    metadata: Metadata,
+    pub header: GridHeader,
+    pub name: Option<String>,
+    /// The index of the parent subgrid
+    pub parent: Option<usize>,
+    /// The bytes of the file holding the image data
+    pub data: Range<u64>,
+}
+
+/// Describe the subgrids of a GeoTIFF grid from (the start of) a file holding all the
+/// image directories
+pub(crate) fn directories(bytes: &[u8]) -> Result<Vec<Directory>> {
+    let tiff = Tiff::new(bytes)?;
+
+    let mut directories = Vec::new();
+    let mut parent_names = Vec::new();
+    let mut first_metadata: Option<Metadata> = None;
+
+    let mut visited = BTreeSet::new();
+    let mut offset = tiff.first_ifd()?;
+    while offset != 0 {
+        if !visited.insert(offset) {
+            return Err(invalid("the image directories form a cycle"));
+        }
+        let (ifd, next) = tiff.ifd(offset)?;
+
+        let subfile_type = ifd.uint(NEW_SUBFILE_TYPE).unwrap_or(0);
+        if subfile_type & (REDUCED_RESOLUTION | TRANSPARENCY_MASK) == 0 {
+            let mut metadata = ifd
+                .ascii(GDAL_METADATA)
+                .map(|xml| Metadata::parse(&xml))
+                .unwrap_or_default();
+            let name = metadata.get("grid_name").map(str::to_string);
+            parent_names.push(metadata.get("parent_grid_name").map(str::to_string));
+            // Later subgrids usually only repeat what differs from the first one
+            match &first_metadata {
+                Some(first) => metadata.inherit(first),
+                None => first_metadata = Some(metadata.clone()),
+            }
+
+            directories.push(Directory {
+                offset,
+                header: subgrid_header(&ifd, &metadata)?,
+                data: data_range(&ifd)?,
+                metadata,
+                name,
+                parent: None,
+            });
+        }
+        offset = next;
+    }
+
+    for (i, parent) in parent_names.iter().enumerate() {
+        directories[i].parent = parent.as_deref().and_then(|parent| {
+            directories
+                .iter()
+                .position(|d| d.name.as_deref() == Some(parent))
+        });
+    }
+    Ok(directories)
+}
+
+/// Read the values of a subgrid, where `data` holds the bytes `directory.data` of the file
+/// and `bytes` the start of the file as given to [directories]
+pub(crate) fn read_directory(
+    bytes: &[u8],
+    directory: &Directory,
+    data: &[u8],
+) -> Result<RasterGrid> {
+    let tiff = Tiff::new(bytes)?;
+    let (ifd, _) = tiff.ifd(directory.offset)?;
+    let image = Image::read(&tiff.with_data(data, directory.data.start), &ifd)?;
+    let values = band_values(&image, &ifd, &directory.metadata)?;
+    RasterGrid::new(directory.header, values)
+}
+
+/// The bytes of the file holding the strips or tiles with nodes in `rows` and `cols` of a
+/// subgrid, where `bytes` is the start of the file as given to [directories]
+pub(crate) fn window_data(
+    bytes: &[u8],
+    directory: &Directory,
+    rows: &Range<usize>,
+    cols: &Range<usize>,
+) -> Result<Vec<Range<u64>>> {
+    let tiff = Tiff::new(bytes)?;
+    let (ifd, _) = tiff.ifd(directory.offset)?;
+    let chunks = Chunks::new(&ifd, tiff.little)?;
+    Ok(chunks
+        .covering(rows, cols)
+        .map(|(index, ..)| chunks.offsets[index]..chunks.offsets[index] + chunks.counts[index])
+        .collect())
+}
+
+/// Read the nodes in `rows` and `cols` of a subgrid, where `parts` hold (at least) the
+/// bytes given by [window_data] as `(offset, bytes)` pairs
+pub(crate) fn read_window(
+    bytes: &[u8],
+    directory: &Directory,
+    rows: &Range<usize>,
+    cols: &Range<usize>,
+    parts: &[(u64, Vec<u8>)],
+) -> Result<RasterGrid> {
+    let tiff = Tiff::new(bytes)?;
+    let (ifd, _) = tiff.ifd(directory.offset)?;
+    let chunks = Chunks::new(&ifd, tiff.little)?;
+    let image = chunks.read(rows, cols, |offset, count| {
+        parts
+            .iter()
+            .find(|(start, part)| offset >= *start && offset + count <= start + part.len() as u64)
+            .map(|(start, part)| {
+                &part[(offset - start) as usize..(offset - start + count) as usize]
+            })
+            .ok_or_else(|| invalid("unexpected end of file"))
+    })?;
+    let values = band_values(&image, &ifd, &directory.metadata)?;
+    RasterGrid::new(directory.header.part(rows, cols), values)
+}
+
+fn subgrid_header(ifd: &Ifd, metadata: &Metadata) -> Result<GridHeader> {
+    let geokeys = ifd.geokeys();
+    if let Some(model) = geokeys.get(&GT_MODEL_TYPE) {
+        if *model != MODEL_TYPE_GEOGRAPHIC {
+            return Err(invalid(
+                "only grids in geographic coordinates are supported",
+            ));
+        }
+    }
+    let pixel_is_point = geokeys.get(&GT_RASTER_TYPE) == Some(&RASTER_PIXEL_IS_POINT);
+
+    let scale = ifd
+        .values(MODEL_PIXEL_SCALE)
+        .filter(|s| s.len() >= 2)
+        .ok_or_else(|| invalid("missing ModelPixelScale"))?;
+    let tiepoint = ifd
+        .values(MODEL_TIEPOINT)
+        .filter(|t| t.len() >= 6)
+        .ok_or_else(|| invalid("missing ModelTiepoint"))?;
+
+    let (mut lon_w, mut lat_n) = (
+        tiepoint[3] - tiepoint[0] * scale[0],
+        tiepoint[4] + tiepoint[1] * scale[1],
+    );
+    // Grids refer to node centres, PixelIsArea tiepoints to the corner of the first cell
+    if !pixel_is_point {
+        lon_w += scale[0] / 2.;
+        lat_n -= scale[1] / 2.;
+    }
+
+    let spp = ifd.uint(SAMPLES_PER_PIXEL).unwrap_or(1) as usize;
+    let (bands, _) = select_bands(spp, metadata)?;
+    Ok(GridHeader {
+        lat_n: lat_n.to_radians(),
+        lon_w: lon_w.to_radians(),
+        dlat: scale[1].to_radians(),
+        dlon: scale[0].to_radians(),
+        rows: ifd.uint(IMAGE_LENGTH).unwrap_or(0) as usize,
+        cols: ifd.uint(IMAGE_WIDTH).unwrap_or(0) as usize,
+        bands: bands.len(),
+    })
+}
+
+// The span of the file holding the strips or tiles of an image
+fn data_range(ifd: &Ifd) -> Result<Range<u64>> {
+    let (offsets, counts) = if ifd.fields.contains_key(&TILE_OFFSETS) {
+        (ifd.uints(TILE_OFFSETS), ifd.uints(TILE_BYTE_COUNTS))
+    } else {
+        (ifd.uints(STRIP_OFFSETS), ifd.uints(STRIP_BYTE_COUNTS))
+    };
+    let (Some(offsets), Some(counts)) = (offsets, counts) else {
+        return Err(invalid("missing image data offsets"));
+    };
+
+    let chunks = offsets.iter().zip(&counts).filter(|(_, count)| **count > 0);
+    let start = chunks.clone().map(|(offset, _)| *offset).min();
+    let end = chunks
+        .map(|(offset, count)| offset.checked_add(*count))
+        .max()
+        .flatten();
+    match (start, end) {
+        (Some(start), Some(end)) => Ok(start..end),
+        _ => Err(invalid("missing image data offsets")),
+    }
+}
+
+// The bands holding the grid, in the order of [RasterGrid], and their default units
+fn select_bands(spp: usize, metadata: &Metadata) -> Result<(Vec<usize>, &'static str)> {
+    let descriptions: Vec<Option<&str>> = (0..spp)
+        .map(|band| metadata.band("DESCRIPTION", band))
+        .collect();
+    let band_named = |name: &str| descriptions.iter().position(|d| *d == Some(name));
+
+    let horizontal = metadata.get("TYPE") == Some("HORIZONTAL_OFFSET")
+        || (band_named("latitude_offset").is_some() && band_named("longitude_offset").is_some());
+    let velocity = metadata.get("TYPE") == Some("VELOCITY")
+        || (band_named("east_velocity").is_some() && band_named("north_velocity").is_some());
+    if horizontal {
+        if spp < 2 {
+            return Err(invalid("horizontal offset grids need two bands"));
+        }
+        let bands = vec![
+            band_named("latitude_offset").unwrap_or(0),
+            band_named("longitude_offset").unwrap_or(1),
+        ];
+        return Ok((bands, "arc-second"));
+    }
+    if velocity {
+        if spp < 3 {
+            return Err(invalid(
+                "velocity grids need east, north and up velocity bands",
+            ));
+        }
+        let bands = vec![
+            band_named("east_velocity").unwrap_or(0),
+            band_named("north_velocity").unwrap_or(1),
+            band_named("up_velocity").unwrap_or(2),
+        ];
+        return Ok((bands, "millimetres per year"));
+    }
+    Ok(((0..spp).collect(), "metre"))
+}
+
+// Select, order and convert the bands to the layout of [RasterGrid]
+fn band_values(image: &Image, ifd: &Ifd, metadata: &Metadata) -> Result<Vec<f32>> {
+    let spp = image.samples_per_pixel;
+    let (bands, default_units) = select_bands(spp, metadata)?;
+
+    let nodata = ifd
+        .ascii(GDAL_NODATA)
+        .and_then(|n| n.trim().parse::<f64>().ok());
+
+    let mut factors = Vec::with_capacity(bands.len());
+    for &band in &bands {
+        let scale = metadata.band_real("SCALE", band).unwrap_or(1.);
+        let offset = metadata.band_real("OFFSET", band).unwrap_or(0.);
+        let units = metadata.band("UNITTYPE", band).unwrap_or(default_units);
+        let mut unit = unit_factor(units);
+        if metadata.band("positive_value", band) == Some("west") {
+            unit = -unit;
+        }
+        factors.push((scale * unit, offset * unit));
+    }
+
+    let nodes = image.width * image.height;
+    let mut values = Vec::with_capacity(nodes * bands.len());
+    for node in 0..nodes {
+        for (i, &band) in bands.iter().enumerate() {
+            let raw = image.samples[node * spp + band];
+            let is_null = match nodata {
+                Some(nodata) if nodata.is_nan() => raw.is_nan(),
+                Some(nodata) => raw == nodata,
+                None => false,
+            };
+            let (scale, offset) = factors[i];
+            values.push(if is_null {
+                f32::NAN
+            } else {
+                (raw * scale + offset) as f32
+            });
+        }
+    }
+    Ok(values)
+}
+
+// Angular units are converted to radians, millimetres (per year) to metres (per year),
+// everything else is kept as is
+fn unit_factor(units: &str) -> f64 {
+    let units = units.to_ascii_lowercase();
+    if units.starts_with("arc-second") || units.starts_with("arcsec") {
+        (1. / 3600_f64).to_radians()
+    } else if units.starts_with("degree") {
+        1_f64.to_radians()
+    } else if units.starts_with("millimet") || units.starts_with("mm") {
+        1e-3
+    } else {
+        1.
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::Invalid(format!("GeoTIFF: {}", message))
+}
+
+// ----- T I F F -----------------------------------------------------------------------
+
+struct Tiff<'a> {
+    bytes: &'a [u8],
+    /// The offset of `bytes` in the file
+    base: u64,
+    little: bool,
+    big: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(bytes: &'a [u8]) -> Result<Tiff<'a>> {
+        let little = match bytes.get(0..2) {
+            Some(b"II") => true,
+            Some(b"MM") => false,
+            _ => return Err(invalid("not a TIFF file")),
+        };
+        let mut tiff = Tiff {
+            bytes,
+            base: 0,
+            little,
+            big: false,
+        };
+        tiff.big = match tiff.uint(2, 2)? {
+            42 => false,
+            43 => true,
+            _ => return Err(invalid("not a TIFF file")),
+        };
+        Ok(tiff)
+    }
+
+    /// The same file, with `bytes` holding only the part from `base`
+    fn with_data<'b>(&self, bytes: &'b [u8], base: u64) -> Tiff<'b> {
+        Tiff {
+            bytes,
+            base,
+            little: self.little,
+            big: self.big,
+        }
+    }
+
+    fn slice(&self, offset: u64, length: u64) -> Result<&'a [u8]> {
+        let offset = offset.checked_sub(self.base);
+        let start = offset.and_then(|offset| usize::try_from(offset).ok());
+        let end = offset
+            .and_then(|offset| offset.checked_add(length))
+            .and_then(|end| usize::try_from(end).ok());
+        match (start, end) {
+            (Some(start), Some(end)) => self.bytes.get(start..end),
+            _ => None,
+        }
+        .ok_or_else(|| invalid("unexpected end of file"))
+    }
+
+    fn uint(&self, offset: u64, size: u64) -> Result<u64> {
+        Ok(read_uint(self.slice(offset, size)?, self.little))
+    }
+
+    fn first_ifd(&self) -> Result<u64> {
+        if self.big {
+            self.uint(8, 8)
+        } else {
+            self.uint(4, 4)
+        }
+    }
+
+    /// The directory at `offset` and the offset of the next one
+    fn ifd(&self, offset: u64) -> Result<(Ifd<'a>, u64)> {
+        let (count_size, entry_size, value_size) = if self.big { (8, 20, 8) } else { (2, 12, 4) };
+        let count = self.uint(offset, count_size)?;
+
+        let mut fields = BTreeMap::new();
+        for i in 0..count {
+            let entry = offset + count_size + i * entry_size;
+            let tag = self.uint(entry, 2)? as u16;
+            let kind = self.uint(entry + 2, 2)? as u16;
+            let count = self.uint(entry + 4, value_size)?;
+            let value = entry + 4 + value_size;
+
+            // Unknown field types are skipped as the spec requires
+            let Some(size) = type_size(kind) else {
+                continue;
+            };
+            let length = count
+                .checked_mul(size)
+                .ok_or_else(|| invalid("field too large"))?;
+            let data = if length <= value_size {
+                self.slice(value, length)?
+            } else {
+                self.slice(self.uint(value, value_size)?, length)?
+            };
+            fields.insert(tag, Field { kind, data });
+        }
+
+        let next = self.uint(offset + count_size + count * entry_size, value_size)?;
+        Ok((
+            Ifd {
+                fields,
+                little: self.little,
+            },
+            next,
+        ))
+    }
+}
+
+fn type_size(kind: u16) -> Option<u64> {
+    match kind {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 | 16 | 17 | 18 => Some(8),
+        _ => None,
+    }
+}
+
+fn read_uint(bytes: &[u8], little: bool) -> u64 {
+    let fold = |acc: u64, b: &u8| (acc << 8) | *b as u64;
+    if little {
+        bytes.iter().rev().fold(0, fold)
+    } else {
+        bytes.iter().fold(0, fold)
+    }
+}
+
+struct Field<'a> {
+    kind: u16,
+    data: &'a [u8],
+}
+
+struct Ifd<'a> {
+    fields: BTreeMap<u16, Field<'a>>,
+    little: bool,
+}
+
+impl Ifd<'_> {
+    /// All values of a numeric field
+    fn values(&self, tag: u16) -> Option<Vec<f64>> {
+        let field = self.fields.get(&tag)?;
+        let size = type_size(field.kind)? as usize;
+        let little = self.little;
+
+        let values = field.data.chunks_exact(size).map(|v| {
+            let bits = read_uint(v, little);
+            match field.kind {
+                6 => bits as i8 as f64,
+                8 => bits as i16 as f64,
+                9 => bits as i32 as f64,
+                17 => bits as i64 as f64,
+                11 => f32::from_bits(bits as u32) as f64,
+                12 => f64::from_bits(bits),
+                5 => read_uint(&v[..4], little) as f64 / read_uint(&v[4..], little) as f64,
+                10 => {
+                    read_uint(&v[..4], little) as i32 as f64
+                        / read_uint(&v[4..], little) as i32 as f64
+                }
+                _ => bits as f64,
+            }
+        });
+        Some(values.collect())
+    }
+
+    fn uints(&self, tag: u16) -> Option<Vec<u64>> {
+        Some(self.values(tag)?.into_iter().map(|v| v as u64).collect())
+    }
+
+    fn uint(&self, tag: u16) -> Option<u64> {
+        self.uints(tag)?.first().copied()
+    }
+
+    fn ascii(&self, tag: u16) -> Option<String> {
+        let field = self.fields.get(&tag).filter(|f| f.kind == 2)?;
+        let text = String::from_utf8_lossy(field.data);
+        Some(text.trim_end_matches('\0').to_string())
+    }
+
+    /// The GeoKeys with values stored directly in the directory
+    fn geokeys(&self) -> BTreeMap<u16, u64> {
+        let keys = self.uints(GEO_KEY_DIRECTORY).unwrap_or_default();
+        keys.get(4..)
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .filter(|key| key[1] == 0)
+            .map(|key| (key[0] as u16, key[3]))
+            .collect()
+    }
+}
+
+// ----- I M A G E ---------------------------------------------------------------------
+
+/// The samples of an image, interleaved per pixel
+struct Image {
+    width: usize,
+    height: usize,
+    samples_per_pixel: usize,
+    samples: Vec<f64>,
+}
+
+impl Image {
+    fn read(tiff: &Tiff, ifd: &Ifd) -> Result<Image> {
+        let chunks = Chunks::new(ifd, tiff.little)?;
+        let (rows, cols) = (0..chunks.height, 0..chunks.width);
+        chunks.read(&rows, &cols, |offset, count| tiff.slice(offset, count))
+    }
+}
+
+/// How the image data of a directory is split into strips or tiles
+struct Chunks {
+    width: usize,
+    height: usize,
+    spp: usize,
+    tiled: bool,
+    /// The nodes in each strip or tile
+    chunk_width: usize,
+    chunk_height: usize,
+    /// The number of strips or tiles across and down the image
+    across: usize,
+    down: usize,
+    planar: bool,
+    offsets: Vec<u64>,
+    counts: Vec<u64>,
+    compression: u64,
+    layout: SampleLayout,
+}
+
+impl Chunks {
+    fn new(ifd: &Ifd, little: bool) -> Result<Chunks> {
+        let width = ifd
+            .uint(IMAGE_WIDTH)
+            .ok_or_else(|| invalid("missing ImageWidth"))? as usize;
+        let height = ifd
+            .uint(IMAGE_LENGTH)
+            .ok_or_else(|| invalid("missing ImageLength"))? as usize;
+        let spp = ifd.uint(SAMPLES_PER_PIXEL).unwrap_or(1) as usize;
+        if width == 0 || height == 0 || spp == 0 {
+            return Err(invalid("empty image"));
+        }
+
+        let bits = ifd.uints(BITS_PER_SAMPLE).unwrap_or_else(|| vec![1]);
+        if bits.iter().any(|b| *b != bits[0]) {
+            return Err(invalid("bands with different bit depths are not supported"));
+        }
+        let bytes = match bits[0] {
+            8 | 16 | 32 | 64 => bits[0] as usize / 8,
+            b => return Err(invalid(&format!("{} bit samples are not supported", b))),
+        };
+        let format = ifd.uint(SAMPLE_FORMAT).unwrap_or(1);
+        match (format, bytes) {
+            (1 | 2, _) | (3, 4 | 8) => {}
+            _ => return Err(invalid("unsupported sample format")),
+        }
+        let compression = ifd.uint(COMPRESSION).unwrap_or(1);
+        let predictor = ifd.uint(PREDICTOR).unwrap_or(1);
+        let planar = ifd.uint(PLANAR_CONFIGURATION).unwrap_or(1) == 2;
+
+        let tiled = ifd.fields.contains_key(&TILE_OFFSETS);
+        let (chunk_width, chunk_height, offsets, counts) = if tiled {
+            (
+                ifd.uint(TILE_WIDTH).unwrap_or(0) as usize,
+                ifd.uint(TILE_LENGTH).unwrap_or(0) as usize,
+                ifd.uints(TILE_OFFSETS),
+                ifd.uints(TILE_BYTE_COUNTS),
+            )
+        } else {
+            (
+                width,
+                (ifd.uint(ROWS_PER_STRIP).unwrap_or(height as u64) as usize).min(height),
+                ifd.uints(STRIP_OFFSETS),
+                ifd.uints(STRIP_BYTE_COUNTS),
+            )
+        };
+        let (Some(offsets), Some(counts)) = (offsets, counts) else {
+            return Err(invalid("missing image data offsets"));
+        };
+        if chunk_width == 0 || chunk_height == 0 {
+            return Err(invalid("invalid tile size"));
+        }
+
+        let across = (width + chunk_width - 1) / chunk_width;
+        let down = (height + chunk_height - 1) / chunk_height;
+        let planes = if planar { spp } else { 1 };
+        if offsets.len() < across * down * planes || counts.len() < offsets.len() {
+            return Err(invalid("too few image data offsets"));
+        }
+
+        let layout = SampleLayout {
+            width: chunk_width,
+            spp: if planar { 1 } else { spp },
+            bytes,
+            format,
+            predictor,
+            little,
+        };
+        Ok(Chunks {
+            width,
+            height,
+            spp,
+            tiled,
+            chunk_width,
+            chunk_height,
+            across,
+            down,
+            planar,
+            offsets,
+            counts,
+            compression,
+            layout,
+        })
+    }
+
+    /// The strips or tiles holding nodes in `rows` and `cols`, as `(index, plane, chunk row, chunk column)`
+    fn covering(
+        &self,
+        rows: &Range<usize>,
+        cols: &Range<usize>,
+    ) -> impl Iterator<Item = (usize, usize, usize, usize)> + '_ {
+        let planes = if self.planar { self.spp } else { 1 };
+        let chunk_rows = rows.start / self.chunk_height..(rows.end - 1) / self.chunk_height + 1;
+        let chunk_cols = cols.start / self.chunk_width..(cols.end - 1) / self.chunk_width + 1;
+        (0..planes).flat_map(move |plane| {
+            let chunk_cols = chunk_cols.clone();
+            chunk_rows.clone().flat_map(move |chunk_row| {
+                chunk_cols.clone().map(move |chunk_col| {
+                    let index = (plane * self.down + chunk_row) * self.across + chunk_col;
+                    (index, plane, chunk_row, chunk_col)
+                })
+            })
+        })
+    }
+
+    /// The samples of the nodes in `rows` and `cols`, with `data` giving the bytes at an offset
+    fn read<'b>(
+        &self,
+        rows: &Range<usize>,
+        cols: &Range<usize>,
+        data: impl Fn(u64, u64) -> Result<&'b [u8]>,
+    ) -> Result<Image> {
+        if rows.is_empty() || cols.is_empty() || rows.end > self.height || cols.end > self.width {
+            return Err(invalid("the window is outside the image"));
+        }
+        let (width, spp) = (cols.len(), self.spp);
+        let chunk_spp = self.layout.spp;
+
+        let mut samples = vec![0_f64; width * rows.len() * spp];
+        for (index, plane, chunk_row, chunk_col) in self.covering(rows, cols) {
+            // Strips at the bottom of the image may be shorter, tiles are always padded
+            let chunk_rows = if self.tiled {
+                self.chunk_height
+            } else {
+                self.chunk_height
+                    .min(self.height - chunk_row * self.chunk_height)
+            };
+
+            let raw = data(self.offsets[index], self.counts[index])?;
+            let expected = self.chunk_width * chunk_rows * chunk_spp * self.layout.bytes;
+            let mut bytes = decompress(raw, self.compression, expected)?;
+            if bytes.len() < expected {
+                return Err(invalid("image data is truncated"));
+            }
+            let chunk = self.layout.decode(&mut bytes[..expected], chunk_rows)?;
+
+            for r in 0..chunk_rows {
+                let row = chunk_row * self.chunk_height + r;
+                if !rows.contains(&row) {
+                    continue;
+                }
+                for c in 0..self.chunk_width {
+                    let col = chunk_col * self.chunk_width + c;
+                    if !cols.contains(&col) {
+                        continue;
+                    }
+                    for s in 0..chunk_spp {
+                        let band = if self.planar { plane } else { s };
+                        let node = (row - rows.start) * width + col - cols.start;
+                        samples[node * spp + band] =
+                            chunk[(r * self.chunk_width + c) * chunk_spp + s];
+                    }
+                }
+            }
+        }
+
+        Ok(Image {
+            width,
+            height: rows.len(),
+            samples_per_pixel: spp,
+            samples,
+        })
+    }
+}
+
+fn decompress(raw: &[u8], compression: u64, expected: usize) -> Result<Vec<u8>> {
+    match compression {
+        1 => Ok(raw.to_vec()),
+        5 => lzw_decode(raw, expected),
+        8 | 32946 => miniz_oxide::inflate::decompress_to_vec_zlib(raw)
+            .map_err(|e| invalid(&format!("invalid Deflate data: {}", e))),
+        c => Err(invalid(&format!("compression {} is not supported", c))),
+    }
+}
+
+/// How samples are stored in a decompressed strip or tile
+struct SampleLayout {
+    width: usize,
+    spp: usize,
+    bytes: usize,
+    format: u64,
+    predictor: u64,
+    little: bool,
+}
+
+impl SampleLayout {
+    fn decode(&self, data: &mut [u8], rows: usize) -> Result<Vec<f64>> {
+        let row_length = self.width * self.spp * self.bytes;
+        let mut samples = Vec::with_capacity(self.width * self.spp * rows);
+
+        for row in data.chunks_exact_mut(row_length).take(rows) {
+            match self.predictor {
+                1 => samples.extend(
+                    row.chunks_exact(self.bytes)
+                        .map(|s| self.convert(read_uint(s, self.little))),
+                ),
+                // Horizontal differencing of whole samples
+                2 => {
+                    let mask = u64::MAX >> (64 - 8 * self.bytes);
+                    let mut values: Vec<u64> = row
+                        .chunks_exact(self.bytes)
+                        .map(|s| read_uint(s, self.little))
+                        .collect();
+                    for i in self.spp..values.len() {
+                        values[i] = values[i].wrapping_add(values[i - self.spp]) & mask;
+                    }
+                    samples.extend(values.into_iter().map(|v| self.convert(v)));
+                }
+                // Floating point: differenced bytes with the samples split into big endian byte planes
+                3 => {
+                    for i in self.spp..row.len() {
+                        row[i] = row[i].wrapping_add(row[i - self.spp]);
+                    }
+                    let count = self.width * self.spp;
+                    samples.extend((0..count).map(|k| {
+                        let bits = (0..self.bytes)
+                            .fold(0_u64, |acc, b| (acc << 8) | row[b * count + k] as u64);
+                        self.convert(bits)
+                    }));
+                }
+                p => return Err(invalid(&format!("predictor {} is not supported", p))),
+            }
+        }
+        Ok(samples)
+    }
+
+    fn convert(&self, bits: u64) -> f64 {
+        match (self.format, self.bytes) {
+            (3, 4) => f32::from_bits(bits as u32) as f64,
+            (3, _) => f64::from_bits(bits),
+            (2, _) => {
+                let shift = 64 - 8 * self.bytes as u32;
+                ((bits << shift) as i64 >> shift) as f64
+            }
+            _ => bits as f64,
+        }
+    }
+}
+
+// TIFF flavoured LZW: MSB first codes of 9 to 12 bits, widened one code early
+fn lzw_decode(input: &[u8], expected: usize) -> Result<Vec<u8>> {
+    const CLEAR: usize = 256;
+    const END: usize = 257;
+
+    let mut output = Vec::with_capacity(expected);
+    // Each table entry is the code of its prefix, its last byte and its length
+    let mut prefix: Vec<u16> = (0..258).map(|_| 0).collect();
+    let mut suffix: Vec<u8> = (0..258).map(|c| c as u8).collect();
+    let mut length: Vec<u32> = (0..258).map(|c| u32::from(c < 256)).collect();
+
+    let mut width = 9;
+    let mut buffer = 0_u32;
+    let mut buffered = 0;
+    let mut bytes = input.iter();
+    let mut previous: Option<usize> = None;
+
+    loop {
+        while buffered < width {
+            let Some(byte) = bytes.next() else {
+                return Ok(output);
+            };
+            buffer = (buffer << 8) | *byte as u32;
+            buffered += 8;
+        }
+        let code = ((buffer >> (buffered - width)) & ((1 << width) - 1)) as usize;
+        buffered -= width;
+
+        if code == CLEAR {
+            prefix.truncate(258);
+            suffix.truncate(258);
+            length.truncate(258);
+            width = 9;
+            previous = None;
+            continue;
+        }
+        if code == END {
+            return Ok(output);
+        }
+
+        let Some(prev) = previous else {
+            if code >= CLEAR {
+                return Err(invalid("invalid LZW data"));
+            }
+            output.push(code as u8);
+            previous = Some(code);
+            continue;
+        };
+
+        let start = output.len();
+        let entry = match code.cmp(&suffix.len()) {
+            std::cmp::Ordering::Less => code,
+            std::cmp::Ordering::Equal => prev,
+            std::cmp::Ordering::Greater => return Err(invalid("invalid LZW data")),
+        };
+        let entry_length = length[entry] as usize;
+        output.resize(start + entry_length, 0);
+        let mut node = entry;
+        for position in (start..start + entry_length).rev() {
+            output[position] = suffix[node];
+            node = prefix[node] as usize;
+        }
+        let first = output[start];
+        if code == suffix.len() {
+            output.push(first);
+        }
+
+        if suffix.len() < 4096 {
+            prefix.push(prev as u16);
+            suffix.push(first);
+            length.push(length[prev] + 1);
+        }
+        if suffix.len() + 1 >= 1 << width && width < 12 {
+            width += 1;
+        }
+        previous = Some(code);
+    }
+}
+
+// ----- M E T A D A T A ---------------------------------------------------------------
+
+/// The `<Item>`s of GDAL's XML metadata
+#[derive(Debug, Default, Clone)]
+struct Metadata {
+    items: Vec<MetadataItem>,
+}
+
+#[derive(Debug, Clone)]
+struct MetadataItem {
+    name: String,
+    sample: Option<usize>,
+    role: Option<String>,
+    value: String,
+}
+
+impl Metadata {
+    fn parse(xml: &str) -> Metadata {
+        let mut items = Vec::new();
+        let mut rest = xml;
+        while let Some(start) = rest.find("<Item") {
+            rest = &rest[start + 5..];
+            let Some(tag_end) = rest.find('>') else {
+                break;
+            };
+            let attributes = &rest[..tag_end];
+            rest = &rest[tag_end + 1..];
+            // Self closing items have no value
+            let value = if attributes.ends_with('/') {
+                ""
+            } else {
+                let end = rest.find("</Item>").unwrap_or(rest.len());
+                let value = &rest[..end];
+                rest = &rest[end..];
+                value
+            };
+
+            let attributes = parse_attributes(attributes);
+            let Some(name) = attributes.get("name") else {
+                continue;
+            };
+            items.push(MetadataItem {
+                name: name.clone(),
+                sample: attributes.get("sample").and_then(|s| s.parse().ok()),
+                role: attributes.get("role").cloned(),
+                value: unescape(value.trim()),
+            });
+        }
+        Metadata { items }
+    }
+
+    /// A dataset level item
+    fn get(&self, name: &str) -> Option<&str> {
+        self.items
+            .iter()
+            .find(|item| item.sample.is_none() && item.name.eq_ignore_ascii_case(name))
+            .map(|item| item.value.as_str())
+    }
+
+    /// A band level item, by name or role
+    fn band(&self, name: &str, band: usize) -> Option<&str> {
+        self.items
+            .iter()
+            .find(|item| {
+                item.sample == Some(band)
+                    && (item.name.eq_ignore_ascii_case(name)
+                        || item
+                            .role
+                            .as_deref()
+                            .is_some_and(|r| r.eq_ignore_ascii_case(name)))
+            })
+            .map(|item| item.value.as_str())
+    }
+
+    fn band_real(&self, name: &str, band: usize) -> Option<f64> {
+        self.band(name, band)?.parse().ok()
+    }
+
+    /// Take the items of `other` not set here, except for the subgrid names
+    fn inherit(&mut self, other: &Metadata) {
+        for item in &other.items {
+            let named = |i: &MetadataItem| i.name.eq_ignore_ascii_case(&item.name);
+            if item.name.ends_with("grid_name")
+                || self
+                    .items
+                    .iter()
+                    .any(|i| named(i) && i.sample == item.sample)
+            {
+                continue;
+            }
+            self.items.push(item.clone());
+        }
+    }
+}
+
+fn parse_attributes(text: &str) -> BTreeMap<String, String> {
+    let mut attributes = BTreeMap::new();
+    let mut rest = text;
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq]
+            .split_whitespace()
+            .last()
+            .unwrap_or("")
+            .to_string();
+        let after = rest[eq + 1..].trim_start();
+        let Some(quote) = after.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            break;
+        };
+        let Some(end) = after[1..].find(quote) else {
+            break;
+        };
+        attributes.insert(key, unescape(&after[1..1 + end]));
+        rest = &after[end + 2..];
+    }
+    attributes
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use float_eq::assert_float_eq;
+    use geodesy_rs::authoring::{Coor4D, Grid};
+
+    const ARCSEC: f64 = 4.848_136_811_095_36e-6;
+
+    // A classic little endian TIFF with the image in a single strip right after the header
+    fn tiff(tags: Vec<(u16, u16, Vec<u8>)>, image: &[u8]) -> Vec<u8> {
+        chunked(tags, &[image.to_vec()], (STRIP_OFFSETS, STRIP_BYTE_COUNTS))
+    }
+
+    // The same with the image in strips or tiles, following each other after the header
+    fn chunked(
+        mut tags: Vec<(u16, u16, Vec<u8>)>,
+        chunks: &[Vec<u8>],
+        (offsets_tag, counts_tag): (u16, u16),
+    ) -> Vec<u8> {
+        let (mut offsets, mut counts) = (Vec::new(), Vec::new());
+        let mut ifd = 8_u32;
+        for chunk in chunks {
+            offsets.extend(ifd.to_le_bytes());
+            counts.extend((chunk.len() as u32).to_le_bytes());
+            ifd += chunk.len() as u32;
+        }
+        tags.push((offsets_tag, 4, offsets));
+        tags.push((counts_tag, 4, counts));
+        tags.sort_by_key(|t| t.0);
+
+        let mut file = b"II*\0".to_vec();
+        file.extend(ifd.to_le_bytes());
+        file.extend(chunks.concat());
+
+        let mut values = Vec::<u8>::new();
+        let values_at = ifd + 2 + 12 * tags.len() as u32 + 4;
+        file.extend((tags.len() as u16).to_le_bytes());
+        for (tag, kind, data) in &tags {
+            file.extend(tag.to_le_bytes());
+            file.extend(kind.to_le_bytes());
+            file.extend((data.len() as u32 / type_size(*kind).unwrap() as u32).to_le_bytes());
+            if data.len() <= 4 {
+                let mut inline = data.clone();
+                inline.resize(4, 0);
+                file.extend(inline);
+            } else {
+                file.extend((values_at + values.len() as u32).to_le_bytes());
+                values.extend(data);
+            }
+        }
+        file.extend(0_u32.to_le_bytes());
+        file.extend(values);
+        file
+    }
+
+    /// A geoid of `rows` x `cols` nodes 0.1 degree apart from 52N 1W, stored in square tiles
+    /// of `tile` nodes. The height of each node is its index in metres.
+    pub(crate) fn tiled_geoid(rows: usize, cols: usize, tile: usize) -> Vec<u8> {
+        let tags = vec![
+            (IMAGE_WIDTH, 3, shorts(&[cols as u16])),
+            (IMAGE_LENGTH, 3, shorts(&[rows as u16])),
+            (BITS_PER_SAMPLE, 3, shorts(&[32])),
+            (COMPRESSION, 3, shorts(&[1])),
+            (SAMPLES_PER_PIXEL, 3, shorts(&[1])),
+            (TILE_WIDTH, 3, shorts(&[tile as u16])),
+            (TILE_LENGTH, 3, shorts(&[tile as u16])),
+            (SAMPLE_FORMAT, 3, shorts(&[3])),
+            (MODEL_PIXEL_SCALE, 12, doubles(&[0.1, 0.1, 0.])),
+            (MODEL_TIEPOINT, 12, doubles(&[0., 0., 0., -1., 52., 0.])),
+            (
+                GEO_KEY_DIRECTORY,
+                3,
+                shorts(&[1, 1, 0, 2, 1024, 0, 1, 2, 1025, 0, 1, 2]),
+            ),
+        ];
+
+        let mut tiles = Vec::new();
+        for tile_row in 0..(rows + tile - 1) / tile {
+            for tile_col in 0..(cols + tile - 1) / tile {
+                let mut bytes = Vec::with_capacity(tile * tile * 4);
+                for r in 0..tile {
+                    for c in 0..tile {
+                        let (row, col) = (tile_row * tile + r, tile_col * tile + c);
+                        // Tiles are padded past the edges of the image
+                        let height = if row < rows && col < cols {
+                            (row * cols + col) as f32
+                        } else {
+                            0.
+                        };
+                        bytes.extend(height.to_le_bytes());
+                    }
+                }
+                tiles.push(bytes);
+            }
+        }
+        chunked(tags, &tiles, (TILE_OFFSETS, TILE_BYTE_COUNTS))
+    }
+
+    fn shorts(values: &[u16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn ascii(text: &str) -> Vec<u8> {
+        format!("{}\0", text).into_bytes()
+    }
+
+    // A 3x2 grid of 2 float bands over 50N..49N, 1W..1E with PixelIsPoint tiepoints
+    fn tags(compression: u16, predictor: u16, metadata: &str) -> Vec<(u16, u16, Vec<u8>)> {
+        vec![
+            (IMAGE_WIDTH, 3, shorts(&[3])),
+            (IMAGE_LENGTH, 3, shorts(&[2])),
+            (BITS_PER_SAMPLE, 3, shorts(&[32, 32])),
+            (COMPRESSION, 3, shorts(&[compression])),
+            (SAMPLES_PER_PIXEL, 3, shorts(&[2])),
+            (PREDICTOR, 3, shorts(&[predictor])),
+            (SAMPLE_FORMAT, 3, shorts(&[3, 3])),
+            (MODEL_PIXEL_SCALE, 12, doubles(&[1., 1., 0.])),
+            (MODEL_TIEPOINT, 12, doubles(&[0., 0., 0., -1., 50., 0.])),
+            (
+                GEO_KEY_DIRECTORY,
+                3,
+                shorts(&[1, 1, 0, 2, 1024, 0, 1, 2, 1025, 0, 1, 2]),
+            ),
+            (GDAL_METADATA, 2, ascii(metadata)),
+        ]
+    }
+
+    const HORIZONTAL: &str = r#"<GDALMetadata>
+        <Item name="TYPE">HORIZONTAL_OFFSET</Item>
+        <Item name="DESCRIPTION" sample="0" role="description">latitude_offset</Item>
+        <Item name="DESCRIPTION" sample="1" role="description">longitude_offset</Item>
+        <Item name="UNITTYPE" sample="0" role="unittype">arc-second</Item>
+        <Item name="UNITTYPE" sample="1" role="unittype">arc-second</Item>
+        <Item name="positive_value" sample="1">west</Item>
+    </GDALMetadata>"#;
+
+    // (latitude offset, longitude offset) per node, in arc seconds
+    const NODES: [f32; 12] = [1., 2., 1., 2., 1., 2., 3., 4., 3., 4., 3., 4.];
+
+    #[test]
+    fn horizontal_offsets() -> Result<()> {
+        let image: Vec<u8> = NODES.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let set = read(&tiff(tags(1, 1, HORIZONTAL), &image))?;
+        assert_eq!(set.grids().len(), 1);
+        assert_eq!(set.bands(), 2);
+
+        assert!(set.contains(&Coor4D::gis(1., 50., 0., 0.), 0., false));
+        assert!(!set.contains(&Coor4D::gis(1.1, 50., 0., 0.), 0., false));
+
+        let d = set.at(None, &Coor4D::gis(0., 49.5, 0., 0.), 0.).unwrap();
+        assert_float_eq!(d[0], 2. * ARCSEC, abs <= 1e-12);
+        // Positive west is flipped to positive east
+        assert_float_eq!(d[1], -3. * ARCSEC, abs <= 1e-12);
+        assert!(set.at(None, &Coor4D::gis(2., 49.5, 0., 0.), 0.).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn deflate_and_floating_point_predictor() -> Result<()> {
+        // Split each row into big endian byte planes and difference the bytes
+        let mut image = Vec::new();
+        for row in NODES.chunks(6) {
+            let mut planes: Vec<u8> = (0..4)
+                .flat_map(|b| row.iter().map(move |v| v.to_be_bytes()[b]))
+                .collect();
+            for i in (2..planes.len()).rev() {
+                planes[i] = planes[i].wrapping_sub(planes[i - 2]);
+            }
+            image.extend(planes);
+        }
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&image, 6);
+        let set = read(&tiff(tags(8, 3, HORIZONTAL), &compressed))?;
+
+        let d = set.at(None, &Coor4D::gis(-1., 50., 0., 0.), 0.).unwrap();
+        assert_float_eq!(d[0], ARCSEC, abs <= 1e-12);
+        assert_float_eq!(d[1], -2. * ARCSEC, abs <= 1e-12);
+        Ok(())
+    }
+
+    #[test]
+    fn tiles() -> Result<()> {
+        // 10 x 12 nodes in 4 x 4 tiles, padded on the bottom and right
+        let file = tiled_geoid(10, 12, 4);
+        let set = read(&file)?;
+        let grid = &set.grids()[0];
+        assert_eq!(grid.values()[12 * 9 + 11], 119.);
+
+        // A window of tile (1, 1) and the left of tile (1, 2)
+        let directory = &directories(&file)?[0];
+        let (rows, cols) = (5..7, 6..10);
+        let data = window_data(&file, directory, &rows, &cols)?;
+        assert_eq!(data.len(), 2);
+        let parts: Vec<_> = data
+            .iter()
+            .map(|r| (r.start, file[r.start as usize..r.end as usize].to_vec()))
+            .collect();
+        let window = read_window(&file, directory, &rows, &cols, &parts)?;
+        assert_eq!(window.values(), [66., 67., 68., 69., 78., 79., 80., 81.]);
+        assert_eq!(*window.header(), grid.header().part(&rows, &cols));
+        assert!(read_window(&file, directory, &rows, &(6..13), &parts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn lzw() -> Result<()> {
+        // Clear, 7, 258 (7 7), 7, End as 9 bit codes
+        let codes = [256_u32, 7, 258, 7, 257];
+        let mut bytes = Vec::new();
+        let (mut buffer, mut bits) = (0_u64, 0);
+        for code in codes {
+            buffer = (buffer << 9) | code as u64;
+            bits += 9;
+            while bits >= 8 {
+                bytes.push((buffer >> (bits - 8)) as u8);
+                bits -= 8;
+            }
+        }
+        bytes.push((buffer << (8 - bits)) as u8);
+
+        assert_eq!(lzw_decode(&bytes, 4)?, vec![7, 7, 7, 7]);
+        Ok(())
+    }
+
+    #[test]
+    fn metadata() {
+        let metadata = Metadata::parse(
+            r#"<GDALMetadata><Item name="grid_name">A &amp; B</Item><Item name="SCALE" sample="0" role="scale">0.5</Item></GDALMetadata>"#,
+        );
+        assert_eq!(metadata.get("grid_name"), Some("A & B"));
+        assert_eq!(metadata.band_real("scale", 0), Some(0.5));
+        assert_eq!(metadata.band("scale", 1), None);
+
+        assert!(read(b"not a tiff").is_err());
+    }
+
+    #[test]
+    fn write_subgrids() -> Result<()> {
+        use crate::geodesy::grids::ntv2;
+        let set = ntv2::read(&ntv2::tests::file(&[
+            ("PARENT", "NONE", [180_000., 183_600., -3600., 3600.], 3600.),
+            ("A & B", "PARENT", [180_000., 181_800., -1800., 0.], 1800.),
+        ]))?;
+
+        let written = read(&write(&set)?)?;
+        assert_eq!(written.parents(), &[None, Some(0)]);
+        assert_eq!(written.names()[1].as_deref(), Some("A & B"));
+        for (lon, lat) in [(0.25, 50.25), (0.75, 50.9), (-0.5, 50.5)] {
+            let position = Coor4D::gis(lon, lat, 0., 0.);
+            let expected = set.at(None, &position, 0.).unwrap();
+            let d = written.at(None, &position, 0.).unwrap();
+            assert_float_eq!(d[0], expected[0], abs <= 1e-10);
+            assert_float_eq!(d[1], expected[1], abs <= 1e-10);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn write_geoid() -> Result<()> {
+        let header = GridHeader {
+            lat_n: 55_f64.to_radians(),
+            lon_w: 8_f64.to_radians(),
+            dlat: 1_f64.to_radians(),
+            dlon: 1_f64.to_radians(),
+            rows: 2,
+            cols: 3,
+            bands: 1,
+        };
+        let values = vec![40., 41., 42., 30., 31., f32::NAN];
+        let set = GridSet::single(RasterGrid::new(header, values)?);
+
+        let written = read(&write(&set)?)?;
+        assert_eq!(written.bands(), 1);
+        assert_eq!(written.grids()[0].header(), &header);
+        let d = written
+            .at(None, &Coor4D::gis(8.5, 54.5, 0., 0.), 0.)
+            .unwrap();
+        assert_float_eq!(d[0], 35.5, abs <= 1e-6);
+        // Null nodes stay null
+        assert!(written
+            .at(None, &Coor4D::gis(9.5, 54.5, 0., 0.), 0.)
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn write_velocities() -> Result<()> {
+        let header = GridHeader {
+            lat_n: 56_f64.to_radians(),
+            lon_w: 8_f64.to_radians(),
+            dlat: 1_f64.to_radians(),
+            dlon: 2_f64.to_radians(),
+            rows: 2,
+            cols: 2,
+            bands: 3,
+        };
+        // East, north and up velocities in metres per year
+        let values = [0.001, 0.002, 0.003].repeat(4);
+        let set = GridSet::single(RasterGrid::new(header, values.clone())?);
+
+        let written = write(&set)?;
+        let xml = String::from_utf8_lossy(&written);
+        assert!(xml.contains(">VELOCITY<") && xml.contains(">up_velocity<"));
+        let read_back = read(&written)?;
+        assert_eq!(read_back.bands(), 3);
+        assert_eq!(read_back.grids()[0].values(), values.as_slice());
+
+        // Millimetres per year, the unit of the PROJ CDN deformation models
+        assert_float_eq!(unit_factor("millimetres per year"), 1e-3, abs <= 1e-15);
+        Ok(())
+    }
+}
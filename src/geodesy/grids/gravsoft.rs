@@ -0,0 +1,114 @@
+//! Gravsoft text grids.
+//!
+//! The header is the first six numbers: `lat_s lat_n lon_w lon_e dlat dlon` in degrees,
+//! followed by the nodes from north to south, west to east. Two band grids hold latitude
+//! and longitude offsets in arc seconds, three band grids east, north and up velocities
+//! of deformation models in millimetres per year. Others are kept in their own units
+//! (metres for geoids).
+//! Anything following a `#` is a comment.
+use super::raster::{GridHeader, GridSet, RasterGrid};
+use crate::error::{Error, Result};
+
+pub fn read(bytes: &[u8]) -> Result<GridSet> {
+    let text = std::str::from_utf8(bytes)
+        .map_err(|_| Error::Invalid("Gravsoft: grids must be UTF-8 text".to_string()))?;
+
+    let mut numbers = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(str::split_whitespace)
+        .map(|token| {
+            token
+                .parse::<f64>()
+                .map_err(|_| Error::Invalid(format!("Gravsoft: `{}` is not a number", token)))
+        });
+
+    let mut header = [0_f64; 6];
+    for value in header.iter_mut() {
+        *value = numbers
+            .next()
+            .ok_or_else(|| Error::Invalid("Gravsoft: incomplete header".to_string()))??;
+    }
+    let [lat_s, lat_n, lon_w, lon_e, dlat, dlon] = header;
+    if !(dlat > 0. && dlon > 0. && lat_n >= lat_s && lon_e >= lon_w) {
+        return Err(Error::Invalid(format!(
+            "Gravsoft: inconsistent header {:?}",
+            header
+        )));
+    }
+    let mut values = numbers
+        .map(|v| v.map(|v| v as f32))
+        .collect::<Result<Vec<f32>>>()?;
+
+    // Bounded by the number of values first, so the counts can not overflow
+    let nodes = |span: f64, step: f64| {
+        let steps = (span / step).round();
+        (steps < values.len() as f64).then(|| steps as usize + 1)
+    };
+    let (rows, cols) = match (nodes(lat_n - lat_s, dlat), nodes(lon_e - lon_w, dlon)) {
+        (Some(rows), Some(cols)) => (rows, cols),
+        _ => {
+            return Err(Error::Invalid(format!(
+                "Gravsoft: {} values do not fill the grid of header {:?}",
+                values.len(),
+                header
+            )))
+        }
+    };
+    let bands = rows
+        .checked_mul(cols)
+        .map_or(0, |nodes| values.len() / nodes);
+    if bands == 0 || bands * rows * cols != values.len() {
+        return Err(Error::Invalid(format!(
+            "Gravsoft: {} values do not fill a {} x {} grid",
+            values.len(),
+            rows,
+            cols
+        )));
+    }
+    let unit = match bands {
+        2 => (1. / 3600_f64).to_radians(),
+        3 => 1e-3,
+        _ => 1.,
+    };
+    if unit != 1. {
+        for v in values.iter_mut() {
+            *v = (*v as f64 * unit) as f32;
+        }
+    }
+
+    let header = GridHeader {
+        lat_n: lat_n.to_radians(),
+        lon_w: lon_w.to_radians(),
+        dlat: dlat.to_radians(),
+        dlon: dlon.to_radians(),
+        rows,
+        cols,
+        bands,
+    };
+    Ok(GridSet::single(RasterGrid::new(header, values)?))
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_eq::assert_float_eq;
+    use geodesy_rs::authoring::{Coor4D, Grid};
+
+    #[test]
+    fn geoid() -> Result<()> {
+        let grid = read(b"# A geoid\n 54 55 8 10 1 1\n 40 41 42\n 30 31 32 # south\n")?;
+        assert_eq!(grid.bands(), 1);
+
+        let at = |lon: f64, lat: f64| grid.at(None, &Coor4D::gis(lon, lat, 0., 0.), 0.);
+        assert_float_eq!(at(8., 55.).unwrap()[0], 40., abs <= 1e-9);
+        assert_float_eq!(at(9.5, 54.5).unwrap()[0], 36.5, abs <= 1e-6);
+
+        assert!(read(b"54 55 8 10 1 1\n 40 41 42\n 30 31").is_err());
+        assert!(read(b"54 55 8 10").is_err());
+        assert!(read(b"0 1e300 0 1e300 1e-300 1e-300\n 1 2 3 4").is_err());
+        Ok(())
+    }
+}
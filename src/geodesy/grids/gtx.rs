@@ -0,0 +1,125 @@
+//! GTX vertical offset and geoid grids.
+//!
+//! A 40 byte big endian header `lat_s lon_w dlat dlon` (`f64` degrees) and `rows cols` (`i32`),
+//! followed by `f32` values in metres from the south west corner, eastwards then northwards.
+//! Nodes of `-88.8888` are null.
+use super::{
+    binary::Binary,
+    raster::{GridHeader, GridSet, RasterGrid},
+};
+use crate::error::{Error, Result};
+
+const HEADER: usize = 40;
+const NULL: f32 = -88.8888;
+
+pub fn read(bytes: &[u8]) -> Result<GridSet> {
+    let file = Binary::new(bytes, false, "GTX");
+    let (lat_s, mut lon_w) = (file.f64(0)?, file.f64(8)?);
+    let (dlat, dlon) = (file.f64(16)?, file.f64(24)?);
+    let (rows, cols) = (file.i32(32)?, file.i32(36)?);
+    if rows <= 0 || cols <= 0 {
+        return Err(file.invalid("the grid has no nodes"));
+    }
+    let (rows, cols) = (rows as usize, cols as usize);
+    // Some global grids run from 0 to 360
+    if lon_w >= 180. {
+        lon_w -= 360.;
+    }
+
+    let nodes = file.nodes(HEADER, rows, cols, 4)?;
+    let mut values = vec![0_f32; nodes];
+    for i in 0..nodes {
+        let value = file.f32(HEADER + 4 * i)?;
+        let row = rows - 1 - i / cols;
+        values[row * cols + i % cols] = if value == NULL { f32::NAN } else { value };
+    }
+
+    let header = GridHeader {
+        lat_n: (lat_s + dlat * (rows - 1) as f64).to_radians(),
+        lon_w: lon_w.to_radians(),
+        dlat: dlat.to_radians(),
+        dlon: dlon.to_radians(),
+        rows,
+        cols,
+        bands: 1,
+    };
+    Ok(GridSet::single(RasterGrid::new(header, values)?))
+}
+
+/// Write a single band grid without subgrids as a GTX file
+pub fn write(grid: &GridSet) -> Result<Vec<u8>> {
+    let [subgrid] = grid.grids() else {
+        return Err(Error::Invalid(format!(
+            "GTX: grids with {} subgrids can not be written",
+            grid.grids().len()
+        )));
+    };
+    let h = subgrid.header();
+    if h.bands != 1 {
+        return Err(Error::Invalid(format!(
+            "GTX: only single band grids can be written, not grids of {} bands",
+            h.bands
+        )));
+    }
+
+    let mut file = Vec::with_capacity(HEADER + 4 * h.rows * h.cols);
+    for v in [h.lat_s(), h.lon_w, h.dlat, h.dlon] {
+        file.extend(v.to_degrees().to_be_bytes());
+    }
+    file.extend((h.rows as i32).to_be_bytes());
+    file.extend((h.cols as i32).to_be_bytes());
+    // The south row first
+    for row in subgrid.nodes().chunks(h.cols).rev() {
+        for value in row {
+            let value = if value.is_nan() { NULL } else { *value };
+            file.extend(value.to_be_bytes());
+        }
+    }
+    Ok(file)
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_eq::assert_float_eq;
+    use geodesy_rs::authoring::{Coor4D, Grid};
+
+    #[test]
+    fn geoid() -> Result<()> {
+        // 2x3 nodes from 54N..55N, 8E..10E, the south row first
+        let mut gtx = Vec::new();
+        for v in [54., 8., 1., 1.] {
+            gtx.extend(f64::to_be_bytes(v));
+        }
+        gtx.extend(2_i32.to_be_bytes());
+        gtx.extend(3_i32.to_be_bytes());
+        for v in [30., 31., NULL, 40., 41., 42.] {
+            gtx.extend(f32::to_be_bytes(v));
+        }
+        let grid = read(&gtx)?;
+        assert_eq!(grid.bands(), 1);
+
+        let at = |lon: f64, lat: f64| grid.at(None, &Coor4D::gis(lon, lat, 0., 0.), 0.);
+        assert_float_eq!(at(8., 55.).unwrap()[0], 40., abs <= 1e-9);
+        assert_float_eq!(at(8.5, 54.5).unwrap()[0], 35.5, abs <= 1e-6);
+        // The cell with the null node
+        assert!(at(9.5, 54.5).is_none());
+
+        assert!(read(&gtx[..gtx.len() - 1]).is_err());
+        // Headers promising more nodes than the file holds fail before allocating them
+        let mut bogus = gtx.clone();
+        bogus[32..40].copy_from_slice(&[0x7f, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff]);
+        assert!(read(&bogus).unwrap_err().to_string().contains("too short"));
+
+        // Written back up to rounding of the header
+        let written = write(&grid)?;
+        assert_eq!(written.len(), gtx.len());
+        assert_eq!(written[HEADER..], gtx[HEADER..]);
+        let grid = read(&written)?;
+        let d = grid.at(None, &Coor4D::gis(8.5, 54.5, 0., 0.), 0.).unwrap();
+        assert_float_eq!(d[0], 35.5, abs <= 1e-6);
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+//! Descriptions of registered grids for JS.
+use super::{
+    format::GridFormat,
+    raster::{GridHeader, RasterGrid},
+    GridData,
+};
+use geodesy_rs::authoring::Grid;
+use wasm_bindgen::prelude::*;
+
+/// A description of a registered grid.
+/// Extents are `[west, south, east, north]` of the node centres in radians.
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct GridInfo {
+    key: String,
+    format: GridFormat,
+    bands: usize,
+    byte_size: usize,
+    extent: [f64; 4],
+    subgrids: Vec<SubgridInfo>,
+}
+
+/// A single subgrid of a [GridInfo]
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct SubgridInfo {
+    name: Option<String>,
+    parent: Option<usize>,
+    extent: [f64; 4],
+    resolution: [f64; 2],
+    rows: usize,
+    cols: usize,
+}
+
+impl GridInfo {
+    pub(crate) fn new(key: &str, format: GridFormat, grid: &GridData) -> GridInfo {
+        // Lazily loaded grids are described from their headers alone
+        let (headers, parents, names, bands): (Vec<&GridHeader>, _, _, _) = match grid {
+            GridData::Loaded(set) => (
+                set.grids().iter().map(RasterGrid::header).collect(),
+                set.parents(),
+                set.names(),
+                set.bands(),
+            ),
+            GridData::Lazy(lazy) => (
+                lazy.headers().iter().collect(),
+                lazy.parents(),
+                lazy.names(),
+                lazy.bands(),
+            ),
+        };
+        let subgrids: Vec<SubgridInfo> = headers
+            .iter()
+            .zip(parents)
+            .zip(names)
+            .map(|((h, parent), name)| SubgridInfo {
+                name: name.clone(),
+                parent: *parent,
+                extent: [h.lon_w, h.lat_s(), h.lon_e(), h.lat_n],
+                resolution: [h.dlon, h.dlat],
+                rows: h.rows,
+                cols: h.cols,
+            })
+            .collect();
+
+        let extent = subgrids.iter().filter(|s| s.parent.is_none()).fold(
+            [
+                f64::INFINITY,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                f64::NEG_INFINITY,
+            ],
+            |e, s| {
+                [
+                    e[0].min(s.extent[0]),
+                    e[1].min(s.extent[1]),
+                    e[2].max(s.extent[2]),
+                    e[3].max(s.extent[3]),
+                ]
+            },
+        );
+
+        GridInfo {
+            key: key.to_string(),
+            format,
+            bands,
+            byte_size: grid.byte_size(),
+            extent,
+            subgrids,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GridInfo {
+    #[wasm_bindgen(getter)]
+    pub fn key(&self) -> String {
+        self.key.clone()
+    }
+
+    /// The format the grid was read from, eg `NTv2`
+    #[wasm_bindgen(getter)]
+    pub fn format(&self) -> String {
+        self.format.to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn bands(&self) -> usize {
+        self.bands
+    }
+
+    /// The memory used by the grid values, only counting fetched subgrids of lazy grids
+    #[wasm_bindgen(getter, js_name = byteSize)]
+    pub fn byte_size(&self) -> usize {
+        self.byte_size
+    }
+
+    /// The combined extent of the top level subgrids
+    #[wasm_bindgen(getter)]
+    pub fn extent(&self) -> Vec<f64> {
+        self.extent.to_vec()
+    }
+
+    /// The subgrids, where each `parent` is an index into this list
+    #[wasm_bindgen(getter)]
+    pub fn subgrids(&self) -> js_sys::Array {
+        self.subgrids.iter().cloned().map(JsValue::from).collect()
+    }
+}
+
+#[wasm_bindgen]
+impl SubgridInfo {
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    /// The index of the parent subgrid, if any
+    #[wasm_bindgen(getter)]
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn extent(&self) -> Vec<f64> {
+        self.extent.to_vec()
+    }
+
+    /// The node spacing `[longitude, latitude]` in radians
+    #[wasm_bindgen(getter)]
+    pub fn resolution(&self) -> Vec<f64> {
+        self.resolution.to_vec()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+}
@@ -0,0 +1,145 @@
+//! ISG (International Service for the Geoid) text grids.
+//!
+//! A `key = value` header between `begin_of_head` and `end_of_head` lines, followed by the
+//! nodes from north to south, west to east in metres. Only geodetic grids in decimal degrees
+//! are supported. With `node offset = 1` the extent is that of the cells rather than the nodes.
+use super::raster::{GridHeader, GridSet, RasterGrid};
+use crate::error::{Error, Result};
+use std::collections::BTreeMap;
+
+pub fn read(bytes: &[u8]) -> Result<GridSet> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut lines = text.lines();
+    lines
+        .by_ref()
+        .find(|line| line.trim_start().starts_with("begin_of_head"))
+        .ok_or_else(|| invalid("missing `begin_of_head`"))?;
+
+    let mut fields = BTreeMap::new();
+    for line in lines.by_ref() {
+        if line.trim_start().starts_with("end_of_head") {
+            break;
+        }
+        // ISG 1.0 uses `=` and 2.0 `:`. Units in square brackets follow the key in 2.0.
+        if let Some((key, value)) = line.split_once(['=', ':']) {
+            let key = key.split('[').next().unwrap_or(key);
+            let key = key.split_whitespace().collect::<Vec<_>>().join(" ");
+            fields.insert(key.to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let text_field = |key: &str| fields.get(key).map(String::as_str);
+    let number = |key: &str| -> Result<f64> {
+        let value = text_field(key).ok_or_else(|| invalid(&format!("missing `{}`", key)))?;
+        value
+            .parse()
+            .map_err(|_| invalid(&format!("`{}` is not a number: `{}`", key, value)))
+    };
+
+    if let Some(units) = text_field("coord units").filter(|u| *u != "deg") {
+        return Err(invalid(&format!("unsupported coord units `{}`", units)));
+    }
+    if let Some(kind) = text_field("coord type").filter(|t| *t != "geodetic") {
+        return Err(invalid(&format!("unsupported coord type `{}`", kind)));
+    }
+    if let Some(order) = text_field("data ordering").filter(|o| *o != "N-to-S, W-to-E") {
+        return Err(invalid(&format!("unsupported data ordering `{}`", order)));
+    }
+    if let Some(format) = text_field("data format").filter(|f| *f != "grid") {
+        return Err(invalid(&format!("unsupported data format `{}`", format)));
+    }
+
+    let (mut lat_n, mut lon_w) = (number("lat max")?, number("lon min")?);
+    let (dlat, dlon) = (number("delta lat")?, number("delta lon")?);
+    let (rows, cols) = (number("nrows")?, number("ncols")?);
+    if !(dlat > 0. && dlon > 0. && rows >= 1. && cols >= 1.) {
+        return Err(invalid("inconsistent header"));
+    }
+    let (rows, cols) = (rows as usize, cols as usize);
+    if text_field("node offset") == Some("1") {
+        lat_n -= dlat / 2.;
+        lon_w += dlon / 2.;
+    }
+    let nodata = text_field("nodata").and_then(|v| v.parse::<f64>().ok());
+
+    let values = lines
+        .flat_map(str::split_whitespace)
+        .map(|token| {
+            let value = token
+                .parse::<f64>()
+                .map_err(|_| invalid(&format!("`{}` is not a number", token)))?;
+            Ok(if Some(value) == nodata {
+                f32::NAN
+            } else {
+                value as f32
+            })
+        })
+        .collect::<Result<Vec<f32>>>()?;
+    if values.len() != rows * cols {
+        return Err(invalid(&format!(
+            "{} values do not fill a {} x {} grid",
+            values.len(),
+            rows,
+            cols
+        )));
+    }
+
+    let header = GridHeader {
+        lat_n: lat_n.to_radians(),
+        lon_w: lon_w.to_radians(),
+        dlat: dlat.to_radians(),
+        dlon: dlon.to_radians(),
+        rows,
+        cols,
+        bands: 1,
+    };
+    Ok(GridSet::single(RasterGrid::new(header, values)?))
+}
+
+fn invalid(message: &str) -> Error {
+    Error::Invalid(format!("ISG: {}", message))
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_eq::assert_float_eq;
+    use geodesy_rs::authoring::{Coor4D, Grid};
+
+    const ISG: &str = "comments
+begin_of_head ================================================
+model name     : TEST
+lat min    [deg] :    53.500000
+lat max    [deg] :    55.500000
+lon min    [deg] :     7.500000
+lon max    [deg] :    10.500000
+delta lat  [deg] :     1.000000
+delta lon  [deg] :     1.000000
+nrows          :            2
+ncols          :            3
+nodata         :   -9999.0000
+node offset    :            1
+ISG format     :          2.0
+end_of_head ==================================================
+   40.0000    41.0000    42.0000
+   30.0000    31.0000 -9999.0000
+";
+
+    #[test]
+    fn geoid() -> Result<()> {
+        let grid = read(ISG.as_bytes())?;
+        assert_eq!(grid.bands(), 1);
+
+        let at = |lon: f64, lat: f64| grid.at(None, &Coor4D::gis(lon, lat, 0., 0.), 0.);
+        // The nodes are the cell centres
+        assert_float_eq!(at(8., 55.).unwrap()[0], 40., abs <= 1e-9);
+        assert_float_eq!(at(8.5, 54.5).unwrap()[0], 35.5, abs <= 1e-6);
+        assert!(at(9.5, 54.5).is_none());
+
+        assert!(read(ISG.replace(" -9999.0000\n", "\n").as_bytes()).is_err());
+        assert!(read(ISG.replace("ncols ", "cols  ").as_bytes()).is_err());
+        Ok(())
+    }
+}
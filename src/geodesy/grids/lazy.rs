@@ -0,0 +1,555 @@
+//! Grids read on demand with HTTP range requests.
+//!
+//! Registering a [LazyGrid] only reads the subgrid headers (NTv2) or image directories (GeoTIFF).
+//! [LazyGrid::prefetch] fetches the nodes covering an extent: the rows of NTv2 subgrids and the
+//! strips or tiles of GeoTIFF images overlapping it. Interpolation only happens inside prefetched
+//! extents so transformations never wait on the network.
+use super::{
+    fetch::RequestOptions,
+    format::GridFormat,
+    geotiff::{self, Directory},
+    ntv2::{self, Ntv2},
+    raster::{GridHeader, Hierarchy, Interpolation, RasterGrid},
+};
+use crate::error::{Error, Result};
+use geodesy_rs::authoring::{Context, Coor4D, Grid};
+use reqwest::{StatusCode, Url};
+use std::{
+    fmt,
+    future::Future,
+    ops::Range,
+    pin::Pin,
+    sync::{Arc, RwLock},
+};
+
+/// The first request, expected to hold the headers of most files
+const PREFIX: u64 = 64 * 1024;
+/// GeoTIFF image directories are expected within this many bytes from the start of the file
+const MAX_PREFIX: u64 = 4 * 1024 * 1024;
+/// Nodes fetched around each extent, so values inside it are interpolated from the same
+/// nodes as with the whole subgrid
+const PADDING: usize = 2;
+
+pub(crate) type Fetch<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>>> + 'a>>;
+
+/// Where the bytes of a [LazyGrid] come from
+pub(crate) trait RangeSource: fmt::Debug + Send + Sync {
+    /// Up to `length` bytes from `offset`, fewer at the end of the file
+    fn fetch(&self, offset: u64, length: u64) -> Fetch<'_>;
+}
+
+/// A grid file behind a URL, read with HTTP range requests
+#[derive(Debug)]
+pub(crate) struct HttpSource {
+    url: Url,
+    options: RequestOptions,
+}
+
+impl HttpSource {
+    pub fn new(url: Url, options: RequestOptions) -> HttpSource {
+        HttpSource { url, options }
+    }
+}
+
+impl RangeSource for HttpSource {
+    fn fetch(&self, offset: u64, length: u64) -> Fetch<'_> {
+        Box::pin(async move {
+            if length == 0 {
+                return Ok(Vec::new());
+            }
+            log::debug!("Fetching {} bytes from {} of {}", length, offset, self.url);
+            let range = offset..offset + length;
+            let response = self.options.get(&self.url, Some(range)).await?;
+            let partial = response.status() == StatusCode::PARTIAL_CONTENT;
+            let bytes = response.bytes().await?;
+            if partial {
+                return Ok(bytes.to_vec());
+            }
+
+            // The server ignored the range and sent the whole file
+            let start = usize::try_from(offset)
+                .unwrap_or(usize::MAX)
+                .min(bytes.len());
+            let end = usize::try_from(length)
+                .ok()
+                .and_then(|length| start.checked_add(length))
+                .unwrap_or(usize::MAX)
+                .min(bytes.len());
+            Ok(bytes[start..end].to_vec())
+        })
+    }
+}
+
+// How to decode the values of a subgrid
+#[derive(Debug)]
+enum Layout {
+    Ntv2(Ntv2),
+    GeoTiff {
+        /// The start of the file, holding all image directories
+        prefix: Vec<u8>,
+        directories: Vec<Directory>,
+    },
+}
+
+// The nodes of a subgrid fetched so far
+#[derive(Debug)]
+struct Window {
+    rows: Range<usize>,
+    cols: Range<usize>,
+    grid: Arc<RasterGrid>,
+}
+
+/// A grid whose nodes are fetched when an extent they cover is prefetched
+#[derive(Debug)]
+pub struct LazyGrid {
+    source: Box<dyn RangeSource>,
+    layout: Layout,
+    headers: Vec<GridHeader>,
+    names: Vec<Option<String>>,
+    hierarchy: Hierarchy,
+    /// The bytes of the file holding the values of each subgrid
+    data: Vec<Range<u64>>,
+    loaded: RwLock<Vec<Vec<Window>>>,
+    /// The `[west, south, east, north]` extents prefetched so far
+    regions: RwLock<Vec<[f64; 4]>>,
+}
+
+impl LazyGrid {
+    /// Read the headers of the grid, detecting the format unless it is given
+    pub async fn new(
+        source: Box<dyn RangeSource>,
+        format: Option<GridFormat>,
+    ) -> Result<(GridFormat, LazyGrid)> {
+        let prefix = source.fetch(0, PREFIX).await?;
+        let format = match format.or_else(|| GridFormat::detect(&prefix)) {
+            Some(format) => format,
+            None => {
+                return Err(Error::Invalid(
+                    "Could not detect the grid format, set it with the `format` option".to_string(),
+                ))
+            }
+        };
+
+        let grid = match format {
+            GridFormat::Ntv2 => LazyGrid::ntv2(source, prefix).await?,
+            GridFormat::GeoTiff => LazyGrid::geotiff(source, prefix).await?,
+            format => {
+                return Err(Error::Invalid(format!(
+                    "{} grids can not be loaded lazily, register them with `registerGrid`",
+                    format
+                )))
+            }
+        };
+        Ok((format, grid))
+    }
+
+    async fn ntv2(source: Box<dyn RangeSource>, prefix: Vec<u8>) -> Result<LazyGrid> {
+        let file = Ntv2::new(&prefix)?;
+
+        let mut headers = Vec::with_capacity(file.subgrids);
+        let mut names = Vec::with_capacity(file.subgrids);
+        let mut parent_names = Vec::with_capacity(file.subgrids);
+        let mut data = Vec::with_capacity(file.subgrids);
+        let mut offset = ntv2::HEADER as u64;
+        for _ in 0..file.subgrids {
+            let record = read_at(source.as_ref(), &prefix, offset, ntv2::HEADER as u64).await?;
+            let subgrid = file.subgrid(&record)?;
+            offset += ntv2::HEADER as u64;
+            let length = (subgrid.header.rows * subgrid.header.cols) as u64 * ntv2::NODE as u64;
+            data.push(offset..offset + length);
+            offset += length;
+
+            headers.push(subgrid.header);
+            names.push(subgrid.name);
+            parent_names.push(subgrid.parent);
+        }
+
+        let parents = parent_names
+            .iter()
+            .map(|parent| names.iter().position(|name| name == parent))
+            .collect();
+        let names = names.into_iter().map(Some).collect();
+        LazyGrid::new_with(source, Layout::Ntv2(file), headers, names, parents, data)
+    }
+
+    async fn geotiff(source: Box<dyn RangeSource>, mut prefix: Vec<u8>) -> Result<LazyGrid> {
+        // Grow the prefix until it holds all image directories
+        let mut requested = PREFIX;
+        let directories = loop {
+            match geotiff::directories(&prefix) {
+                Ok(directories) => break directories,
+                Err(e) if (prefix.len() as u64) < requested || requested >= MAX_PREFIX => {
+                    return Err(e)
+                }
+                Err(_) => {
+                    requested = (requested * 4).min(MAX_PREFIX);
+                    prefix = source.fetch(0, requested).await?;
+                }
+            }
+        };
+
+        let headers = directories.iter().map(|d| d.header).collect();
+        let names = directories.iter().map(|d| d.name.clone()).collect();
+        let parents = directories.iter().map(|d| d.parent).collect();
+        let data = directories.iter().map(|d| d.data.clone()).collect();
+        let layout = Layout::GeoTiff {
+            prefix,
+            directories,
+        };
+        LazyGrid::new_with(source, layout, headers, names, parents, data)
+    }
+
+    fn new_with(
+        source: Box<dyn RangeSource>,
+        layout: Layout,
+        headers: Vec<GridHeader>,
+        names: Vec<Option<String>>,
+        parents: Vec<Option<usize>>,
+        data: Vec<Range<u64>>,
+    ) -> Result<LazyGrid> {
+        let hierarchy = Hierarchy::new(&headers.iter().collect::<Vec<_>>(), parents)?;
+        Ok(LazyGrid {
+            source,
+            layout,
+            loaded: RwLock::new(headers.iter().map(|_| Vec::new()).collect()),
+            regions: RwLock::new(Vec::new()),
+            headers,
+            names,
+            hierarchy,
+            data,
+        })
+    }
+
+    pub fn headers(&self) -> &[GridHeader] {
+        &self.headers
+    }
+
+    pub fn parents(&self) -> &[Option<usize>] {
+        self.hierarchy.parents()
+    }
+
+    pub fn names(&self) -> &[Option<String>] {
+        &self.names
+    }
+
+    /// The approximate heap size of the loaded grid values in bytes
+    pub fn byte_size(&self) -> usize {
+        self.loaded
+            .read()
+            .unwrap()
+            .iter()
+            .flatten()
+            .map(|w| std::mem::size_of_val(w.grid.values()))
+            .sum()
+    }
+
+    /// Fetch the nodes of the subgrids overlapping the `[west, south, east, north]` extent in radians
+    pub async fn prefetch(&self, extent: [f64; 4]) -> Result<()> {
+        for i in 0..self.headers.len() {
+            let header = &self.headers[i];
+            if header.window(&extent, 0).is_none() {
+                continue;
+            }
+            let Some((rows, mut cols)) = header.window(&extent, PADDING) else {
+                continue;
+            };
+            // Windows across the seam of grids wrapping around take all columns
+            if cols.end > header.cols {
+                cols = 0..header.cols;
+            }
+            let fetched = self.loaded.read().unwrap()[i].iter().any(|w| {
+                w.rows.start <= rows.start
+                    && rows.end <= w.rows.end
+                    && w.cols.start <= cols.start
+                    && cols.end <= w.cols.end
+            });
+            if fetched {
+                continue;
+            }
+
+            let window = match &self.layout {
+                // Rows are stored from the south, each holding all columns
+                Layout::Ntv2(file) => {
+                    let row = (header.cols * ntv2::NODE) as u64;
+                    let start = self.data[i].start + (header.rows - rows.end) as u64 * row;
+                    let bytes = self.fetch(start, rows.len() as u64 * row, i).await?;
+                    let cols = 0..header.cols;
+                    let grid = file.values(&header.part(&rows, &cols), &bytes)?;
+                    Window {
+                        rows,
+                        cols,
+                        grid: Arc::new(grid),
+                    }
+                }
+                Layout::GeoTiff {
+                    prefix,
+                    directories,
+                } => {
+                    let directory = &directories[i];
+                    let mut parts = Vec::new();
+                    for range in merge(geotiff::window_data(prefix, directory, &rows, &cols)?) {
+                        let bytes = self.fetch(range.start, range.end - range.start, i).await?;
+                        parts.push((range.start, bytes));
+                    }
+                    let grid = geotiff::read_window(prefix, directory, &rows, &cols, &parts)?;
+                    Window {
+                        rows,
+                        cols,
+                        grid: Arc::new(grid),
+                    }
+                }
+            };
+            self.loaded.write().unwrap()[i].push(window);
+        }
+
+        self.regions.write().unwrap().push(extent);
+        Ok(())
+    }
+
+    // Exactly `length` bytes from `offset`, holding values of subgrid `index`
+    async fn fetch(&self, offset: u64, length: u64, index: usize) -> Result<Vec<u8>> {
+        let bytes = self.source.fetch(offset, length).await?;
+        if bytes.len() as u64 != length {
+            return Err(Error::Invalid(format!(
+                "The grid file ended before subgrid {}",
+                index
+            )));
+        }
+        Ok(bytes)
+    }
+
+    /// The values of the finest prefetched subgrid containing `position`
+    pub fn interpolate(
+        &self,
+        position: &Coor4D,
+        margin: f64,
+        interpolation: Interpolation,
+    ) -> Option<Coor4D> {
+        self.find(position, margin)?
+            .interpolate(position, margin, interpolation)
+    }
+
+    /// The index of the finest subgrid containing `position`, prefetched or not
+    pub fn find_index(&self, position: &Coor4D, margin: f64) -> Option<usize> {
+        let contains = |i: usize, margin| self.headers[i].contains(position, margin);
+        self.hierarchy.find(contains, margin)
+    }
+
+    // The finest subgrid containing `position`, if it has been prefetched
+    fn find(&self, position: &Coor4D, margin: f64) -> Option<Arc<RasterGrid>> {
+        let prefetched =
+            self.regions.read().unwrap().iter().any(|r| {
+                (r[0]..=r[2]).contains(&position[0]) && (r[1]..=r[3]).contains(&position[1])
+            });
+        if !prefetched {
+            return None;
+        }
+
+        // Only points outside the nodes of the subgrid may use its margin
+        let index = self.find_index(position, margin)?;
+        let margin = if self.headers[index].contains(position, 0.) {
+            0.
+        } else {
+            margin
+        };
+        self.loaded.read().unwrap()[index]
+            .iter()
+            .find(|w| w.grid.header().contains(position, margin))
+            .map(|w| w.grid.clone())
+    }
+}
+
+// Sorted byte ranges, with adjacent and overlapping ones joined into one request
+fn merge(mut ranges: Vec<Range<u64>>) -> Vec<Range<u64>> {
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+// Bytes from the already fetched start of the file when possible
+async fn read_at(
+    source: &dyn RangeSource,
+    prefix: &[u8],
+    offset: u64,
+    length: u64,
+) -> Result<Vec<u8>> {
+    let end = offset + length;
+    if end <= prefix.len() as u64 {
+        return Ok(prefix[offset as usize..end as usize].to_vec());
+    }
+    let bytes = source.fetch(offset, length).await?;
+    if (bytes.len() as u64) < length {
+        return Err(Error::Invalid(
+            "Unexpected end of the grid file".to_string(),
+        ));
+    }
+    Ok(bytes)
+}
+
+impl Grid for LazyGrid {
+    fn bands(&self) -> usize {
+        self.headers[0].bands
+    }
+
+    fn contains(&self, position: &Coor4D, margin: f64, _all: bool) -> bool {
+        self.find(position, margin).is_some()
+    }
+
+    fn at(&self, _ctx: Option<&dyn Context>, at: &Coor4D, margin: f64) -> Option<Coor4D> {
+        self.interpolate(at, margin, Interpolation::Bilinear)
+    }
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::geodesy::grids::{geotiff::tests::tiled_geoid, ntv2::tests::file, tests::GEOID};
+    use float_eq::assert_float_eq;
+    use std::{
+        sync::Mutex,
+        task::{self, Poll, RawWaker, RawWakerVTable, Waker},
+    };
+
+    // A stand-in for an HTTP server, recording the requested ranges
+    #[derive(Debug)]
+    struct MemorySource {
+        bytes: Vec<u8>,
+        requests: Arc<Mutex<Vec<Range<u64>>>>,
+    }
+
+    impl RangeSource for MemorySource {
+        fn fetch(&self, offset: u64, length: u64) -> Fetch<'_> {
+            self.requests.lock().unwrap().push(offset..offset + length);
+            let start = (offset as usize).min(self.bytes.len());
+            let end = (start + length as usize).min(self.bytes.len());
+            let bytes = self.bytes[start..end].to_vec();
+            Box::pin(async move { Ok(bytes) })
+        }
+    }
+
+    // The memory source never waits, so a single poll completes any future using it
+    pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+        fn raw() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker {
+                raw()
+            }
+            fn noop(_: *const ()) {}
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+        let waker = unsafe { Waker::from_raw(raw()) };
+        let mut cx = task::Context::from_waker(&waker);
+        match std::pin::pin!(future).poll(&mut cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("the memory source never waits"),
+        }
+    }
+
+    #[test]
+    fn prefetch() -> Result<()> {
+        // Two large subgrids a degree apart at 50N and 60N, 1E..1W in seconds, positive west
+        let bytes = file(&[
+            ("SOUTH", "NONE", [180_000., 183_600., -3600., 3600.], 36.),
+            ("NORTH", "NONE", [216_000., 219_600., -3600., 3600.], 36.),
+        ]);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let source = MemorySource {
+            bytes: bytes.clone(),
+            requests: requests.clone(),
+        };
+        let (format, grid) = block_on(LazyGrid::new(Box::new(source), None))?;
+        assert_eq!(format, GridFormat::Ntv2);
+        // NUM_FILE, not NUM_SREC (11), counts the subgrids
+        assert_eq!(&bytes[16..24], b"NUM_SREC");
+        assert_eq!(grid.names(), &[Some("SOUTH".into()), Some("NORTH".into())]);
+        assert_eq!(grid.byte_size(), 0);
+
+        let south = Coor4D::gis(0.5, 50.5, 0., 0.);
+        let north = Coor4D::gis(0.5, 60.5, 0., 0.);
+        assert!(grid.at(None, &south, 0.).is_none());
+
+        let headers = requests.lock().unwrap().len();
+        let extent = [-0.5_f64, 50.2, 0.5, 50.8].map(f64::to_radians);
+        block_on(grid.prefetch(extent))?;
+        assert!(grid.at(None, &south, 0.).is_some());
+        assert!(grid.at(None, &north, 0.).is_none());
+        // The rows of the first subgrid within two nodes of 50.2..50.8N
+        assert_eq!(grid.byte_size(), 66 * 201 * 2 * 4);
+
+        // Only those rows were requested, in one range
+        let requested = requests.lock().unwrap()[headers..].to_vec();
+        assert_eq!(requested.len(), 1);
+        assert_eq!(requested[0].end - requested[0].start, 66 * 201 * 16);
+        block_on(grid.prefetch([-0.4_f64, 50.3, 0.4, 50.7].map(f64::to_radians)))?;
+        assert_eq!(requests.lock().unwrap().len(), headers + 1);
+
+        // The same values as reading the whole file, up to rounding
+        let full = ntv2::read(&bytes)?;
+        assert_float_eq!(
+            grid.at(None, &south, 0.).unwrap().0,
+            full.at(None, &south, 0.).unwrap().0,
+            abs_all <= 1e-12
+        );
+
+        let source = MemorySource {
+            bytes: GEOID.to_vec(),
+            requests,
+        };
+        assert!(block_on(LazyGrid::new(Box::new(source), None)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn prefetch_tiles() -> Result<()> {
+        // 60 x 80 nodes over 52N..46.1N, 1W..6.9E in 8 x 8 tiles
+        let bytes = tiled_geoid(60, 80, 8);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let source = MemorySource {
+            bytes: bytes.clone(),
+            requests: requests.clone(),
+        };
+        let (format, grid) = block_on(LazyGrid::new(Box::new(source), None))?;
+        assert_eq!(format, GridFormat::GeoTiff);
+        let headers = requests.lock().unwrap().len();
+
+        let extent = [0.42_f64, 49.38, 0.58, 49.62].map(f64::to_radians);
+        block_on(grid.prefetch(extent))?;
+
+        // Rows 21..29 and columns 12..18 with the padding, in 4 tiles of 256 bytes
+        let requested: u64 = requests.lock().unwrap()[headers..]
+            .iter()
+            .map(|r| r.end - r.start)
+            .sum();
+        let image = 8 * 10 * 8 * 8 * 4;
+        assert_eq!(requested, 4 * 8 * 8 * 4);
+        assert!(requested < image / 10);
+
+        // The same values as reading the whole file, also where bicubic needs the padding
+        let full = geotiff::read(&bytes)?;
+        for (lon, lat) in [(0.5, 49.5), (0.42, 49.38), (0.58, 49.62), (0.4321, 49.6012)] {
+            let position = Coor4D::gis(lon, lat, 0., 0.);
+            for interpolation in [Interpolation::Bilinear, Interpolation::Bicubic] {
+                assert_float_eq!(
+                    grid.interpolate(&position, 0., interpolation).unwrap().0,
+                    full.grids()[0]
+                        .interpolate(&position, 0., interpolation)
+                        .unwrap()
+                        .0,
+                    abs_all <= 1e-9
+                );
+            }
+        }
+        assert!(grid
+            .interpolate(&Coor4D::gis(0.7, 49.5, 0., 0.), 0., Interpolation::Bilinear)
+            .is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,603 @@
+use super::coordinate::Coordinates;
+use crate::error::{Error, Result, WasmResult};
+use fetch::RequestOptions;
+use geodesy_rs::authoring::{Context, Coor4D, Grid};
+use js_sys::{DataView, Function, Object, Uint8Array};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Url,
+};
+use std::{sync::Arc, time::Duration};
+use wasm_bindgen::prelude::*;
+
+mod bake;
+mod binary;
+mod cache;
+mod fetch;
+mod format;
+mod geotiff;
+mod gravsoft;
+mod gtx;
+mod info;
+mod isg;
+mod lazy;
+mod nadcon;
+mod namespace;
+mod ntv1;
+mod ntv2;
+mod raster;
+mod registry;
+mod sample;
+
+pub(crate) use bake::bake;
+pub use format::GridFormat;
+pub use info::GridInfo;
+use lazy::LazyGrid;
+pub use namespace::GridNamespace;
+use raster::GridSet;
+pub(crate) use raster::Interpolation;
+pub(crate) use registry::{global, Registry};
+pub use sample::GridSample;
+
+/// A registered grid
+#[derive(Debug, Clone)]
+pub(crate) struct GridEntry {
+    pub format: GridFormat,
+    pub grid: GridData,
+}
+
+/// The values of a registered grid, either read in full or fetched on demand
+#[derive(Debug, Clone)]
+pub(crate) enum GridData {
+    Loaded(Arc<GridSet>),
+    Lazy(Arc<LazyGrid>),
+}
+
+impl GridData {
+    fn grid(&self, interpolation: Interpolation) -> Arc<dyn Grid> {
+        match (self, interpolation) {
+            (GridData::Loaded(grid), Interpolation::Bilinear) => grid.clone(),
+            (GridData::Lazy(grid), Interpolation::Bilinear) => grid.clone(),
+            (grid, interpolation) => Arc::new(Interpolated {
+                grid: grid.clone(),
+                interpolation,
+            }),
+        }
+    }
+
+    /// The index of the finest subgrid containing `position`, as listed by [GridInfo]
+    fn subgrid_at(&self, position: &Coor4D) -> Option<usize> {
+        match self {
+            GridData::Loaded(grid) => grid.find_index(position, 0.),
+            GridData::Lazy(grid) => grid.find_index(position, 0.),
+        }
+    }
+
+    fn interpolate(
+        &self,
+        position: &Coor4D,
+        margin: f64,
+        interpolation: Interpolation,
+    ) -> Option<Coor4D> {
+        match self {
+            GridData::Loaded(grid) => grid.interpolate(position, margin, interpolation),
+            GridData::Lazy(grid) => grid.interpolate(position, margin, interpolation),
+        }
+    }
+
+    fn byte_size(&self) -> usize {
+        match self {
+            GridData::Loaded(grid) => grid.byte_size(),
+            GridData::Lazy(grid) => grid.byte_size(),
+        }
+    }
+}
+
+/// A registered grid with other than the default bilinear interpolation
+#[derive(Debug)]
+struct Interpolated {
+    grid: GridData,
+    interpolation: Interpolation,
+}
+
+impl Grid for Interpolated {
+    fn bands(&self) -> usize {
+        self.grid.grid(Interpolation::Bilinear).bands()
+    }
+
+    fn contains(&self, position: &Coor4D, margin: f64, all: bool) -> bool {
+        self.grid
+            .grid(Interpolation::Bilinear)
+            .contains(position, margin, all)
+    }
+
+    fn at(&self, _ctx: Option<&dyn Context>, at: &Coor4D, margin: f64) -> Option<Coor4D> {
+        self.grid.interpolate(at, margin, self.interpolation)
+    }
+}
+
+// Appended to the grid names [get_grid] passes to the context. Grid keys in definitions
+// can not hold whitespace, so this can not be part of one.
+const INTERPOLATION_SUFFIX: &str = " interpolation=";
+
+/// Look a grid up through `ctx` for interpolation other than bilinear. As [Context::get_grid]
+/// only takes a name, the interpolation is passed in it, see [grid_request].
+pub(crate) fn get_grid(
+    ctx: &dyn Context,
+    key: &str,
+    interpolation: Interpolation,
+) -> std::result::Result<Arc<dyn Grid>, geodesy_rs::Error> {
+    match interpolation {
+        Interpolation::Bilinear => ctx.get_grid(key),
+        _ => ctx.get_grid(&format!("{}{}{}", key, INTERPOLATION_SUFFIX, interpolation)),
+    }
+}
+
+/// The key and interpolation of a grid name [Context::get_grid] was called with
+pub(crate) fn grid_request(name: &str) -> (&str, Interpolation) {
+    name.rsplit_once(INTERPOLATION_SUFFIX)
+        .and_then(|(key, interpolation)| Some((key, interpolation.parse().ok()?)))
+        .unwrap_or((name, Interpolation::Bilinear))
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const GRID_OPTIONS: &'static str = r#"
+export interface GridOptions {
+    /** The grid file format. Detected from the content when not given. */
+    format?: "ntv2" | "ntv1" | "gravsoft" | "gtx" | "isg" | "geotiff";
+    /** Extra request headers for `registerGrid` and `registerLazyGrid`, eg `{Authorization: "Bearer ..."}`. */
+    headers?: Record<string, string>;
+    /** Abort each request after this many milliseconds. */
+    timeoutMs?: number;
+    /** Whether requests send cookies and HTTP authentication, as in `fetch`. */
+    credentials?: "omit" | "same-origin" | "include";
+    /** Retry requests failing with network errors, 408, 429 or 5xx responses this many times. Defaults to 0. */
+    retries?: number;
+    /** Called during `registerGrid` downloads with the bytes received so far and the total, when known. */
+    onProgress?: (loaded: number, total?: number) => void;
+    /** Only keep the subgrids and nodes covering this `[west, south, east, north]` extent in radians, see `cropGrid`. Ignored by `registerLazyGrid`. */
+    bbox?: [number, number, number, number];
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "GridOptions")]
+    pub type JsGridOptions;
+}
+
+/// Options for registering a grid
+#[derive(Debug, Default, Clone)]
+pub struct GridOptions {
+    pub format: Option<GridFormat>,
+    pub request: RequestOptions,
+    pub on_progress: Option<Function>,
+    pub bbox: Option<[f64; 4]>,
+}
+
+impl GridOptions {
+    pub(crate) fn from_js(options: Option<JsGridOptions>) -> Result<GridOptions> {
+        let Some(options) = options else {
+            return Ok(GridOptions::default());
+        };
+        let get = |name: &str| {
+            js_sys::Reflect::get(&options, &JsValue::from_str(name))
+                .ok()
+                .filter(|value| !value.is_undefined() && !value.is_null())
+        };
+        let number = |name: &str| -> Result<Option<f64>> {
+            match get(name) {
+                Some(value) => match value.as_f64() {
+                    Some(number) if number >= 0. => Ok(Some(number)),
+                    _ => Err(Error::Invalid(format!(
+                        "`{}` must be a non-negative number",
+                        name
+                    ))),
+                },
+                None => Ok(None),
+            }
+        };
+
+        let string = |name: &str| -> Result<Option<String>> {
+            match get(name) {
+                Some(value) => value
+                    .as_string()
+                    .map(Some)
+                    .ok_or_else(|| Error::Invalid(format!("`{}` must be a string", name))),
+                None => Ok(None),
+            }
+        };
+
+        let format = string("format")?.map(|format| format.parse()).transpose()?;
+
+        let mut headers = HeaderMap::new();
+        if let Some(object) = get("headers") {
+            let object = object
+                .dyn_into::<Object>()
+                .map_err(|_| Error::Invalid("`headers` must be an object".to_string()))?;
+            for entry in Object::entries(&object).iter() {
+                let entry: js_sys::Array = entry.unchecked_into();
+                let name = entry.get(0).as_string();
+                let invalid = || {
+                    Error::Invalid(format!(
+                        "Invalid request header `{}`",
+                        name.as_deref().unwrap_or_default()
+                    ))
+                };
+                let (Some(name), Some(value)) = (name.as_ref(), entry.get(1).as_string()) else {
+                    return Err(invalid());
+                };
+                headers.insert(
+                    HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?,
+                    HeaderValue::from_str(&value).map_err(|_| invalid())?,
+                );
+            }
+        }
+
+        let request = RequestOptions {
+            headers,
+            timeout: number("timeoutMs")?.map(|ms| Duration::from_millis(ms as u64)),
+            credentials: string("credentials")?.map(|c| c.parse()).transpose()?,
+            retries: number("retries")?.unwrap_or(0.) as u32,
+        };
+        let on_progress = get("onProgress")
+            .map(|f| {
+                f.dyn_into::<Function>()
+                    .map_err(|_| Error::Invalid("`onProgress` must be a function".to_string()))
+            })
+            .transpose()?;
+        let bbox = get("bbox")
+            .map(|bbox| {
+                let values: Option<Vec<f64>> = js_sys::Array::from(&bbox)
+                    .iter()
+                    .map(|v| v.as_f64())
+                    .collect();
+                extent(values.unwrap_or_default())
+            })
+            .transpose()?;
+
+        Ok(GridOptions {
+            format,
+            request,
+            on_progress,
+            bbox,
+        })
+    }
+}
+
+/// Download a file in full, through the cache set with `setGridCache` if any
+pub(crate) async fn download(url: &str, options: &GridOptions) -> Result<Vec<u8>> {
+    let url = Url::parse(url).map_err(|e| Error::Invalid(format!("{}: {}", e, url)))?;
+    let on_progress = options.on_progress.as_ref();
+    match cache::grid_cache() {
+        Some(cache) => cache::fetch(cache.as_ref(), &url, &options.request, on_progress).await,
+        None => {
+            let response = options.request.get(&url, None).await?;
+            fetch::read_body(response, on_progress).await
+        }
+    }
+}
+
+/// A `[west, south, east, north]` extent from JS
+pub(crate) fn extent(values: Vec<f64>) -> Result<[f64; 4]> {
+    match <[f64; 4]>::try_from(values) {
+        Ok(extent) if extent[0] <= extent[2] && extent[1] <= extent[3] => Ok(extent),
+        _ => Err(Error::Invalid(
+            "The extent must be [west, south, east, north]".to_string(),
+        )),
+    }
+}
+
+/// A synchronous way to register grids for use in the [Geo] class with named [DataView]s.
+///
+/// The keys used to load the grid MUST be the same
+/// as the `grids=<key>` parameter in the definition string.
+///
+/// The format is detected from the content unless `options.format` is given.
+///
+/// Supported Grid Types:
+///     - `NTv2` (.gsb)
+///     - `NTv1` (.dac)
+///     - `GeoTIFF` (.tif) as distributed on the PROJ CDN
+///     - `GTX` (.gtx) vertical offset and geoid grids
+///     - `ISG` (.isg) geoid grids
+///     - `Gravsoft`
+#[wasm_bindgen(js_name = registerGridSync)]
+pub fn register_grid_sync(
+    key: &str,
+    data_view: DataView,
+    options: Option<JsGridOptions>,
+) -> WasmResult<()> {
+    let options = GridOptions::from_js(options)?;
+    let grid = view_bytes(&data_view);
+    global().add_grid(key, grid, &options)?;
+
+    Ok(())
+}
+
+/// [Unstable] Add grids to the [Geo] class with names urls.
+/// The URL MUST return a grid file in the supported formats.
+///
+/// The keys used to load the grid MUST be the same
+/// as the `grids=<key>` parameter in the definition string.
+///
+/// The format is detected from the content unless `options.format` is given.
+/// Request headers, timeouts, credentials, retries and a progress callback can be set
+/// in `options`. Responses other than 2xx fail with a `NetworkError`.
+///
+/// With a cache set by `setGridCache` unchanged grids are not downloaded again.
+///
+/// Supported Grid Types:
+///     - `NTv2` (.gsb)
+///     - `NTv1` (.dac)
+///     - `GeoTIFF` (.tif) as distributed on the PROJ CDN
+///     - `GTX` (.gtx) vertical offset and geoid grids
+///     - `ISG` (.isg) geoid grids
+///     - `Gravsoft`
+#[wasm_bindgen(js_name = registerGrid)]
+pub async fn UNSTABLE_register_grid(
+    key: &str,
+    url: &str,
+    options: Option<JsGridOptions>,
+) -> WasmResult<()> {
+    let options = GridOptions::from_js(options)?;
+    global().fetch_grid(key, url, &options).await?;
+
+    Ok(())
+}
+
+/// Register a NADCON grid from its latitude (`.las`) and longitude (`.los`) offset files.
+///
+/// The key MUST be the same as the `grids=<key>` parameter in the definition string.
+#[wasm_bindgen(js_name = registerNadconGridSync)]
+pub fn register_nadcon_grid_sync(key: &str, las: DataView, los: DataView) -> WasmResult<()> {
+    global().add_nadcon_grid(key, &view_bytes(&las), &view_bytes(&los))?;
+
+    Ok(())
+}
+
+/// Copy the bytes a [DataView] covers, which need not be its whole buffer.
+pub(crate) fn view_bytes(view: &DataView) -> Vec<u8> {
+    Uint8Array::new_with_byte_offset_and_length(
+        &view.buffer(),
+        view.byte_offset() as u32,
+        view.byte_length() as u32,
+    )
+    .to_vec()
+}
+
+/// [Unstable] Register a grid behind a URL which is read on demand with HTTP range requests.
+/// Only the subgrid headers are fetched here, the values are fetched by [prefetch_grid].
+///
+/// Transformations only use the grid inside prefetched extents, elsewhere the grid is treated
+/// as not covering the point. The server must allow range requests.
+/// The request options of `options` are used for all requests of the grid.
+///
+/// Supported Grid Types:
+///     - `NTv2` (.gsb)
+///     - `GeoTIFF` (.tif) as distributed on the PROJ CDN
+#[wasm_bindgen(js_name = registerLazyGrid)]
+pub async fn register_lazy_grid(
+    key: &str,
+    url: &str,
+    options: Option<JsGridOptions>,
+) -> WasmResult<()> {
+    let options = GridOptions::from_js(options)?;
+    global().fetch_lazy_grid(key, url, options).await?;
+
+    Ok(())
+}
+
+/// Fetch the parts of a grid registered with `registerLazyGrid` covering an
+/// `[west, south, east, north]` extent in radians. Grids registered in full are left as is.
+#[wasm_bindgen(js_name = prefetchGrid)]
+pub async fn prefetch_grid(key: &str, extent: Vec<f64>) -> WasmResult<()> {
+    global().prefetch(key, extent).await?;
+
+    Ok(())
+}
+
+/// Crop a registered grid to the `[west, south, east, north]` extent in radians, keeping only
+/// the subgrids and nodes covering it and a margin of two nodes around it for interpolation.
+/// Transformations inside the extent give the same results as with the whole grid.
+///
+/// Grids registered with `registerLazyGrid` can not be cropped, they only fetch prefetched extents.
+#[wasm_bindgen(js_name = cropGrid)]
+pub fn crop_grid(key: &str, bbox: Vec<f64>) -> WasmResult<()> {
+    global().crop(key, extent(bbox)?)
+}
+
+/// Write a registered grid as a file in another format: `"ntv2"` for horizontal offset grids,
+/// `"gtx"` for single band grids without subgrids or `"geotiff"` for any grid.
+///
+/// Grids registered with `registerLazyGrid` can not be exported.
+#[wasm_bindgen(js_name = exportGrid)]
+pub fn export_grid(key: &str, format: &str) -> WasmResult<Vec<u8>> {
+    global().export(key, format.parse()?)
+}
+
+/// The keys of all registered grids
+#[wasm_bindgen(js_name = listGrids)]
+pub fn list_grids() -> Vec<String> {
+    global().keys()
+}
+
+/// A description of a registered grid: format, extent, resolution, subgrids and size
+#[wasm_bindgen(js_name = gridInfo)]
+pub fn grid_info(key: &str) -> WasmResult<GridInfo> {
+    global().info(key)
+}
+
+/// For each coordinate, the index of the finest subgrid of a registered grid covering it
+/// (see `gridInfo(key).subgrids`), or `-1` outside the grid.
+///
+/// Only points between the outermost nodes are covered, while `gridshift` also extrapolates
+/// half a cell beyond them. Lazily registered grids report coverage whether prefetched or not.
+#[wasm_bindgen(js_name = gridCoverage)]
+pub fn grid_coverage(key: &str, coordinates: &Coordinates) -> WasmResult<Vec<i32>> {
+    global().coverage(key, coordinates)
+}
+
+/// Interpolate a registered grid at the pixel centres of a `width` x `height` raster covering the
+/// `[west, south, east, north]` extent in radians, eg to render its coverage and offsets as a
+/// map overlay. Rows run north to south.
+///
+/// Grids registered with `registerLazyGrid` only have values where prefetched.
+#[wasm_bindgen(js_name = sampleGrid)]
+pub fn sample_grid(
+    key: &str,
+    bbox: Vec<f64>,
+    width: usize,
+    height: usize,
+) -> WasmResult<GridSample> {
+    global().sample(key, extent(bbox)?, width, height)
+}
+
+/// Remove a grid from the registry, returning whether it was registered.
+///
+/// [Geo] instances using the grid will fail with a `MissingGridError` on their next
+/// transformation unless the grid was optional (`@key`).
+#[wasm_bindgen(js_name = unregisterGrid)]
+pub fn unregister_grid(key: &str) -> bool {
+    global().remove(key)
+}
+
+/// Remove all grids from the registry
+#[wasm_bindgen(js_name = clearGrids)]
+pub fn clear_grids() {
+    global().clear();
+}
+
+/// The memory used by the values of all registered grids, in bytes
+#[wasm_bindgen(js_name = gridMemoryUsage)]
+pub fn grid_memory_usage() -> usize {
+    global().byte_size()
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    pub(crate) use ntv2::tests::file as ntv2_file;
+
+    /// A Gravsoft geoid over 54N..55N, 8E..10E with heights 40, 41, 42 in the north row
+    /// and 30, 31, 32 in the south row
+    pub(crate) const GEOID: &[u8] = b"54 55 8 10 1 1\n 40 41 42\n 30 31 32\n";
+
+    /// Gravsoft offsets in arc seconds over the same nodes as [GEOID]
+    pub(crate) const OFFSETS: &[u8] = b"54 55 8 10 1 1\n 1 10 2 20 3 30\n 4 40 5 50 6 60\n";
+
+    /// A grid registered globally until dropped, also when a test fails
+    pub(crate) struct Registered(String);
+
+    impl Drop for Registered {
+        fn drop(&mut self) {
+            unregister_grid(&self.0);
+        }
+    }
+
+    /// Register `bytes` globally as `key` for the rest of the scope
+    pub(crate) fn register(key: &str, bytes: &[u8]) -> Result<Registered> {
+        global().add_grid(key, bytes.to_vec(), &GridOptions::default())?;
+        Ok(Registered(key.to_string()))
+    }
+
+    #[test]
+    fn registry() -> Result<()> {
+        let before = global().key_generation("registry-geoid");
+        global().add_grid("registry-geoid", GEOID.to_vec(), &GridOptions::default())?;
+        assert!(global().key_generation("registry-geoid") > before);
+        assert!(list_grids().contains(&"registry-geoid".to_string()));
+        assert!(grid_memory_usage() >= 6 * 4);
+
+        let info = grid_info("registry-geoid")?;
+        assert_eq!(info.format(), "Gravsoft");
+        assert_eq!(info.bands(), 1);
+        assert_eq!(info.byte_size(), 6 * 4);
+        let expected = [8_f64, 54., 10., 55.].map(f64::to_radians);
+        assert_eq!(info.extent(), expected.to_vec());
+
+        assert!(unregister_grid("registry-geoid"));
+        assert!(!unregister_grid("registry-geoid"));
+        assert!(global()
+            .grid("registry-geoid", Interpolation::Bilinear)
+            .is_none());
+        assert_eq!(
+            grid_info("registry-geoid").unwrap_err().code(),
+            "MISSING_GRID"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn coverage() -> Result<()> {
+        // 50N..51N, 1W..1E with a child covering 50N..50.5N, 0..0.5W
+        let subgrids = ntv2::tests::file(&[
+            ("PARENT", "NONE", [180_000., 183_600., -3600., 3600.], 3600.),
+            ("CHILD", "PARENT", [180_000., 181_800., 0., 1800.], 1800.),
+        ]);
+        let _subgrids = register("coverage-ntv2", &subgrids)?;
+
+        let points = [(-0.25, 50.25), (0.5, 50.75), (5., 50.)]
+            .into_iter()
+            .flat_map(|(lon, lat): (f64, f64)| [lon.to_radians(), lat.to_radians(), 0., 0.])
+            .collect();
+        let coordinates = Coordinates::new(points)?;
+        assert_eq!(
+            grid_coverage("coverage-ntv2", &coordinates)?,
+            vec![1, 0, -1]
+        );
+        assert_eq!(
+            grid_coverage("coverage-missing", &coordinates)
+                .unwrap_err()
+                .code(),
+            "MISSING_GRID"
+        );
+
+        // Cropping keeps the subgrids around the extent
+        let extent = |e: [f64; 4]| e.map(f64::to_radians).to_vec();
+        crop_grid("coverage-ntv2", extent([-0.3, 50.2, -0.2, 50.3]))?;
+        assert_eq!(
+            grid_coverage("coverage-ntv2", &coordinates)?,
+            vec![1, 0, -1]
+        );
+        assert!(crop_grid("coverage-ntv2", extent([10., 50., 11., 51.])).is_err());
+        assert!(crop_grid("coverage-ntv2", extent([1., 50., 0., 51.])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn export() -> Result<()> {
+        // Gravsoft offsets in arc seconds over 54N..55N, 8E..10E, converted to NTv2 and GeoTIFF
+        let _offsets = register("export-gravsoft", OFFSETS)?;
+        let position = Coor4D::gis(9.5, 54.5, 0., 0.);
+        let expected = global()
+            .grid("export-gravsoft", Interpolation::Bilinear)
+            .unwrap()
+            .at(None, &position, 0.)
+            .unwrap();
+
+        for format in ["ntv2", "geotiff"] {
+            let key = format!("export-{}", format);
+            let bytes = export_grid("export-gravsoft", format)?;
+            let _exported = register(&key, &bytes)?;
+            assert_eq!(
+                grid_info(&key)?.format(),
+                format.parse::<GridFormat>()?.name()
+            );
+            let d = global()
+                .grid(&key, Interpolation::Bilinear)
+                .unwrap()
+                .at(None, &position, 0.)
+                .unwrap();
+            assert!((d[0] - expected[0]).abs() < 1e-12 && (d[1] - expected[1]).abs() < 1e-12);
+        }
+
+        // GTX only holds single band grids
+        assert!(export_grid("export-gravsoft", "gtx").is_err());
+        assert!(export_grid("export-gravsoft", "gravsoft").is_err());
+        assert!(export_grid("export-missing", "ntv2").is_err());
+        Ok(())
+    }
+}
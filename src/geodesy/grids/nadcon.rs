@@ -0,0 +1,140 @@
+//! NADCON (`.las`/`.los`) horizontal offset grids.
+//!
+//! Latitude and longitude offsets come as two little endian files with the same layout:
+//! records of `cols + 1` 4 byte words, the first holding the header
+//! (`cols rows bands` as `i32` at byte 64, then `lon_w dlon lat_s dlat` as `f32` degrees),
+//! then one record per row from south to north, each starting with a padding word.
+//! Offsets are `f32` arc seconds, with longitude offsets positive west.
+use super::{
+    binary::Binary,
+    raster::{GridHeader, GridSet, RasterGrid},
+};
+use crate::error::Result;
+
+struct Shifts {
+    header: GridHeader,
+    /// Arc seconds, rows north to south
+    values: Vec<f32>,
+}
+
+fn read_shifts(bytes: &[u8], name: &'static str) -> Result<Shifts> {
+    let file = Binary::new(bytes, true, name);
+    let (cols, rows) = (file.i32(64)?, file.i32(68)?);
+    if cols <= 0 || rows <= 0 {
+        return Err(file.invalid("the grid has no nodes"));
+    }
+    let (cols, rows) = (cols as usize, rows as usize);
+    let (lon_w, dlon) = (file.f32(76)? as f64, file.f32(80)? as f64);
+    let (lat_s, dlat) = (file.f32(84)? as f64, file.f32(88)? as f64);
+    if !(dlat > 0. && dlon > 0.) {
+        return Err(file.invalid("node spacing must be positive"));
+    }
+
+    // The header record and one per row, each with a padding word
+    file.nodes(0, rows + 1, cols + 1, 4)?;
+    let record = 4 * (cols + 1);
+    let mut values = vec![0_f32; rows * cols];
+    for row in 0..rows {
+        let offset = (row + 1) * record + 4;
+        for col in 0..cols {
+            values[(rows - 1 - row) * cols + col] = file.f32(offset + 4 * col)?;
+        }
+    }
+
+    let header = GridHeader {
+        lat_n: (lat_s + dlat * (rows - 1) as f64).to_radians(),
+        lon_w: lon_w.to_radians(),
+        dlat: dlat.to_radians(),
+        dlon: dlon.to_radians(),
+        rows,
+        cols,
+        bands: 2,
+    };
+    Ok(Shifts { header, values })
+}
+
+/// Combine the latitude (`.las`) and longitude (`.los`) offset files into one grid
+pub fn read(las: &[u8], los: &[u8]) -> Result<GridSet> {
+    let lat = read_shifts(las, "NADCON .las")?;
+    let lon = read_shifts(los, "NADCON .los")?;
+    if lat.header != lon.header {
+        return Err(Binary::new(los, true, "NADCON")
+            .invalid("the .las and .los files cover different grids"));
+    }
+
+    let arcsec = (1. / 3600_f64).to_radians();
+    let values = lat
+        .values
+        .iter()
+        .zip(&lon.values)
+        .flat_map(|(dlat, dlon)| {
+            [
+                (*dlat as f64 * arcsec) as f32,
+                (-*dlon as f64 * arcsec) as f32,
+            ]
+        })
+        .collect();
+    Ok(GridSet::single(RasterGrid::new(lat.header, values)?))
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_eq::assert_float_eq;
+    use geodesy_rs::authoring::{Coor4D, Grid};
+
+    const ARCSEC: f64 = 4.848_136_811_095_36e-6;
+
+    // 2x24 nodes from 50N..51N, 1W..4.75E with values counting up from the south west corner.
+    // The header needs at least 24 columns to fit in the first record.
+    fn shifts(scale: f32) -> Vec<u8> {
+        let mut file = vec![b' '; 64];
+        for v in [24_i32, 2, 1] {
+            file.extend(v.to_le_bytes());
+        }
+        for v in [-1_f32, 0.25, 50., 1., 0.] {
+            file.extend(v.to_le_bytes());
+        }
+        file.resize(4 * 25, 0);
+        for row in 0..2 {
+            file.extend([0; 4]);
+            for col in 0..24 {
+                file.extend((scale * (24 * row + col) as f32).to_le_bytes());
+            }
+        }
+        file
+    }
+
+    #[test]
+    fn read_grid() -> Result<()> {
+        let grid = read(&shifts(1.), &shifts(10.))?;
+        assert_eq!(grid.bands(), 2);
+
+        let d = grid.at(None, &Coor4D::gis(4.75, 51., 0., 0.), 0.).unwrap();
+        assert_float_eq!(d[0], 47. * ARCSEC, abs <= 1e-10);
+        assert_float_eq!(d[1], -470. * ARCSEC, abs <= 1e-9);
+        let d = grid
+            .at(None, &Coor4D::gis(-0.875, 50., 0., 0.), 0.)
+            .unwrap();
+        assert_float_eq!(d[0], 0.5 * ARCSEC, abs <= 1e-12);
+
+        let mut other = shifts(10.);
+        other[84..88].copy_from_slice(&49_f32.to_le_bytes());
+        assert!(read(&shifts(1.), &other).is_err());
+        assert!(read(&shifts(1.), &shifts(1.)[..40]).is_err());
+
+        // Headers promising more nodes than the file holds fail before allocating them
+        let mut bogus = shifts(1.);
+        bogus[68..72].copy_from_slice(&i32::MAX.to_le_bytes());
+        assert!(read(&bogus, &bogus)
+            .unwrap_err()
+            .to_string()
+            .contains("too short"));
+        let mut flat = shifts(1.);
+        flat[88..92].copy_from_slice(&0_f32.to_le_bytes());
+        assert!(read(&flat, &flat).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use super::{
+    extent, registry::Registry, view_bytes, GridInfo, GridOptions, GridSample, JsGridOptions,
+};
+use crate::{
+    error::WasmResult,
+    geodesy::{blobs::Blobs, coordinate::Coordinates},
+};
+use js_sys::{DataView, Promise};
+use std::sync::Arc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::future_to_promise;
+
+/// A set of grids and blobs isolated from the global registry and other namespaces,
+/// eg for different projects or tenants in the same page.
+///
+/// [Geo](crate::geodesy::context::Geo) instances given the namespace with `Geo.useGrids`
+/// look grids and blobs up here first and fall back to the ones registered with
+/// `registerGridSync`, `registerBlobSync` and friends.
+/// The methods work like their global counterparts.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Default)]
+pub struct GridNamespace {
+    registry: Arc<Registry>,
+    blobs: Arc<Blobs>,
+}
+
+impl GridNamespace {
+    pub(crate) fn registry(&self) -> &Arc<Registry> {
+        &self.registry
+    }
+
+    pub(crate) fn blobs(&self) -> &Arc<Blobs> {
+        &self.blobs
+    }
+}
+
+#[wasm_bindgen]
+impl GridNamespace {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> GridNamespace {
+        GridNamespace::default()
+    }
+
+    /// Register a grid from a [DataView], see `registerGridSync`
+    #[wasm_bindgen(js_name = registerGridSync)]
+    pub fn register_grid_sync(
+        &self,
+        key: &str,
+        data_view: DataView,
+        options: Option<JsGridOptions>,
+    ) -> WasmResult<()> {
+        let options = GridOptions::from_js(options)?;
+        self.registry
+            .add_grid(key, view_bytes(&data_view), &options)?;
+
+        Ok(())
+    }
+
+    /// Register a grid from a URL, see `registerGrid`
+    #[wasm_bindgen(js_name = registerGrid)]
+    pub fn register_grid(
+        &self,
+        key: String,
+        url: String,
+        options: Option<JsGridOptions>,
+    ) -> Promise {
+        let registry = self.registry.clone();
+        future_to_promise(async move {
+            let options = GridOptions::from_js(options)?;
+            registry.fetch_grid(&key, &url, &options).await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Register a NADCON grid pair, see `registerNadconGridSync`
+    #[wasm_bindgen(js_name = registerNadconGridSync)]
+    pub fn register_nadcon_grid_sync(
+        &self,
+        key: &str,
+        las: DataView,
+        los: DataView,
+    ) -> WasmResult<()> {
+        self.registry
+            .add_nadcon_grid(key, &view_bytes(&las), &view_bytes(&los))?;
+
+        Ok(())
+    }
+
+    /// Register a grid read on demand with range requests, see `registerLazyGrid`
+    #[wasm_bindgen(js_name = registerLazyGrid)]
+    pub fn register_lazy_grid(
+        &self,
+        key: String,
+        url: String,
+        options: Option<JsGridOptions>,
+    ) -> Promise {
+        let registry = self.registry.clone();
+        future_to_promise(async move {
+            let options = GridOptions::from_js(options)?;
+            registry.fetch_lazy_grid(&key, &url, options).await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Fetch the parts of a lazily registered grid covering an extent, see `prefetchGrid`
+    #[wasm_bindgen(js_name = prefetchGrid)]
+    pub fn prefetch_grid(&self, key: String, extent: Vec<f64>) -> Promise {
+        let registry = self.registry.clone();
+        future_to_promise(async move {
+            registry.prefetch(&key, extent).await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Crop a grid in this namespace to an extent, see `cropGrid`
+    #[wasm_bindgen(js_name = cropGrid)]
+    pub fn crop_grid(&self, key: &str, bbox: Vec<f64>) -> WasmResult<()> {
+        self.registry.crop(key, extent(bbox)?)
+    }
+
+    /// Write a grid in this namespace as a file in another format, see `exportGrid`
+    #[wasm_bindgen(js_name = exportGrid)]
+    pub fn export_grid(&self, key: &str, format: &str) -> WasmResult<Vec<u8>> {
+        self.registry.export(key, format.parse()?)
+    }
+
+    /// The keys of the grids in this namespace
+    #[wasm_bindgen(js_name = listGrids)]
+    pub fn list_grids(&self) -> Vec<String> {
+        self.registry.keys()
+    }
+
+    /// A description of a grid in this namespace
+    #[wasm_bindgen(js_name = gridInfo)]
+    pub fn grid_info(&self, key: &str) -> WasmResult<GridInfo> {
+        self.registry.info(key)
+    }
+
+    /// The subgrid of a grid in this namespace covering each coordinate, see `gridCoverage`
+    #[wasm_bindgen(js_name = gridCoverage)]
+    pub fn grid_coverage(&self, key: &str, coordinates: &Coordinates) -> WasmResult<Vec<i32>> {
+        self.registry.coverage(key, coordinates)
+    }
+
+    /// Interpolate a grid in this namespace at the pixels of a raster, see `sampleGrid`
+    #[wasm_bindgen(js_name = sampleGrid)]
+    pub fn sample_grid(
+        &self,
+        key: &str,
+        bbox: Vec<f64>,
+        width: usize,
+        height: usize,
+    ) -> WasmResult<GridSample> {
+        self.registry.sample(key, extent(bbox)?, width, height)
+    }
+
+    /// Remove a grid from this namespace, returning whether it was registered
+    #[wasm_bindgen(js_name = unregisterGrid)]
+    pub fn unregister_grid(&self, key: &str) -> bool {
+        self.registry.remove(key)
+    }
+
+    /// Remove all grids from this namespace
+    #[wasm_bindgen]
+    pub fn clear(&self) {
+        self.registry.clear();
+    }
+
+    /// The memory used by the values of the grids in this namespace, in bytes
+    #[wasm_bindgen(js_name = memoryUsage)]
+    pub fn memory_usage(&self) -> usize {
+        self.registry.byte_size()
+    }
+
+    /// Register a blob in this namespace, see `registerBlobSync`
+    #[wasm_bindgen(js_name = registerBlobSync)]
+    pub fn register_blob_sync(&self, key: &str, bytes: Vec<u8>) {
+        self.blobs.insert(key, bytes);
+    }
+
+    /// Register a blob in this namespace from a URL, see `registerBlob`
+    #[wasm_bindgen(js_name = registerBlob)]
+    pub fn register_blob(
+        &self,
+        key: String,
+        url: String,
+        options: Option<JsGridOptions>,
+    ) -> Promise {
+        let blobs = self.blobs.clone();
+        future_to_promise(async move {
+            let options = GridOptions::from_js(options)?;
+            blobs.fetch(&key, &url, &options).await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// The keys of the blobs in this namespace
+    #[wasm_bindgen(js_name = listBlobs)]
+    pub fn list_blobs(&self) -> Vec<String> {
+        self.blobs.keys()
+    }
+
+    /// Remove a blob from this namespace, returning whether it was registered
+    #[wasm_bindgen(js_name = unregisterBlob)]
+    pub fn unregister_blob(&self, key: &str) -> bool {
+        self.blobs.remove(key)
+    }
+
+    /// Remove all blobs from this namespace
+    #[wasm_bindgen(js_name = clearBlobs)]
+    pub fn clear_blobs(&self) {
+        self.blobs.clear();
+    }
+
+    /// The memory used by the blobs in this namespace, in bytes
+    #[wasm_bindgen(js_name = blobMemoryUsage)]
+    pub fn blob_memory_usage(&self) -> usize {
+        self.blobs.byte_size()
+    }
+}
@@ -0,0 +1,134 @@
+//! NTv1 (`.dac`) horizontal offset grids.
+//!
+//! The predecessor of NTv2 with a single big endian grid of `f64` offsets in arc seconds.
+//! Like NTv2, longitudes are positive west and nodes run from the south east corner,
+//! westwards then northwards.
+use super::{
+    binary::Binary,
+    raster::{GridHeader, GridSet, RasterGrid},
+};
+use crate::error::Result;
+
+const RECORD: usize = 16;
+const HEADER: usize = 12 * RECORD;
+const NODE: usize = 2 * 8;
+
+pub fn read(bytes: &[u8]) -> Result<GridSet> {
+    let file = Binary::new(bytes, false, "NTv1");
+    if file.i32(8)? != 12 {
+        return Err(file.invalid("not an NTv1 file"));
+    }
+    let value = |record: usize| file.f64(record * RECORD + 8);
+    let (s_lat, n_lat) = (value(1)?, value(2)?);
+    let (e_long, w_long) = (value(3)?, value(4)?);
+    let (lat_inc, long_inc) = (value(5)?, value(6)?);
+    if !(lat_inc > 0. && long_inc > 0. && n_lat >= s_lat && w_long >= e_long) {
+        return Err(file.invalid("inconsistent header"));
+    }
+    // Bounded by the file length first, so the counts can not overflow
+    let nodes = |span: f64, step: f64| {
+        let steps = (span / step).round();
+        (steps < bytes.len() as f64)
+            .then(|| steps as usize + 1)
+            .ok_or_else(|| file.invalid("the file is too short for the extent of its header"))
+    };
+    let (rows, cols) = (
+        nodes(n_lat - s_lat, lat_inc)?,
+        nodes(w_long - e_long, long_inc)?,
+    );
+
+    let arcsec = (1. / 3600_f64).to_radians();
+    let nodes = file.nodes(HEADER, rows, cols, NODE)?;
+    let mut values = vec![0_f32; 2 * nodes];
+    for i in 0..nodes {
+        let node = HEADER + i * NODE;
+        let row = rows - 1 - i / cols;
+        let col = cols - 1 - i % cols;
+        let index = 2 * (row * cols + col);
+        values[index] = (file.f64(node)? * arcsec) as f32;
+        values[index + 1] = (-file.f64(node + 8)? * arcsec) as f32;
+    }
+
+    let header = GridHeader {
+        lat_n: n_lat * arcsec,
+        lon_w: -w_long * arcsec,
+        dlat: lat_inc * arcsec,
+        dlon: long_inc * arcsec,
+        rows,
+        cols,
+        bands: 2,
+    };
+    Ok(GridSet::single(RasterGrid::new(header, values)?))
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geodesy::grids::{GridOptions, Interpolation, Registry};
+    use float_eq::assert_float_eq;
+    use geodesy_rs::authoring::{Coor4D, Grid};
+
+    const ARCSEC: f64 = 4.848_136_811_095_36e-6;
+
+    fn record(file: &mut Vec<u8>, key: &str, value: [u8; 8]) {
+        file.extend(format!("{:<8}", key).as_bytes());
+        file.extend(value);
+    }
+
+    /// An NTv1 file with the header records of real files, over 50N..51N, 1E..1W in seconds,
+    /// positive west
+    fn file() -> Vec<u8> {
+        let mut file = Vec::new();
+        let mut count = [0; 8];
+        count[..4].copy_from_slice(&12_i32.to_be_bytes());
+        record(&mut file, "HEADER", count);
+        let extent = [180_000_f64, 183_600., -3600., 3600., 3600., 3600.];
+        for (key, value) in ["S LAT", "N LAT", "E LONG", "W LONG", "N GRID", "W GRID"]
+            .into_iter()
+            .zip(extent)
+        {
+            record(&mut file, key, value.to_be_bytes());
+        }
+        record(&mut file, "TYPE", *b"SECONDS ");
+        record(&mut file, "FROM", *b"NAD27   ");
+        record(&mut file, "TO", *b"NAD83   ");
+        record(&mut file, "VERSION", *b"NTv1.0  ");
+        record(&mut file, "", [0; 8]);
+        for i in 0..6 {
+            file.extend((i as f64).to_be_bytes());
+            file.extend((10. * i as f64).to_be_bytes());
+        }
+        file
+    }
+
+    #[test]
+    fn read_grid() -> Result<()> {
+        let file = file();
+        assert_eq!(file.len(), HEADER + 6 * NODE);
+        let grid = read(&file)?;
+        assert_eq!(grid.bands(), 2);
+        // The south east corner is the first node, so the north east one is the fourth
+        let d = grid.at(None, &Coor4D::gis(1., 51., 0., 0.), 0.).unwrap();
+        assert_float_eq!(d[0], 3. * ARCSEC, abs <= 1e-12);
+        assert_float_eq!(d[1], -30. * ARCSEC, abs <= 1e-10);
+
+        assert!(read(&file[..file.len() - 8]).is_err());
+        // Extents promising more nodes than the file holds fail before allocating them
+        for n_lat in [1e12, 1e300] {
+            let mut bogus = file.clone();
+            bogus[2 * RECORD + 8..3 * RECORD].copy_from_slice(&f64::to_be_bytes(n_lat));
+            assert!(read(&bogus).unwrap_err().to_string().contains("too short"));
+        }
+
+        // Detected and read when registered without a format
+        let registry = Registry::default();
+        registry.add_grid("ntv1", file, &GridOptions::default())?;
+        assert_eq!(registry.info("ntv1")?.format(), "NTv1");
+        let grid = registry.grid("ntv1", Interpolation::Bilinear).unwrap();
+        let d = grid.at(None, &Coor4D::gis(1., 51., 0., 0.), 0.).unwrap();
+        assert_float_eq!(d[0], 3. * ARCSEC, abs <= 1e-12);
+        Ok(())
+    }
+}
@@ -0,0 +1,353 @@
+//! NTv2 (`.gsb`) horizontal offset grids.
+//!
+//! Longitudes and longitude offsets are positive west in the file, nodes run from the
+//! south east corner, westwards then northwards. Each subgrid names its parent.
+use super::{
+    binary::Binary,
+    raster::{GridHeader, GridSet, RasterGrid},
+};
+use crate::error::{Error, Result};
+use geodesy_rs::authoring::Grid;
+
+const RECORD: usize = 16;
+/// The size of the file header and of each subgrid header
+pub(crate) const HEADER: usize = 11 * RECORD;
+/// The size of each node
+pub(crate) const NODE: usize = 4 * 4;
+
+pub fn read(bytes: &[u8]) -> Result<GridSet> {
+    let ntv2 = Ntv2::new(bytes)?;
+    let file = Binary::new(bytes, ntv2.little, "NTv2");
+
+    let mut grids = Vec::new();
+    let mut names = Vec::new();
+    let mut parent_names = Vec::new();
+    let mut offset = HEADER;
+    for _ in 0..ntv2.subgrids {
+        let subgrid = ntv2.subgrid(file.slice(offset, HEADER)?)?;
+        offset += HEADER;
+        let nodes = file.nodes(offset, subgrid.header.rows, subgrid.header.cols, NODE)?;
+        let length = nodes * NODE;
+        grids.push(ntv2.values(&subgrid.header, file.slice(offset, length)?)?);
+        offset += length;
+        names.push(subgrid.name);
+        parent_names.push(subgrid.parent);
+    }
+
+    let parents = parent_names
+        .iter()
+        .map(|parent| names.iter().position(|name| name == parent))
+        .collect();
+    let names = names.into_iter().map(Some).collect();
+    Ok(GridSet::new(grids, parents)?.with_names(names))
+}
+
+/// Write a horizontal offset grid as a little endian NTv2 file in seconds.
+/// Nothing is known about the ellipsoids, so their axes are left as zero.
+pub fn write(grid: &GridSet) -> Result<Vec<u8>> {
+    if grid.bands() != 2 {
+        return Err(Error::Invalid(format!(
+            "NTv2: only horizontal offset grids can be written, not grids of {} band(s)",
+            grid.bands()
+        )));
+    }
+    let seconds = |radians: f64| radians.to_degrees() * 3600.;
+    let names = grid.unique_names(8);
+
+    let mut file = Vec::new();
+    int(&mut file, "NUM_OREC", 11);
+    int(&mut file, "NUM_SREC", 11);
+    int(&mut file, "NUM_FILE", grid.grids().len() as i32);
+    text(&mut file, "GS_TYPE", "SECONDS");
+    text(&mut file, "VERSION", "NTv2.0");
+    for key in ["SYSTEM_F", "SYSTEM_T"] {
+        text(&mut file, key, "");
+    }
+    for key in ["MAJOR_F", "MINOR_F", "MAJOR_T", "MINOR_T"] {
+        real(&mut file, key, 0.);
+    }
+
+    for (i, subgrid) in grid.grids().iter().enumerate() {
+        let h = subgrid.header();
+        let parent = grid.parents()[i].map_or("NONE", |parent| &names[parent]);
+        text(&mut file, "SUB_NAME", &names[i]);
+        text(&mut file, "PARENT", parent);
+        text(&mut file, "CREATED", "");
+        text(&mut file, "UPDATED", "");
+        real(&mut file, "S_LAT", seconds(h.lat_s()));
+        real(&mut file, "N_LAT", seconds(h.lat_n));
+        real(&mut file, "E_LONG", -seconds(h.lon_e()));
+        real(&mut file, "W_LONG", -seconds(h.lon_w));
+        real(&mut file, "LAT_INC", seconds(h.dlat));
+        real(&mut file, "LONG_INC", seconds(h.dlon));
+        int(&mut file, "GS_COUNT", (h.rows * h.cols) as i32);
+
+        // From the south east corner, positive west, with unknown accuracies
+        let values = subgrid.nodes();
+        for row in (0..h.rows).rev() {
+            for col in (0..h.cols).rev() {
+                let index = 2 * (row * h.cols + col);
+                let lat = seconds(values[index] as f64) as f32;
+                let lon = -seconds(values[index + 1] as f64) as f32;
+                for v in [lat, lon, -1., -1.] {
+                    file.extend(v.to_le_bytes());
+                }
+            }
+        }
+    }
+    file.extend(format!("{:<8}", "END").as_bytes());
+    file.extend([0; 8]);
+    Ok(file)
+}
+
+fn text(file: &mut Vec<u8>, key: &str, value: &str) {
+    file.extend(format!("{:<8}{:<8}", key, value).as_bytes());
+}
+
+fn int(file: &mut Vec<u8>, key: &str, value: i32) {
+    file.extend(format!("{:<8}", key).as_bytes());
+    file.extend(value.to_le_bytes());
+    file.extend([0; 4]);
+}
+
+fn real(file: &mut Vec<u8>, key: &str, value: f64) {
+    file.extend(format!("{:<8}", key).as_bytes());
+    file.extend(value.to_le_bytes());
+}
+
+/// The overall header of an NTv2 file, for reading its subgrids one at a time
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Ntv2 {
+    little: bool,
+    /// Radians per unit of the file
+    unit: f64,
+    pub subgrids: usize,
+}
+
+/// A subgrid header
+#[derive(Debug, Clone)]
+pub(crate) struct Subgrid {
+    pub name: String,
+    pub parent: String,
+    pub header: GridHeader,
+}
+
+impl Ntv2 {
+    /// Read the file header from (at least) the first [HEADER] bytes
+    pub fn new(bytes: &[u8]) -> Result<Ntv2> {
+        // NUM_OREC is always 11, which tells us the byte order
+        let little = match bytes.get(8..12) {
+            Some([11, 0, 0, 0]) => true,
+            Some([0, 0, 0, 11]) => false,
+            _ => return Err(Binary::new(bytes, true, "NTv2").invalid("not an NTv2 file")),
+        };
+        let file = Binary::new(bytes, little, "NTv2");
+
+        // NUM_FILE, following the record counts
+        let subgrids = file.i32(2 * RECORD + 8)?.max(0) as usize;
+        let unit = match file.text(3 * RECORD + 8, 8)?.as_str() {
+            "SECONDS" => (1. / 3600_f64).to_radians(),
+            "MINUTES" => (1. / 60_f64).to_radians(),
+            "DEGREES" => 1_f64.to_radians(),
+            other => return Err(file.invalid(&format!("unknown GS_TYPE `{}`", other))),
+        };
+        Ok(Ntv2 {
+            little,
+            unit,
+            subgrids,
+        })
+    }
+
+    /// Read a subgrid header from its [HEADER] bytes
+    pub fn subgrid(&self, bytes: &[u8]) -> Result<Subgrid> {
+        let file = Binary::new(bytes, self.little, "NTv2");
+        let value = |record: usize| record * RECORD + 8;
+        let name = file.text(value(0), 8)?;
+        let parent = file.text(value(1), 8)?;
+        let (s_lat, n_lat) = (file.f64(value(4))?, file.f64(value(5))?);
+        let (e_long, w_long) = (file.f64(value(6))?, file.f64(value(7))?);
+        let (lat_inc, long_inc) = (file.f64(value(8))?, file.f64(value(9))?);
+        let count = file.i32(value(10))?.max(0) as usize;
+
+        if !(lat_inc > 0. && long_inc > 0.) {
+            return Err(file.invalid("subgrid increments must be positive"));
+        }
+        // Bounded by the node count first, so the counts can not overflow
+        let nodes = |span: f64, step: f64| {
+            let steps = (span / step).round();
+            (steps >= 0. && steps < count as f64).then(|| steps as usize + 1)
+        };
+        let (rows, cols) = match (
+            nodes(n_lat - s_lat, lat_inc),
+            nodes(w_long - e_long, long_inc),
+        ) {
+            (Some(rows), Some(cols)) => (rows, cols),
+            _ => return Err(file.invalid("subgrid node count does not match its extent")),
+        };
+        if rows.checked_mul(cols) != Some(count) {
+            return Err(file.invalid("subgrid node count does not match its extent"));
+        }
+
+        let header = GridHeader {
+            lat_n: n_lat * self.unit,
+            lon_w: -w_long * self.unit,
+            dlat: lat_inc * self.unit,
+            dlon: long_inc * self.unit,
+            rows,
+            cols,
+            bands: 2,
+        };
+        Ok(Subgrid {
+            name,
+            parent,
+            header,
+        })
+    }
+
+    /// Read the values of a subgrid from the nodes following its header
+    pub fn values(&self, header: &GridHeader, bytes: &[u8]) -> Result<RasterGrid> {
+        let file = Binary::new(bytes, self.little, "NTv2");
+        let (rows, cols) = (header.rows, header.cols);
+
+        let nodes = file.nodes(0, rows, cols, NODE)?;
+        let mut values = vec![0_f32; 2 * nodes];
+        for i in 0..nodes {
+            let node = i * NODE;
+            let row = rows - 1 - i / cols;
+            let col = cols - 1 - i % cols;
+            let index = 2 * (row * cols + col);
+            values[index] = (file.f32(node)? as f64 * self.unit) as f32;
+            values[index + 1] = (-file.f32(node + 4)? as f64 * self.unit) as f32;
+        }
+        RasterGrid::new(*header, values)
+    }
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::geodesy::grids::tests::GEOID;
+    use float_eq::assert_float_eq;
+    use geodesy_rs::authoring::{Coor4D, Grid};
+
+    const ARCSEC: f64 = 4.848_136_811_095_36e-6;
+
+    // A subgrid with nodes counting up from the south east corner
+    fn subgrid(file: &mut Vec<u8>, name: &str, parent: &str, extent: [f64; 4], step: f64) {
+        let [s, n, e, w] = extent;
+        let rows = ((n - s) / step) as usize + 1;
+        let cols = ((w - e) / step) as usize + 1;
+        text(file, "SUB_NAME", name);
+        text(file, "PARENT", parent);
+        text(file, "CREATED", "");
+        text(file, "UPDATED", "");
+        for (key, value) in [("S_LAT", s), ("N_LAT", n), ("E_LONG", e), ("W_LONG", w)] {
+            real(file, key, value);
+        }
+        real(file, "LAT_INC", step);
+        real(file, "LONG_INC", step);
+        int(file, "GS_COUNT", (rows * cols) as i32);
+        for i in 0..rows * cols {
+            for v in [i as f32, 10. * i as f32, 0., 0.] {
+                file.extend(v.to_le_bytes());
+            }
+        }
+    }
+
+    /// An NTv2 file of `(name, parent, [s, n, e, w], step)` subgrids in seconds, positive west
+    pub(crate) fn file(subgrids: &[(&str, &str, [f64; 4], f64)]) -> Vec<u8> {
+        let mut file = Vec::new();
+        int(&mut file, "NUM_OREC", 11);
+        int(&mut file, "NUM_SREC", 11);
+        int(&mut file, "NUM_FILE", subgrids.len() as i32);
+        text(&mut file, "GS_TYPE", "SECONDS");
+        for key in ["VERSION", "SYSTEM_F", "SYSTEM_T"] {
+            text(&mut file, key, "");
+        }
+        for key in ["MAJOR_F", "MINOR_F", "MAJOR_T", "MINOR_T"] {
+            real(&mut file, key, 0.);
+        }
+        for (name, parent, extent, step) in subgrids {
+            subgrid(&mut file, name, parent, *extent, *step);
+        }
+        file
+    }
+
+    fn ntv2() -> Vec<u8> {
+        // 50N..51N, 1E..1W
+        file(&[
+            ("PARENT", "NONE", [180_000., 183_600., -3600., 3600.], 3600.),
+            ("CHILD", "PARENT", [180_000., 181_800., -1800., 0.], 1800.),
+        ])
+    }
+
+    #[test]
+    fn read_subgrids() -> Result<()> {
+        let set = read(&ntv2())?;
+        assert_eq!(set.grids().len(), 2);
+        assert_eq!(set.parents(), &[None, Some(0)]);
+
+        // The south east corner is the first node of the parent, so the north east one is the fourth
+        let d = set.at(None, &Coor4D::gis(1., 51., 0., 0.), 0.).unwrap();
+        assert_float_eq!(d[0], 3. * ARCSEC, abs <= 1e-12);
+        // Between the 30" and 40" positive west offsets of the fourth and fifth nodes
+        let d = set.at(None, &Coor4D::gis(0.75, 51., 0., 0.), 0.).unwrap();
+        assert_float_eq!(d[1], -32.5 * ARCSEC, abs <= 1e-10);
+
+        // The child covers 50N..50.5N, 0..0.5E and starts again from 0
+        let d = set.at(None, &Coor4D::gis(0.5, 50., 0., 0.), 0.).unwrap();
+        assert_float_eq!(d[0], 0., abs <= 1e-12);
+
+        assert!(read(b"NUM_OREC").is_err());
+
+        // Subgrids of 46340 x 46340 nodes without them fail before allocating them
+        let mut bogus = file(&[("HUGE", "NONE", [0., 1., 0., 1.], 1.)]);
+        let record = |k: usize| HEADER + k * RECORD + 8..HEADER + (k + 1) * RECORD;
+        for (k, value) in [(4, 0.), (5, 46_339.), (6, 0.), (7, 46_339.)] {
+            bogus[record(k)].copy_from_slice(&f64::to_le_bytes(value));
+        }
+        bogus[record(10)][..4].copy_from_slice(&(46_340_i32 * 46_340).to_le_bytes());
+        assert!(read(&bogus).unwrap_err().to_string().contains("too short"));
+        Ok(())
+    }
+
+    #[test]
+    fn header_records() -> Result<()> {
+        // As in real files: 11 overview and subgrid records, then the number of subgrids
+        let file = file(&[("ONLY", "NONE", [180_000., 183_600., -3600., 3600.], 3600.)]);
+        let keys: Vec<&[u8]> = file[..48].chunks(RECORD).map(|r| &r[..8]).collect();
+        assert_eq!(keys, [b"NUM_OREC", b"NUM_SREC", b"NUM_FILE"]);
+        let counts: Vec<i32> = file[..48]
+            .chunks(RECORD)
+            .map(|r| i32::from_le_bytes(r[8..12].try_into().unwrap()))
+            .collect();
+        assert_eq!(counts, [11, 11, 1]);
+        assert_eq!(Ntv2::new(&file)?.subgrids, 1);
+        assert_eq!(read(&file)?.grids().len(), 1);
+
+        let set = read(&ntv2())?;
+        assert_eq!(Ntv2::new(&ntv2())?.subgrids, set.grids().len());
+        Ok(())
+    }
+
+    #[test]
+    fn write_subgrids() -> Result<()> {
+        use crate::geodesy::grids::gravsoft;
+        let set = read(&ntv2())?;
+        let written = read(&write(&set)?)?;
+        assert_eq!(written.parents(), &[None, Some(0)]);
+        assert_eq!(written.names(), set.names());
+        for (a, b) in written.grids().iter().zip(set.grids()) {
+            assert_eq!(a.header().rows, b.header().rows);
+            assert_float_eq!(a.header().lon_w, b.header().lon_w, abs <= 1e-15);
+            for (x, y) in a.values().iter().zip(b.values()) {
+                assert_float_eq!(*x, *y, rmax <= 1e-6);
+            }
+        }
+
+        assert!(write(&gravsoft::read(GEOID)?).is_err());
+        Ok(())
+    }
+}
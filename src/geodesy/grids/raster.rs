@@ -0,0 +1,852 @@
+//! Regular geographic rasters and sets of nested subgrids.
+//!
+//! All grids are stored the same way regardless of the file they were read from:
+//! rows run north to south, columns west to east and bands are interleaved per node.
+//! Horizontal offsets are in radians (latitude first, longitude positive east),
+//! vertical offsets in metres and the east, north and up velocities of deformation
+//! models in metres per year. Null nodes are stored as `NaN`.
+use crate::error::{Error, Result};
+use geodesy_rs::authoring::{Context, Coor4D, Grid};
+use std::{borrow::Cow, f64::consts::TAU, fmt, ops::Range, str::FromStr};
+
+// In grid cells
+const EDGE_TOLERANCE: f64 = 1e-9;
+
+/// The georeferencing of a [RasterGrid]. Angles are in radians and refer to node centres.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridHeader {
+    /// Latitude of the northernmost row
+    pub lat_n: f64,
+    /// Longitude of the westernmost column
+    pub lon_w: f64,
+    /// Node spacing in latitude, always positive
+    pub dlat: f64,
+    /// Node spacing in longitude, always positive
+    pub dlon: f64,
+    pub rows: usize,
+    pub cols: usize,
+    pub bands: usize,
+}
+
+impl GridHeader {
+    /// Latitude of the southernmost row
+    pub fn lat_s(&self) -> f64 {
+        self.lat_n - self.dlat * (self.rows - 1) as f64
+    }
+
+    /// Longitude of the easternmost column
+    pub fn lon_e(&self) -> f64 {
+        self.lon_w + self.dlon * (self.cols - 1) as f64
+    }
+
+    // Bring the longitude into the range of the grid if it is a whole turn away
+    fn normalise_lon(&self, lon: f64) -> f64 {
+        if lon < self.lon_w {
+            let wrapped = lon + TAU;
+            if wrapped <= self.lon_e() + self.dlon {
+                return wrapped;
+            }
+        } else if lon > self.lon_e() + self.dlon {
+            let wrapped = lon - TAU;
+            if wrapped >= self.lon_w - self.dlon {
+                return wrapped;
+            }
+        }
+        lon
+    }
+
+    /// Whether `position` is inside the nodes, or within `margin` grid cells of them
+    pub fn contains(&self, position: &Coor4D, margin: f64) -> bool {
+        // Work in (fractional) cells so nodes on the edge are not lost to rounding
+        let col = (self.normalise_lon(position[0]) - self.lon_w) / self.dlon;
+        let row = (self.lat_n - position[1]) / self.dlat;
+
+        let margin = margin + EDGE_TOLERANCE;
+        (-margin..=(self.cols - 1) as f64 + margin).contains(&col)
+            && (-margin..=(self.rows - 1) as f64 + margin).contains(&row)
+    }
+
+    /// The number of columns in a whole turn when the grid covers one and wraps around
+    pub fn period(&self) -> Option<usize> {
+        let period = (TAU / self.dlon).round();
+        ((period * self.dlon - TAU).abs() <= EDGE_TOLERANCE * self.dlon
+            && self.cols >= period as usize)
+            .then_some(period as usize)
+    }
+
+    /// The column holding the values of column `col`, which runs past the last one of grids
+    /// wrapping around (see [GridHeader::window])
+    pub fn wrap_col(&self, col: usize) -> usize {
+        match self.period() {
+            Some(period) if col >= self.cols => col % period,
+            _ => col,
+        }
+    }
+
+    /// The rows and columns of the nodes covering the `[west, south, east, north]` extent in
+    /// radians and `margin` nodes around it, or `None` when they do not overlap.
+    ///
+    /// Longitudes a whole turn away from the grid are brought into it, as when interpolating.
+    /// The columns of grids covering a whole turn may run past the last one to continue from
+    /// the first, so extents across the seam stay in one window. Other grids covering both
+    /// ends of an extent wider than the gap between their edges keep all columns in between.
+    pub fn window(&self, extent: &[f64; 4], margin: usize) -> Option<(Range<usize>, Range<usize>)> {
+        let [west, south, east, north] = *extent;
+        let margin = margin as isize;
+        let range =
+            |from: f64, to: f64| (from.floor() as isize - margin, to.ceil() as isize + margin);
+        let clip = |(first, last): (isize, isize), length: usize| {
+            let (first, last) = (first.max(0), last.min(length as isize - 1));
+            (first <= last).then_some(first as usize..last as usize + 1)
+        };
+        let rows = clip(
+            range(
+                (self.lat_n - north) / self.dlat,
+                (self.lat_n - south) / self.dlat,
+            ),
+            self.rows,
+        )?;
+
+        // Start the extent within a turn east of the western column
+        let (west, east) = {
+            let start = self.lon_w + (west - self.lon_w).rem_euclid(TAU);
+            (start, start + east - west)
+        };
+        let cols = match self.period() {
+            Some(period) => {
+                let (first, last) = range(
+                    (west - self.lon_w) / self.dlon,
+                    (east - self.lon_w) / self.dlon,
+                );
+                if last - first + 1 >= period as isize {
+                    0..self.cols
+                } else {
+                    let start = first.rem_euclid(period as isize) as usize;
+                    start..start + (last - first + 1) as usize
+                }
+            }
+            None => {
+                // The extent overlaps the grid as it is, or a turn to the west
+                let columns = |shift: f64| {
+                    clip(
+                        range(
+                            (west - shift - self.lon_w) / self.dlon,
+                            (east - shift - self.lon_w) / self.dlon,
+                        ),
+                        self.cols,
+                    )
+                };
+                match (columns(0.), columns(TAU)) {
+                    (Some(a), Some(b)) => a.start.min(b.start)..a.end.max(b.end),
+                    (a, b) => a.or(b)?,
+                }
+            }
+        };
+        Some((rows, cols))
+    }
+
+    /// The header of the nodes in `rows` and `cols`
+    pub fn part(&self, rows: &Range<usize>, cols: &Range<usize>) -> GridHeader {
+        GridHeader {
+            lat_n: self.lat_n - rows.start as f64 * self.dlat,
+            lon_w: self.lon_w + cols.start as f64 * self.dlon,
+            rows: rows.len(),
+            cols: cols.len(),
+            ..*self
+        }
+    }
+
+    /// Node spacing as a single number for comparing resolutions
+    pub fn cell_size(&self) -> f64 {
+        self.dlat * self.dlon
+    }
+}
+
+/// How values between nodes are interpolated. Biquadratic and bicubic interpolation fit
+/// Lagrange polynomials through the nearest 3x3 and 4x4 nodes, shifted inwards at the edges.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    #[default]
+    Bilinear,
+    Biquadratic,
+    Bicubic,
+}
+
+impl Interpolation {
+    /// The number of nodes along each axis used for a value
+    fn nodes(self) -> usize {
+        match self {
+            Interpolation::Bilinear => 2,
+            Interpolation::Biquadratic => 3,
+            Interpolation::Bicubic => 4,
+        }
+    }
+}
+
+impl FromStr for Interpolation {
+    type Err = Error;
+
+    fn from_str(interpolation: &str) -> Result<Interpolation> {
+        match interpolation {
+            "bilinear" => Ok(Interpolation::Bilinear),
+            "biquadratic" => Ok(Interpolation::Biquadratic),
+            "bicubic" => Ok(Interpolation::Bicubic),
+            _ => Err(Error::Invalid(format!(
+                "Unknown interpolation `{}`, expected one of bilinear, biquadratic or bicubic",
+                interpolation
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Interpolation::Bilinear => "bilinear",
+            Interpolation::Biquadratic => "biquadratic",
+            Interpolation::Bicubic => "bicubic",
+        };
+        f.write_str(name)
+    }
+}
+
+// The first of `nodes` nodes around the fractional index `x` of an axis with `length` nodes,
+// and the Lagrange weights of each of them
+fn stencil(x: f64, length: usize, nodes: usize) -> (usize, [f64; 4]) {
+    let nodes = nodes.min(length);
+    // Odd stencils centre on the nearest node, even ones on the cell
+    let centre = if nodes % 2 == 1 { x + 0.5 } else { x };
+    let first = (centre.floor() as isize - (nodes as isize - 1) / 2)
+        .clamp(0, (length - nodes) as isize) as usize;
+
+    let mut weights = [0.; 4];
+    for (i, weight) in weights.iter_mut().enumerate().take(nodes) {
+        *weight = (0..nodes)
+            .filter(|j| *j != i)
+            .map(|j| (x - (first + j) as f64) / (i as f64 - j as f64))
+            .product();
+    }
+    (first, weights)
+}
+
+/// Planes added to the stored values of each band, so values growing steadily across a grid,
+/// eg the offsets of a projection, keep their precision in `f32`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trend {
+    /// The longitude and latitude in radians the planes are centred on
+    pub origin: [f64; 2],
+    /// Per band the value at the origin and its change per radian of longitude and latitude
+    pub planes: Vec<[f64; 3]>,
+}
+
+impl Trend {
+    /// The value of `band` at `lon`, `lat` in radians
+    pub fn at(&self, lon: f64, lat: f64, band: usize) -> f64 {
+        let [value, dlon, dlat] = self.planes[band];
+        value + dlon * (lon - self.origin[0]) + dlat * (lat - self.origin[1])
+    }
+}
+
+/// A single regular raster
+#[derive(Debug, Clone)]
+pub struct RasterGrid {
+    header: GridHeader,
+    values: Vec<f32>,
+    trend: Option<Trend>,
+}
+
+impl RasterGrid {
+    pub fn new(header: GridHeader, values: Vec<f32>) -> Result<RasterGrid> {
+        if header.rows == 0 || header.cols == 0 || header.bands == 0 {
+            return Err(Error::Invalid(
+                "Grids must have at least one row, column and band".to_string(),
+            ));
+        }
+        if !(header.dlat > 0. && header.dlon > 0.) {
+            return Err(Error::Invalid(format!(
+                "Grid node spacing must be positive: {} x {}",
+                header.dlat, header.dlon
+            )));
+        }
+        let expected = header.rows * header.cols * header.bands;
+        if values.len() != expected {
+            return Err(Error::Invalid(format!(
+                "Expected {} grid values but found {}",
+                expected,
+                values.len()
+            )));
+        }
+
+        Ok(RasterGrid {
+            header,
+            values,
+            trend: None,
+        })
+    }
+
+    /// A grid of `values` relative to the planes of `trend`
+    pub fn with_trend(header: GridHeader, values: Vec<f32>, trend: Trend) -> Result<RasterGrid> {
+        if trend.planes.len() != header.bands {
+            return Err(Error::Invalid(format!(
+                "Expected a trend for each of {} bands but found {}",
+                header.bands,
+                trend.planes.len()
+            )));
+        }
+        let grid = RasterGrid::new(header, values)?;
+        Ok(RasterGrid {
+            trend: Some(trend),
+            ..grid
+        })
+    }
+
+    pub fn header(&self) -> &GridHeader {
+        &self.header
+    }
+
+    /// The stored values, relative to the trend if any
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    /// The values of all nodes including the trend, as stored in grid files
+    pub fn nodes(&self) -> Cow<'_, [f32]> {
+        let h = &self.header;
+        match self.trend {
+            None => Cow::Borrowed(&self.values),
+            Some(_) => (0..self.values.len())
+                .map(|i| self.value(i / h.bands / h.cols, i / h.bands % h.cols, i % h.bands) as f32)
+                .collect(),
+        }
+    }
+
+    fn value(&self, row: usize, col: usize, band: usize) -> f64 {
+        let h = &self.header;
+        let value = self.values[(row * h.cols + col) * h.bands + band] as f64;
+        match &self.trend {
+            None => value,
+            Some(trend) => {
+                let (lon, lat) = (h.lon_w + col as f64 * h.dlon, h.lat_n - row as f64 * h.dlat);
+                value + trend.at(lon, lat, band)
+            }
+        }
+    }
+
+    /// The part of the grid covering the `[west, south, east, north]` extent in radians
+    /// and `margin` nodes around it, or `None` when they do not overlap.
+    /// Crops of grids covering a whole turn may run across their seam, see [GridHeader::window].
+    pub fn crop(&self, extent: &[f64; 4], margin: usize) -> Option<RasterGrid> {
+        let h = &self.header;
+        let (rows, cols) = h.window(extent, margin)?;
+        let header = h.part(&rows, &cols);
+        let mut values = Vec::with_capacity(header.rows * header.cols * h.bands);
+        for row in rows.clone() {
+            // Columns past the last one of grids wrapping around continue from the first
+            for col in cols.clone() {
+                let start = (row * h.cols + h.wrap_col(col)) * h.bands;
+                values.extend_from_slice(&self.values[start..start + h.bands]);
+            }
+        }
+        let mut grid = RasterGrid {
+            header,
+            values,
+            trend: self.trend.clone(),
+        };
+        // The trend differs a turn away, so those nodes keep their values instead
+        if let Some(trend) = &self.trend {
+            for col in cols.clone().filter(|col| h.wrap_col(*col) != *col) {
+                for (r, row) in rows.clone().enumerate() {
+                    for band in 0..h.bands {
+                        let lon = header.lon_w + (col - cols.start) as f64 * h.dlon;
+                        let lat = header.lat_n - r as f64 * h.dlat;
+                        let value =
+                            self.value(row, h.wrap_col(col), band) - trend.at(lon, lat, band);
+                        let index = (r * header.cols + col - cols.start) * h.bands + band;
+                        grid.values[index] = value as f32;
+                    }
+                }
+            }
+        }
+        Some(grid)
+    }
+
+    /// The values at `position`, or `None` outside the grid and `margin` or next to null nodes
+    pub fn interpolate(
+        &self,
+        position: &Coor4D,
+        margin: f64,
+        interpolation: Interpolation,
+    ) -> Option<Coor4D> {
+        if !self.header.contains(position, margin) {
+            return None;
+        }
+        let h = &self.header;
+        let lon = h.normalise_lon(position[0]);
+        let lat = position[1];
+
+        // Fractional row and column, clamped so points in the margin take the edge values
+        let col = ((lon - h.lon_w) / h.dlon).clamp(0., (h.cols - 1) as f64);
+        let row = ((h.lat_n - lat) / h.dlat).clamp(0., (h.rows - 1) as f64);
+        let nodes = interpolation.nodes();
+        let (c0, wx) = stencil(col, h.cols, nodes);
+        let (r0, wy) = stencil(row, h.rows, nodes);
+
+        let mut result = Coor4D::default();
+        for band in 0..h.bands.min(4) {
+            let mut value = 0.;
+            for (r, wy) in wy.iter().enumerate().take(nodes.min(h.rows)) {
+                for (c, wx) in wx.iter().enumerate().take(nodes.min(h.cols)) {
+                    value += wy * wx * self.value(r0 + r, c0 + c, band);
+                }
+            }
+            result[band] = value;
+        }
+
+        // Any null node among the ones used makes the value unusable
+        if result.0.iter().any(|v| v.is_nan()) {
+            return None;
+        }
+        Some(result)
+    }
+}
+
+impl Grid for RasterGrid {
+    fn bands(&self) -> usize {
+        self.header.bands
+    }
+
+    /// `margin` is in grid cells
+    fn contains(&self, position: &Coor4D, margin: f64, _all: bool) -> bool {
+        self.header.contains(position, margin)
+    }
+
+    fn at(&self, _ctx: Option<&dyn Context>, at: &Coor4D, margin: f64) -> Option<Coor4D> {
+        self.interpolate(at, margin, Interpolation::Bilinear)
+    }
+}
+
+/// The nesting of subgrids, where subgrids refine (parts of) their parent
+#[derive(Debug, Clone)]
+pub struct Hierarchy {
+    parents: Vec<Option<usize>>,
+    /// Ordered finest first
+    children: Vec<Vec<usize>>,
+}
+
+impl Hierarchy {
+    /// `parents[i]` is the index of the subgrid `headers[i]` refines, or `None` for top level grids
+    pub fn new(headers: &[&GridHeader], parents: Vec<Option<usize>>) -> Result<Hierarchy> {
+        if headers.is_empty() {
+            return Err(Error::Invalid(
+                "A grid needs at least one subgrid".to_string(),
+            ));
+        }
+        if headers.len() != parents.len() {
+            return Err(Error::Invalid(
+                "Every subgrid needs a parent entry".to_string(),
+            ));
+        }
+        let bands = headers[0].bands;
+        if headers.iter().any(|h| h.bands != bands) {
+            return Err(Error::Invalid(
+                "All subgrids must have the same number of bands".to_string(),
+            ));
+        }
+
+        let mut children = vec![Vec::new(); headers.len()];
+        for (i, parent) in parents.iter().enumerate() {
+            if let Some(parent) = *parent {
+                if parent >= headers.len() || parent == i {
+                    return Err(Error::Invalid(format!(
+                        "Subgrid {} has an invalid parent {}",
+                        i, parent
+                    )));
+                }
+                children[parent].push(i);
+            }
+        }
+        // Prefer the finest of overlapping siblings
+        let by_resolution = |list: &mut Vec<usize>| {
+            list.sort_by(|a, b| headers[*a].cell_size().total_cmp(&headers[*b].cell_size()))
+        };
+        children.iter_mut().for_each(by_resolution);
+
+        Ok(Hierarchy { parents, children })
+    }
+
+    fn single() -> Hierarchy {
+        Hierarchy {
+            parents: vec![None],
+            children: vec![Vec::new()],
+        }
+    }
+
+    pub fn parents(&self) -> &[Option<usize>] {
+        &self.parents
+    }
+
+    /// The index of the finest subgrid containing a point, where `contains(i, margin)`
+    /// tells whether subgrid `i` contains it
+    pub fn find(&self, contains: impl Fn(usize, f64) -> bool, margin: f64) -> Option<usize> {
+        let mut current = (0..self.parents.len())
+            .filter(|i| self.parents[*i].is_none())
+            .find(|i| contains(*i, margin))?;
+
+        // Descend while a child strictly contains the point. Guard against cycles in bad files.
+        for _ in 0..self.parents.len() {
+            match self.children[current].iter().find(|i| contains(**i, 0.)) {
+                Some(child) => current = *child,
+                None => break,
+            }
+        }
+        Some(current)
+    }
+}
+
+/// A set of grids where subgrids refine (parts of) their parent.
+/// The finest subgrid containing a point is used for interpolation.
+#[derive(Debug, Clone)]
+pub struct GridSet {
+    grids: Vec<RasterGrid>,
+    hierarchy: Hierarchy,
+    names: Vec<Option<String>>,
+}
+
+impl GridSet {
+    /// `parents[i]` is the index of the grid `grids[i]` refines, or `None` for top level grids
+    pub fn new(grids: Vec<RasterGrid>, parents: Vec<Option<usize>>) -> Result<GridSet> {
+        let headers: Vec<&GridHeader> = grids.iter().map(RasterGrid::header).collect();
+        let hierarchy = Hierarchy::new(&headers, parents)?;
+        let names = vec![None; grids.len()];
+        Ok(GridSet {
+            grids,
+            hierarchy,
+            names,
+        })
+    }
+
+    pub fn single(grid: RasterGrid) -> GridSet {
+        GridSet {
+            grids: vec![grid],
+            hierarchy: Hierarchy::single(),
+            names: vec![None],
+        }
+    }
+
+    /// Name the subgrids, as given in the file
+    pub fn with_names(mut self, names: Vec<Option<String>>) -> GridSet {
+        if names.len() == self.grids.len() {
+            self.names = names;
+        }
+        self
+    }
+
+    pub fn grids(&self) -> &[RasterGrid] {
+        &self.grids
+    }
+
+    pub fn parents(&self) -> &[Option<usize>] {
+        self.hierarchy.parents()
+    }
+
+    pub fn names(&self) -> &[Option<String>] {
+        &self.names
+    }
+
+    /// Unique names of at most `length` characters for writing the subgrids, as given in
+    /// the file where possible and numbered otherwise
+    pub fn unique_names(&self, length: usize) -> Vec<String> {
+        let mut names: Vec<String> = Vec::with_capacity(self.grids.len());
+        for (i, name) in self.names.iter().enumerate() {
+            let name = match name {
+                Some(name) if !name.is_empty() && name.len() <= length && !names.contains(name) => {
+                    name.clone()
+                }
+                _ => (i..)
+                    .map(|n| format!("GRID{}", n))
+                    .find(|n| !names.contains(n) && !self.names.contains(&Some(n.clone())))
+                    .unwrap(),
+            };
+            names.push(name);
+        }
+        names
+    }
+
+    /// The approximate heap size of the grid values in bytes
+    pub fn byte_size(&self) -> usize {
+        self.grids
+            .iter()
+            .map(|g| std::mem::size_of_val(g.values()))
+            .sum()
+    }
+
+    /// Only the subgrids, and their nodes, covering the `[west, south, east, north]` extent
+    /// in radians and `margin` nodes around it. Results inside the extent are unchanged.
+    pub fn crop(&self, extent: &[f64; 4], margin: usize) -> Result<GridSet> {
+        let cropped: Vec<Option<RasterGrid>> = self
+            .grids
+            .iter()
+            .map(|grid| grid.crop(extent, margin))
+            .collect();
+
+        // Subgrids move up to their closest remaining ancestor
+        let mut index = vec![None; cropped.len()];
+        let mut kept = 0;
+        for (i, grid) in cropped.iter().enumerate() {
+            if grid.is_some() {
+                index[i] = Some(kept);
+                kept += 1;
+            }
+        }
+        let parents = self.parents();
+        let ancestor = |mut i: usize| {
+            for _ in 0..parents.len() {
+                i = parents[i]?;
+                if index[i].is_some() {
+                    return index[i];
+                }
+            }
+            None
+        };
+        let parents: Vec<Option<usize>> = (0..cropped.len())
+            .filter(|i| index[*i].is_some())
+            .map(ancestor)
+            .collect();
+        let names = (0..cropped.len())
+            .filter(|i| index[*i].is_some())
+            .map(|i| self.names[i].clone())
+            .collect();
+
+        let grids: Vec<RasterGrid> = cropped.into_iter().flatten().collect();
+        if grids.is_empty() {
+            return Err(Error::Invalid(format!(
+                "The grid does not cover {:?}",
+                extent
+            )));
+        }
+        Ok(GridSet::new(grids, parents)?.with_names(names))
+    }
+
+    /// The values of the finest subgrid containing `position`
+    pub fn interpolate(
+        &self,
+        position: &Coor4D,
+        margin: f64,
+        interpolation: Interpolation,
+    ) -> Option<Coor4D> {
+        self.find(position, margin)?
+            .interpolate(position, margin, interpolation)
+    }
+
+    /// The finest subgrid containing `position`
+    pub fn find(&self, position: &Coor4D, margin: f64) -> Option<&RasterGrid> {
+        Some(&self.grids[self.find_index(position, margin)?])
+    }
+
+    /// The index of the finest subgrid containing `position`
+    pub fn find_index(&self, position: &Coor4D, margin: f64) -> Option<usize> {
+        let contains = |i: usize, margin| self.grids[i].header().contains(position, margin);
+        self.hierarchy.find(contains, margin)
+    }
+}
+
+impl Grid for GridSet {
+    fn bands(&self) -> usize {
+        self.grids[0].bands()
+    }
+
+    fn contains(&self, position: &Coor4D, margin: f64, _all: bool) -> bool {
+        self.find(position, margin).is_some()
+    }
+
+    fn at(&self, _ctx: Option<&dyn Context>, at: &Coor4D, margin: f64) -> Option<Coor4D> {
+        self.interpolate(at, margin, Interpolation::Bilinear)
+    }
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_eq::assert_float_eq;
+
+    fn header(lat_n: f64, lon_w: f64, step: f64, rows: usize, cols: usize) -> GridHeader {
+        GridHeader {
+            lat_n: lat_n.to_radians(),
+            lon_w: lon_w.to_radians(),
+            dlat: step.to_radians(),
+            dlon: step.to_radians(),
+            rows,
+            cols,
+            bands: 1,
+        }
+    }
+
+    #[test]
+    fn bilinear() -> Result<()> {
+        // 2x3 nodes from 60N..59N, 10E..12E
+        let grid = RasterGrid::new(header(60., 10., 1., 2, 3), vec![0., 1., 2., 10., 11., 12.])?;
+
+        let at = |lon: f64, lat: f64, margin| {
+            grid.at(None, &Coor4D::gis(lon, lat, 0., 0.), margin)
+                .map(|v| v[0])
+        };
+        assert_float_eq!(at(10., 60., 0.).unwrap(), 0., abs <= 1e-9);
+        assert_float_eq!(at(12., 59., 0.).unwrap(), 12., abs <= 1e-9);
+        assert_float_eq!(at(10.5, 59.5, 0.).unwrap(), 5.5, abs <= 1e-6);
+        assert!(at(12.5, 59.5, 0.).is_none());
+        // In the margin the edge values are used
+        assert_float_eq!(at(12.25, 59.5, 0.5).unwrap(), 7., abs <= 1e-6);
+
+        // Null nodes
+        let grid = RasterGrid::new(header(60., 10., 1., 2, 2), vec![0., f32::NAN, 2., 3.])?;
+        assert!(grid
+            .at(None, &Coor4D::gis(10.5, 59.5, 0., 0.), 0.)
+            .is_none());
+
+        assert!(RasterGrid::new(header(60., 10., 1., 2, 2), vec![0.; 3]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn interpolation() -> Result<()> {
+        // 5x6 nodes from 60N..56N, 10E..15E holding f(x, y) = x^3 - 2xy^2 + y + 1
+        // with x and y the column and row
+        let f = |x: f64, y: f64| x.powi(3) - 2. * x * y.powi(2) + y + 1.;
+        let values = (0..5)
+            .flat_map(|y| (0..6).map(move |x| f(x as f64, y as f64) as f32))
+            .collect();
+        let grid = RasterGrid::new(header(60., 10., 1., 5, 6), values)?;
+
+        let at = |x: f64, y: f64, interpolation| {
+            let position = Coor4D::gis(10. + x, 60. - y, 0., 0.);
+            grid.interpolate(&position, 0., interpolation).unwrap()[0]
+        };
+        // Cubic in x and quadratic in y, so only bicubic interpolation is exact
+        for (x, y) in [(0.25, 0.5), (2.4, 1.7), (4.8, 3.9), (5., 4.)] {
+            assert_float_eq!(at(x, y, Interpolation::Bicubic), f(x, y), abs <= 1e-9);
+        }
+        assert_float_eq!(at(2.4, 2.3, Interpolation::Bilinear), -7.5, abs <= 1e-9);
+        assert_float_eq!(
+            at(2.4, 2.3, Interpolation::Biquadratic),
+            -7.932,
+            abs <= 1e-9
+        );
+        assert_float_eq!(at(2.4, 2.3, Interpolation::Bicubic), -8.268, abs <= 1e-9);
+        // Nodes are reproduced by all of them
+        for interpolation in [Interpolation::Bilinear, Interpolation::Biquadratic] {
+            assert_float_eq!(at(3., 1., interpolation), f(3., 1.), abs <= 1e-9);
+        }
+
+        // Stencils shrink to small grids
+        let grid = RasterGrid::new(header(60., 10., 1., 2, 2), vec![0., 1., 2., 3.])?;
+        let position = Coor4D::gis(10.5, 59.5, 0., 0.);
+        let value = grid.interpolate(&position, 0., Interpolation::Bicubic);
+        assert_float_eq!(value.unwrap()[0], 1.5, abs <= 1e-9);
+
+        assert_eq!("bicubic".parse::<Interpolation>()?, Interpolation::Bicubic);
+        assert!("nearest".parse::<Interpolation>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn crop() -> Result<()> {
+        // 8x10 nodes from 60N..53N, 10E..19E
+        let values = (0..80).map(|v| ((v * 37) % 11) as f32).collect();
+        let grid = RasterGrid::new(header(60., 10., 1., 8, 10), values)?;
+        let extent = [13.2, 55.5, 14.8, 56.5].map(f64::to_radians);
+
+        // The cells 13E..15E and 55N..57N with two more nodes on every side
+        let cropped = grid.crop(&extent, 2).unwrap();
+        assert_eq!((cropped.header().rows, cropped.header().cols), (7, 7));
+        assert_float_eq!(cropped.header().lat_n, 59_f64.to_radians(), abs <= 1e-12);
+        assert_float_eq!(cropped.header().lon_w, 11_f64.to_radians(), abs <= 1e-12);
+
+        for (lon, lat) in [(13.2, 55.5), (14., 56.), (14.8, 56.5), (13.7, 56.3)] {
+            let position = Coor4D::gis(lon, lat, 0., 0.);
+            let original = grid.interpolate(&position, 0., Interpolation::Bicubic);
+            let result = cropped.interpolate(&position, 0., Interpolation::Bicubic);
+            assert_float_eq!(result.unwrap()[0], original.unwrap()[0], abs <= 1e-12);
+        }
+
+        // Margins stop at the edges of the grid
+        let corner = grid
+            .crop(&[9., 59.5, 10.2, 61.].map(f64::to_radians), 2)
+            .unwrap();
+        assert_eq!((corner.header().rows, corner.header().cols), (4, 4));
+        assert!(grid
+            .crop(&[25., 55., 26., 56.].map(f64::to_radians), 2)
+            .is_none());
+
+        // Subgrids outside the extent are dropped
+        let parent = RasterGrid::new(header(60., 10., 1., 3, 3), vec![1.; 9])?;
+        let child = RasterGrid::new(header(59.5, 10.5, 0.25, 3, 3), vec![2.; 9])?;
+        let set = GridSet::new(vec![parent, child], vec![None, Some(0)])?;
+        let cropped = set.crop(&[11.5, 58., 12., 58.2].map(f64::to_radians), 0)?;
+        assert_eq!(cropped.grids().len(), 1);
+        assert_eq!(cropped.parents(), &[None]);
+        let cropped = set.crop(&[10.6, 59.2, 10.7, 59.3].map(f64::to_radians), 0)?;
+        assert_eq!(cropped.parents(), &[None, Some(0)]);
+        assert!(set
+            .crop(&[20., 50., 21., 51.].map(f64::to_radians), 2)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn crop_across_seam() -> Result<()> {
+        // A global geoid from 0E to 360E in 10 degree steps, repeating the first column
+        let f = |lon: f64, lat: f64| (lon.to_radians().sin() + lat / 90.) * 10.;
+        let values = (0..19 * 37)
+            .map(|i| f((i % 37 * 10) as f64, 90. - (i / 37 * 10) as f64) as f32)
+            .collect();
+        let grid = RasterGrid::new(header(90., 0., 10., 19, 37), values)?;
+        assert_eq!(grid.header().period(), Some(36));
+
+        // Negative longitudes, entirely west of the seam and across it
+        for extent in [[-60., 20., -40., 30.], [-15., 40., 25., 60.]] {
+            let cropped = grid.crop(&extent.map(f64::to_radians), 2).unwrap();
+            assert!(cropped.header().cols < 37);
+            for lon in [extent[0], extent[2], (extent[0] + extent[2]) / 2.] {
+                let position = Coor4D::gis(lon, extent[1] + 3., 0., 0.);
+                let original = grid.interpolate(&position, 0., Interpolation::Bilinear);
+                let result = cropped.interpolate(&position, 0., Interpolation::Bilinear);
+                assert_float_eq!(result.unwrap()[0], original.unwrap()[0], abs <= 1e-9);
+            }
+        }
+
+        // Across the seam the columns continue from the first one: 340E..390E
+        let cropped = grid
+            .crop(&[-15., 40., 25., 60.].map(f64::to_radians), 0)
+            .unwrap();
+        assert_eq!((cropped.header().rows, cropped.header().cols), (3, 6));
+        assert_float_eq!(cropped.header().lon_w, 340_f64.to_radians(), abs <= 1e-12);
+        for (col, lon) in [(0, 34), (2, 0), (5, 3)] {
+            assert_eq!(cropped.value(0, col, 0), grid.value(3, lon, 0));
+        }
+
+        // Extents wider than a turn keep the whole grid
+        let cropped = grid.crop(&[-200., 0., 200., 10.].map(f64::to_radians), 0);
+        assert_eq!(cropped.unwrap().header().cols, 37);
+        Ok(())
+    }
+
+    #[test]
+    fn subgrids() -> Result<()> {
+        let parent = RasterGrid::new(header(60., 10., 1., 3, 3), vec![1.; 9])?;
+        let child = RasterGrid::new(header(59.5, 10.5, 0.25, 3, 3), vec![2.; 9])?;
+        let set = GridSet::new(vec![parent, child], vec![None, Some(0)])?;
+
+        let at = |lon: f64, lat: f64| {
+            set.at(None, &Coor4D::gis(lon, lat, 0., 0.), 0.)
+                .map(|v| v[0])
+        };
+        assert_eq!(at(10.75, 59.25), Some(2.));
+        assert_eq!(at(11.5, 58.5), Some(1.));
+        assert_eq!(at(13., 58.5), None);
+
+        assert!(GridSet::new(vec![set.grids()[0].clone()], vec![Some(0)]).is_err());
+        Ok(())
+    }
+}
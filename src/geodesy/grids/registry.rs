@@ -0,0 +1,298 @@
+//! Sets of registered grids by key.
+//!
+//! The global registry backs `registerGridSync` and friends, a [GridNamespace](super::GridNamespace)
+//! holds another one for the [Geo](crate::geodesy::context::Geo) instances using it.
+use super::{
+    geotiff, gravsoft, gtx,
+    info::GridInfo,
+    isg,
+    lazy::{HttpSource, LazyGrid},
+    nadcon, ntv1, ntv2,
+    raster::GridSet,
+    sample::GridSample,
+    GridData, GridEntry, GridFormat, GridOptions, Interpolation,
+};
+use crate::error::{Error, Result};
+use geodesy_rs::authoring::{CoordinateSet, Grid};
+use reqwest::Url;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+/// Nodes kept around cropped extents, enough for bicubic interpolation
+const CROP_MARGIN: usize = 2;
+
+/// Registered grids by key
+#[derive(Debug, Default)]
+pub(crate) struct Registry {
+    grids: Mutex<BTreeMap<String, GridEntry>>,
+    // Bumped on every change
+    generation: AtomicUsize,
+    // The generation each key last changed in, so instantiated definitions using it know to
+    // pick up the change
+    changed: Mutex<BTreeMap<String, usize>>,
+}
+
+// A single store on the heap for the grids not registered in a namespace
+static GRIDS: OnceLock<Arc<Registry>> = OnceLock::new();
+
+/// The registry of the global `registerGrid` functions
+pub(crate) fn global() -> &'static Arc<Registry> {
+    GRIDS.get_or_init(Default::default)
+}
+
+impl Registry {
+    /// The generation `key` was last registered, replaced or removed in, `0` if it never was
+    pub fn key_generation(&self, key: &str) -> usize {
+        self.changed.lock().unwrap().get(key).copied().unwrap_or(0)
+    }
+
+    fn changed<'a>(&self, keys: impl IntoIterator<Item = &'a str>) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut changed = self.changed.lock().unwrap();
+        for key in keys {
+            changed.insert(key.to_string(), generation);
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<GridEntry> {
+        self.grids.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn grid(&self, key: &str, interpolation: Interpolation) -> Option<Arc<dyn Grid>> {
+        self.grids
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|entry| entry.grid.grid(interpolation))
+    }
+
+    pub fn info(&self, key: &str) -> Result<GridInfo> {
+        let entry = self
+            .get(key)
+            .ok_or_else(|| Error::MissingGrid(key.to_string()))?;
+        Ok(GridInfo::new(key, entry.format, &entry.grid))
+    }
+
+    /// The index of the subgrid covering each coordinate, `-1` where none does
+    pub fn coverage(&self, key: &str, coordinates: &dyn CoordinateSet) -> Result<Vec<i32>> {
+        let entry = self
+            .get(key)
+            .ok_or_else(|| Error::MissingGrid(key.to_string()))?;
+        let coverage = (0..coordinates.len())
+            .map(|i| match entry.grid.subgrid_at(&coordinates.get_coord(i)) {
+                Some(index) => index as i32,
+                None => -1,
+            })
+            .collect();
+        Ok(coverage)
+    }
+
+    /// Interpolate a grid at the pixels of a raster, see [GridSample]
+    pub fn sample(
+        &self,
+        key: &str,
+        extent: [f64; 4],
+        width: usize,
+        height: usize,
+    ) -> Result<GridSample> {
+        let grid = self
+            .grid(key, Interpolation::Bilinear)
+            .ok_or_else(|| Error::MissingGrid(key.to_string()))?;
+        GridSample::new(grid.as_ref(), extent, width, height)
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.grids.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// The memory used by the values of all grids, in bytes
+    pub fn byte_size(&self) -> usize {
+        self.grids
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| entry.grid.byte_size())
+            .sum()
+    }
+
+    pub fn insert(&self, key: &str, format: GridFormat, grid: GridData) {
+        let byte_size = grid.byte_size();
+        let entry = GridEntry { format, grid };
+        self.grids.lock().unwrap().insert(key.to_string(), entry);
+        self.changed([key]);
+
+        log::info!("Registered {} grid `{}` ({} bytes)", format, key, byte_size);
+    }
+
+    /// Remove a grid, returning whether it was registered
+    pub fn remove(&self, key: &str) -> bool {
+        let removed = self.grids.lock().unwrap().remove(key).is_some();
+        if removed {
+            self.changed([key]);
+            log::info!("Unregistered grid `{}`", key);
+        }
+        removed
+    }
+
+    pub fn clear(&self) {
+        let keys = std::mem::take(&mut *self.grids.lock().unwrap()).into_keys();
+        self.changed(keys.collect::<Vec<_>>().iter().map(String::as_str));
+        log::info!("Cleared all grids");
+    }
+
+    /// Read a grid file, in the format of `options.format` or detected from the content
+    pub fn add_grid(&self, key: &str, grid_bytes: Vec<u8>, options: &GridOptions) -> Result<()> {
+        let format = match options.format {
+            Some(format) => format,
+            None => GridFormat::detect(&grid_bytes).ok_or_else(|| {
+                Error::Invalid(format!(
+                    "Could not detect the format of grid `{}`, set it with the `format` option",
+                    key
+                ))
+            })?,
+        };
+
+        let grid = match format {
+            GridFormat::Ntv2 => ntv2::read(&grid_bytes)?,
+            GridFormat::Ntv1 => ntv1::read(&grid_bytes)?,
+            GridFormat::Gravsoft => gravsoft::read(&grid_bytes)?,
+            GridFormat::GeoTiff => geotiff::read(&grid_bytes)?,
+            GridFormat::Gtx => gtx::read(&grid_bytes)?,
+            GridFormat::Isg => isg::read(&grid_bytes)?,
+            GridFormat::Nadcon => {
+                return Err(Error::Invalid(format!(
+                    "NADCON grids need both the .las and .los files, register `{}` with `registerNadconGridSync`",
+                    key
+                )))
+            }
+            GridFormat::Baked => {
+                return Err(Error::Invalid(format!(
+                    "Baked grids are made with `Geo.bakeGrid`, not read from files: `{}`",
+                    key
+                )))
+            }
+        };
+        let grid = match options.bbox {
+            Some(bbox) => grid.crop(&bbox, CROP_MARGIN)?,
+            None => grid,
+        };
+        self.insert_grid(key, format, grid);
+        Ok(())
+    }
+
+    /// Keep only the parts of a grid covering an extent
+    pub fn crop(&self, key: &str, extent: [f64; 4]) -> Result<()> {
+        let entry = self
+            .get(key)
+            .ok_or_else(|| Error::MissingGrid(key.to_string()))?;
+        let GridData::Loaded(grid) = entry.grid else {
+            return Err(Error::Invalid(format!(
+                "`{}` is read on demand and can not be cropped, prefetch the extent instead",
+                key
+            )));
+        };
+
+        let cropped = grid.crop(&extent, CROP_MARGIN)?;
+        log::info!(
+            "Cropped grid `{}` to {:?} ({} of {} bytes kept)",
+            key,
+            extent,
+            cropped.byte_size(),
+            grid.byte_size()
+        );
+        self.insert_grid(key, entry.format, cropped);
+        Ok(())
+    }
+
+    /// Write a grid as an NTv2, GTX or GeoTIFF file
+    pub fn export(&self, key: &str, format: GridFormat) -> Result<Vec<u8>> {
+        let entry = self
+            .get(key)
+            .ok_or_else(|| Error::MissingGrid(key.to_string()))?;
+        let GridData::Loaded(grid) = entry.grid else {
+            return Err(Error::Invalid(format!(
+                "`{}` is read on demand and can not be exported, register it in full instead",
+                key
+            )));
+        };
+
+        let bytes = match format {
+            GridFormat::Ntv2 => ntv2::write(&grid)?,
+            GridFormat::Gtx => gtx::write(&grid)?,
+            GridFormat::GeoTiff => geotiff::write(&grid)?,
+            _ => {
+                return Err(Error::Invalid(format!(
+                    "Grids can not be written as {}, only as NTv2, GTX or GeoTIFF",
+                    format
+                )))
+            }
+        };
+        log::info!(
+            "Exported {} grid `{}` as {} ({} bytes)",
+            entry.format,
+            key,
+            format,
+            bytes.len()
+        );
+        Ok(bytes)
+    }
+
+    /// Combine NADCON latitude (`.las`) and longitude (`.los`) offset files into one grid
+    pub fn add_nadcon_grid(&self, key: &str, las: &[u8], los: &[u8]) -> Result<()> {
+        self.insert_grid(key, GridFormat::Nadcon, nadcon::read(las, los)?);
+        Ok(())
+    }
+
+    /// Register a grid sampled from a transformation by [bake](super::bake)
+    pub fn add_baked_grid(&self, key: &str, grid: GridSet) {
+        self.insert_grid(key, GridFormat::Baked, grid);
+    }
+
+    /// Download a grid file in full, through the grid cache if one is set
+    pub async fn fetch_grid(&self, key: &str, url: &str, options: &GridOptions) -> Result<()> {
+        log::debug!("Fetching grid `{}` from {}", key, url);
+        let bytes = super::download(url, options).await?;
+        self.add_grid(key, bytes, options)
+    }
+
+    /// Read the headers of a grid file, leaving its values to [Registry::prefetch]
+    pub async fn fetch_lazy_grid(&self, key: &str, url: &str, options: GridOptions) -> Result<()> {
+        let url = Url::parse(url).map_err(|e| Error::Invalid(format!("{}: {}", e, url)))?;
+        log::debug!("Reading the headers of grid `{}` from {}", key, url);
+        let source = Box::new(HttpSource::new(url, options.request));
+        let (format, grid) = LazyGrid::new(source, options.format).await?;
+        log::debug!("`{}` has {} subgrid(s)", key, grid.headers().len());
+
+        self.insert(key, format, GridData::Lazy(Arc::new(grid)));
+        Ok(())
+    }
+
+    /// Fetch the parts of a lazily read grid covering an `[west, south, east, north]` extent
+    pub async fn prefetch(&self, key: &str, extent: Vec<f64>) -> Result<()> {
+        let extent = super::extent(extent)?;
+        let entry = self
+            .get(key)
+            .ok_or_else(|| Error::MissingGrid(key.to_string()))?;
+
+        if let GridData::Lazy(grid) = entry.grid {
+            grid.prefetch(extent).await?;
+            log::info!(
+                "Prefetched grid `{}` for {:?} ({} bytes loaded)",
+                key,
+                extent,
+                grid.byte_size()
+            );
+        }
+        Ok(())
+    }
+
+    fn insert_grid(&self, key: &str, format: GridFormat, grid: GridSet) {
+        log::debug!("`{}` has {} subgrid(s)", key, grid.grids().len());
+        self.insert(key, format, GridData::Loaded(Arc::new(grid)));
+    }
+}
@@ -0,0 +1,142 @@
+//! Sampling registered grids to rasters, eg for rendering their coverage and offsets as a map overlay.
+use crate::error::{Error, Result, WasmResult};
+use geodesy_rs::authoring::{Coor4D, Grid};
+use wasm_bindgen::prelude::*;
+
+/// The most pixels of a sample, 64 MB of values for two bands
+const MAX_PIXELS: usize = 1 << 23;
+
+/// The values of a grid at the pixel centres of a raster, rows running north to south and
+/// columns west to east. Bands are as in the grid: latitude then longitude offsets in radians
+/// for horizontal grids, offsets in metres for vertical ones.
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct GridSample {
+    width: usize,
+    height: usize,
+    bands: usize,
+    // Interleaved per pixel
+    values: Vec<f32>,
+    mask: Vec<u8>,
+}
+
+impl GridSample {
+    /// Interpolate `grid` at `width` x `height` pixels covering the `[west, south, east, north]`
+    /// extent in radians
+    pub(crate) fn new(
+        grid: &dyn Grid,
+        extent: [f64; 4],
+        width: usize,
+        height: usize,
+    ) -> Result<GridSample> {
+        if width == 0 || height == 0 || width.saturating_mul(height) > MAX_PIXELS {
+            return Err(Error::Invalid(format!(
+                "Grid samples must have between 1 and {} pixels, not {} x {}",
+                MAX_PIXELS, width, height
+            )));
+        }
+        let [west, south, east, north] = extent;
+        let (dx, dy) = (
+            (east - west) / width as f64,
+            (north - south) / height as f64,
+        );
+
+        let bands = grid.bands();
+        let mut values = vec![f32::NAN; width * height * bands];
+        let mut mask = vec![0_u8; width * height];
+        for (pixel, valid) in mask.iter_mut().enumerate() {
+            let lon = west + ((pixel % width) as f64 + 0.5) * dx;
+            let lat = north - ((pixel / width) as f64 + 0.5) * dy;
+            let Some(d) = grid.at(None, &Coor4D::raw(lon, lat, 0., 0.), 0.) else {
+                continue;
+            };
+            for band in 0..bands {
+                values[pixel * bands + band] = d[band] as f32;
+            }
+            *valid = 1;
+        }
+
+        Ok(GridSample {
+            width,
+            height,
+            bands,
+            values,
+            mask,
+        })
+    }
+}
+
+#[wasm_bindgen]
+impl GridSample {
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn bands(&self) -> usize {
+        self.bands
+    }
+
+    /// The values of a band per pixel, `NaN` where the grid has no data
+    #[wasm_bindgen]
+    pub fn band(&self, band: usize) -> WasmResult<Vec<f32>> {
+        if band >= self.bands {
+            return Err(Error::Invalid(format!(
+                "The grid has {} band(s), there is no band {}",
+                self.bands, band
+            )));
+        }
+        Ok(self
+            .values
+            .iter()
+            .skip(band)
+            .step_by(self.bands)
+            .copied()
+            .collect())
+    }
+
+    /// `1` for pixels covered by the grid, `0` elsewhere
+    #[wasm_bindgen(getter)]
+    pub fn mask(&self) -> Vec<u8> {
+        self.mask.clone()
+    }
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geodesy::grids::{
+        extent, global,
+        tests::{register, GEOID},
+    };
+    use float_eq::assert_float_eq;
+
+    #[test]
+    fn sample() -> Result<()> {
+        // A geoid over 54N..55N, 8E..10E, sampled over 54N..56N, 8E..10E
+        let _geoid = register("sample-geoid", GEOID)?;
+        let bbox = extent([8_f64, 54., 10., 56.].map(f64::to_radians).to_vec())?;
+
+        let sample = global().sample("sample-geoid", bbox, 2, 4)?;
+        assert_eq!((sample.width(), sample.height(), sample.bands()), (2, 4, 1));
+        // The two northern rows are outside the grid
+        assert_eq!(sample.mask(), vec![0, 0, 0, 0, 1, 1, 1, 1]);
+        let band = sample.band(0)?;
+        assert!(band[0].is_nan());
+        // The pixel centred on 8.5E 54.75N
+        assert_float_eq!(band[4], 38., abs <= 1e-4);
+        assert!(sample.band(1).is_err());
+
+        assert!(global().sample("sample-geoid", bbox, 0, 4).is_err());
+        assert!(global().sample("sample-missing", bbox, 2, 4).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,381 @@
+//! Estimating Helmert transformations from coordinates known in two reference frames.
+//!
+//! The parameters are fitted by least squares to the small angle (`exact` free) Helmert of the
+//! `helmert` operator in the position vector convention, iterating as the rotations and scale
+//! enter the model as products.
+use super::{adjustment::Adjustment, coordinate::Coordinates};
+use crate::error::{Error::Invalid, Result, WasmResult};
+use geodesy_rs::prelude::*;
+use wasm_bindgen::prelude::*;
+
+/// The `helmert` parameters: translations, rotations, scale and their rates
+const NAMES: [&str; 14] = [
+    "x", "y", "z", "rx", "ry", "rz", "s", "dx", "dy", "dz", "drx", "dry", "drz", "ds",
+];
+
+/// Radians to arc seconds
+const ARCSEC: f64 = 3600. * 180. / std::f64::consts::PI;
+
+#[wasm_bindgen(typescript_custom_section)]
+const HELMERT_OPTIONS: &'static str = r#"
+export interface HelmertOptions {
+    /** Translations (3), translations and scale (4), translations, rotations and scale (7), or those and their rates (14). Defaults to 7. */
+    model?: 3 | 4 | 7 | 14;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "HelmertOptions")]
+    pub type JsHelmertOptions;
+}
+
+/// A Helmert transformation fitted to point pairs.
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct HelmertEstimate {
+    model: usize,
+    // In the units of the `helmert` operator: metres, arc seconds and ppm, per year for rates
+    parameters: Vec<(&'static str, f64)>,
+    definition: String,
+    residuals: Vec<f64>,
+    rms: f64,
+}
+
+#[wasm_bindgen]
+impl HelmertEstimate {
+    #[wasm_bindgen(getter)]
+    pub fn model(&self) -> usize {
+        self.model
+    }
+
+    /// The estimated parameters keyed as in the `helmert` operator, eg `{x, y, z, s}`
+    #[wasm_bindgen(getter)]
+    pub fn parameters(&self) -> js_sys::Object {
+        let object = js_sys::Object::new();
+        for (name, value) in &self.parameters {
+            let _ = js_sys::Reflect::set(&object, &(*name).into(), &(*value).into());
+        }
+        if self.model >= 7 {
+            let _ = js_sys::Reflect::set(&object, &"convention".into(), &"position_vector".into());
+        }
+        object
+    }
+
+    /// A `helmert` step taking the source coordinates to the destination, ready for [Geo]
+    ///
+    /// [Geo]: crate::geodesy::context::Geo
+    #[wasm_bindgen(getter)]
+    pub fn definition(&self) -> String {
+        self.definition.clone()
+    }
+
+    /// Destination minus transformed source coordinates per point, ordered (x, y, z)
+    #[wasm_bindgen(getter)]
+    pub fn residuals(&self) -> Vec<f64> {
+        self.residuals.clone()
+    }
+
+    /// The root mean square of the residual vector lengths in metres
+    #[wasm_bindgen(getter)]
+    pub fn rms(&self) -> f64 {
+        self.rms
+    }
+}
+
+/// Estimates the `helmert` step taking earth centred cartesian `source` coordinates to the
+/// matching `destination` coordinates, in metres.
+///
+/// `options.model` selects the parameters, 7 by default. The 14 parameter model needs the
+/// epoch of each point as its 4th value, and refers its rates to their mean epoch, `t_epoch`.
+#[wasm_bindgen(js_name = estimateHelmert)]
+pub fn estimate_helmert_wasm(
+    source: &Coordinates,
+    destination: &Coordinates,
+    options: Option<JsHelmertOptions>,
+) -> WasmResult<HelmertEstimate> {
+    let model = options
+        .and_then(|options| js_sys::Reflect::get(&options, &JsValue::from_str("model")).ok())
+        .filter(|model| !model.is_undefined() && !model.is_null());
+    let model = match model {
+        Some(model) => match model.as_f64() {
+            Some(model) if model.fract() == 0. && model >= 0. => model as usize,
+            _ => return Err(Invalid("`model` must be 3, 4, 7 or 14".to_string())),
+        },
+        None => 7,
+    };
+    estimate(source, destination, model)
+}
+
+fn estimate(
+    source: &dyn CoordinateSet,
+    destination: &dyn CoordinateSet,
+    model: usize,
+) -> Result<HelmertEstimate> {
+    // Indices into NAMES of the estimated parameters
+    let unknowns: Vec<usize> = match model {
+        3 => vec![0, 1, 2],
+        4 => vec![0, 1, 2, 6],
+        7 => (0..7).collect(),
+        14 => (0..14).collect(),
+        _ => {
+            return Err(Invalid(format!(
+                "Helmert models have 3, 4, 7 or 14 parameters, not {}",
+                model
+            )))
+        }
+    };
+    let n = source.len();
+    if destination.len() != n {
+        return Err(Invalid(format!(
+            "There are {} source and {} destination coordinates",
+            n,
+            destination.len()
+        )));
+    }
+    let from: Vec<Coor4D> = (0..n).map(|i| source.get_coord(i)).collect();
+    let to: Vec<Coor4D> = (0..n).map(|i| destination.get_coord(i)).collect();
+    let finite = |c: &Coor4D, dims: usize| c.0[..dims].iter().all(|v| v.is_finite());
+    let dims = if model == 14 { 4 } else { 3 };
+    if let Some(i) = (0..n).find(|&i| !finite(&from[i], dims) || !finite(&to[i], 3)) {
+        return Err(Invalid(format!("Point {} is not finite", i)));
+    }
+
+    let t_epoch = match model {
+        14 if n > 0 => from.iter().map(|c| c[3]).sum::<f64>() / n as f64,
+        _ => 0.,
+    };
+    let dt = |c: &Coor4D| if model == 14 { c[3] - t_epoch } else { 0. };
+
+    // Rotations and scale are estimated multiplied by the size of the coordinates, so all
+    // columns of the observation equations are of similar magnitude
+    let size = from
+        .iter()
+        .map(|c| (c[0] * c[0] + c[1] * c[1] + c[2] * c[2]).sqrt())
+        .fold(1., f64::max);
+    let scale = |k: usize| if k % 7 < 3 { 1. } else { size };
+
+    let mut p = [0_f64; 14];
+    for _ in 0..10 {
+        let mut adjustment = Adjustment::new(unknowns.len());
+        for (a, b) in from.iter().zip(&to) {
+            let dt = dt(a);
+            let (fitted, jacobian) = helmert(&p, a, dt);
+            for axis in 0..3 {
+                let row: Vec<f64> = unknowns
+                    .iter()
+                    .map(|&k| jacobian[axis][k] / scale(k))
+                    .collect();
+                adjustment.observe(&row, b[axis] - fitted[axis]);
+            }
+        }
+        let correction = adjustment.solve()?;
+        for (&k, delta) in unknowns.iter().zip(&correction) {
+            p[k] += delta / scale(k);
+        }
+        // Metres at the size of the coordinates
+        if correction.iter().all(|delta| delta.abs() < 1e-9) {
+            break;
+        }
+    }
+
+    let mut residuals = Vec::with_capacity(3 * n);
+    let mut sum = 0.;
+    for (a, b) in from.iter().zip(&to) {
+        let (fitted, _) = helmert(&p, a, dt(a));
+        let r = [b[0] - fitted[0], b[1] - fitted[1], b[2] - fitted[2]];
+        sum += r[0] * r[0] + r[1] * r[1] + r[2] * r[2];
+        residuals.extend(r);
+    }
+
+    // To the units of the `helmert` operator
+    let mut parameters: Vec<(&'static str, f64)> = unknowns
+        .iter()
+        .map(|&k| {
+            let value = match k % 7 {
+                3..=5 => p[k] * ARCSEC,
+                6 => p[k] * 1e6,
+                _ => p[k],
+            };
+            (NAMES[k], value)
+        })
+        .collect();
+    if model == 14 {
+        parameters.push(("t_epoch", t_epoch));
+    }
+    let mut definition = String::from("helmert");
+    for (name, value) in &parameters {
+        definition += &format!(" {}={}", name, value);
+    }
+    if model >= 7 {
+        definition += " convention=position_vector";
+    }
+
+    Ok(HelmertEstimate {
+        model,
+        parameters,
+        definition,
+        residuals,
+        rms: (sum / n as f64).sqrt(),
+    })
+}
+
+/// The small angle Helmert of `parameters` (metres, radians and unitless scale, and their
+/// rates per year) applied to `c`, `dt` years from the reference epoch, and its derivatives
+/// with respect to the parameters
+fn helmert(parameters: &[f64; 14], c: &Coor4D, dt: f64) -> ([f64; 3], [[f64; 14]; 3]) {
+    let p: [f64; 7] = std::array::from_fn(|k| parameters[k] + parameters[k + 7] * dt);
+    let (x, y, z) = (c[0], c[1], c[2]);
+    let (rx, ry, rz, s) = (p[3], p[4], p[5], 1. + p[6]);
+    let rotated = [
+        x - rz * y + ry * z,
+        rz * x + y - rx * z,
+        -ry * x + rx * y + z,
+    ];
+    let fitted = [0, 1, 2].map(|axis| p[axis] + s * rotated[axis]);
+
+    let mut jacobian = [[0_f64; 14]; 3];
+    for axis in 0..3 {
+        let row = &mut jacobian[axis];
+        row[axis] = 1.;
+        row[6] = rotated[axis];
+    }
+    jacobian[1][3] = -s * z;
+    jacobian[2][3] = s * y;
+    jacobian[0][4] = s * z;
+    jacobian[2][4] = -s * x;
+    jacobian[0][5] = -s * y;
+    jacobian[1][5] = s * x;
+    for row in jacobian.iter_mut() {
+        for k in 0..7 {
+            row[k + 7] = row[k] * dt;
+        }
+    }
+    (fitted, jacobian)
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geodesy::wasmcontext::WasmContext;
+    use float_eq::assert_float_eq;
+
+    // Points of a continental network in earth centred cartesian coordinates
+    fn network(epochs: [f64; 5]) -> Vec<Coor4D> {
+        let ellps = Ellipsoid::named("GRS80").unwrap();
+        [(10., 55.), (12., 56.), (9., 57.), (15., 55.5), (11., 54.)]
+            .iter()
+            .zip(epochs)
+            .map(|(&(lon, lat), epoch)| {
+                let mut c = ellps.cartesian(&Coor4D::geo(lat, lon, 100., 0.));
+                c[3] = epoch;
+                c
+            })
+            .collect()
+    }
+
+    fn transform(parameters: &[f64; 14], points: &[Coor4D], t_epoch: f64) -> Vec<Coor4D> {
+        points
+            .iter()
+            .map(|c| {
+                let (fitted, _) = helmert(parameters, c, c[3] - t_epoch);
+                Coor4D::raw(fitted[0], fitted[1], fitted[2], c[3])
+            })
+            .collect()
+    }
+
+    // Instantiates the definition of `estimate` and checks that it takes `source` to
+    // `destination` up to the residuals, none further off than the RMS allows
+    fn check_definition(
+        estimate: &HelmertEstimate,
+        source: &[Coor4D],
+        destination: &[Coor4D],
+    ) -> Result<()> {
+        let mut ctx = WasmContext::new();
+        let op = ctx.op(&estimate.definition())?;
+        let mut operands = source.to_vec();
+        ctx.apply(op, Fwd, &mut operands)?;
+        for (i, (fitted, expected)) in operands.iter().zip(destination).enumerate() {
+            let length = (0..3)
+                .map(|axis| (expected[axis] - fitted[axis]).powi(2))
+                .sum::<f64>()
+                .sqrt();
+            let bound = (source.len() as f64).sqrt() * estimate.rms();
+            assert!(length <= bound + 1e-6, "point {}: {}", i, length);
+            for axis in 0..3 {
+                let residual = estimate.residuals()[3 * i + axis];
+                assert_float_eq!(fitted[axis] + residual, expected[axis], abs <= 1e-6);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn seven_parameters() -> Result<()> {
+        let mut truth = [0_f64; 14];
+        truth[..7].copy_from_slice(&[
+            -80.,
+            120.,
+            -40.,
+            1.5 / ARCSEC,
+            -0.3 / ARCSEC,
+            2. / ARCSEC,
+            4.2e-6,
+        ]);
+        let source = network([0.; 5]);
+        let destination = transform(&truth, &source, 0.);
+
+        let estimate = estimate(&source, &destination, 7)?;
+        assert!(estimate.rms() < 1e-6);
+        assert_eq!(estimate.residuals().len(), 15);
+        let expected = [-80., 120., -40., 1.5, -0.3, 2., 4.2];
+        for ((name, value), expected) in estimate.parameters.iter().zip(expected) {
+            assert_float_eq!(*value, expected, abs <= 1e-6, "{}", name);
+        }
+        assert!(estimate.definition().starts_with("helmert x=-80"));
+        assert!(estimate
+            .definition()
+            .ends_with("convention=position_vector"));
+        check_definition(&estimate, &source, &destination)?;
+
+        // Translations alone leave the rotations in the residuals
+        let translations = super::estimate(&source, &destination, 3)?;
+        assert_eq!(translations.parameters.len(), 3);
+        assert!(translations.rms() > 1.);
+        assert!(!translations.definition().contains("convention"));
+        check_definition(&translations, &source, &destination)?;
+        Ok(())
+    }
+
+    #[test]
+    fn fourteen_parameters() -> Result<()> {
+        let mut truth = [0_f64; 14];
+        truth[..7].copy_from_slice(&[0.05, -0.02, 0.1, 0., 0., 0.001 / ARCSEC, 1e-8]);
+        truth[7..].copy_from_slice(&[0.001, 0.002, -0.003, 1e-4 / ARCSEC, 0., 0., 1e-9]);
+        let source = network([2000., 2004., 2008., 2012., 2016.]);
+        let destination = transform(&truth, &source, 2008.);
+
+        let estimate = estimate(&source, &destination, 14)?;
+        assert!(estimate.rms() < 1e-6);
+        let value = |name: &str| {
+            let found = estimate.parameters.iter().find(|p| p.0 == name);
+            found.unwrap().1
+        };
+        assert_float_eq!(value("t_epoch"), 2008., abs <= 1e-12);
+        assert_float_eq!(value("z"), 0.1, abs <= 1e-6);
+        assert_float_eq!(value("dy"), 0.002, abs <= 1e-6);
+        assert_float_eq!(value("drx"), 1e-4, abs <= 1e-5);
+        assert_float_eq!(value("ds"), 1e-3, abs <= 1e-4);
+        check_definition(&estimate, &source, &destination)?;
+
+        // Rates need more than one epoch, and every model enough points
+        let one_epoch = network([2010.; 5]);
+        assert!(super::estimate(&one_epoch, &destination, 14).is_err());
+        assert!(super::estimate(&source[..2].to_vec(), &destination[..2].to_vec(), 7).is_err());
+        assert!(super::estimate(&source, &destination[..4].to_vec(), 3).is_err());
+        assert!(super::estimate(&source, &destination, 6).is_err());
+        Ok(())
+    }
+}
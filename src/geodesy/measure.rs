@@ -0,0 +1,259 @@
+//! Ellipsoidal measurements of polygons.
+//!
+//! Areas are computed on the authalic (equal area) sphere of the ellipsoid, treating each
+//! edge as a great circle arc, which is accurate to well below a square metre for survey sized
+//! polygons. Perimeters are summed geodesic distances on the ellipsoid.
+use super::{coordinate::Coordinates, ellipsoids};
+use crate::error::{Error, Result, WasmResult};
+use geodesy_rs::prelude::*;
+use std::f64::consts::{FRAC_PI_2, PI, TAU};
+use wasm_bindgen::prelude::*;
+
+/// The area (m²) and perimeter (m) of a polygon.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PolygonMeasure {
+    pub area: f64,
+    pub perimeter: f64,
+}
+
+/// Computes the ellipsoidal area and perimeter of a polygon in geographic coordinates.
+///
+/// The [Coordinates] MUST be ordered (longitude, latitude, height, time) in radians.
+/// `ringOffsets` holds the index of the first coordinate of each ring, the first ring being
+/// the exterior and any following rings holes. An empty `ringOffsets` means a single ring.
+/// Rings may be given open or closed (first coordinate repeated at the end).
+///
+/// The ellipsoid defaults to `GRS80`.
+#[wasm_bindgen(js_name = polygonArea)]
+pub fn polygon_area_wasm(
+    coordinates: &Coordinates,
+    ring_offsets: Vec<u32>,
+    ellps: Option<String>,
+) -> WasmResult<PolygonMeasure> {
+    let ellps = ellipsoids::named(ellps.as_deref().unwrap_or("GRS80"))?;
+    let offsets: Vec<usize> = ring_offsets.into_iter().map(|o| o as usize).collect();
+    polygon_measure(coordinates, &offsets, &ellps)
+}
+
+/// Area and perimeter of a polygon with optional holes.
+/// See [polygon_area_wasm] for the layout of `operands` and `ring_offsets`.
+pub fn polygon_measure(
+    operands: &dyn CoordinateSet,
+    ring_offsets: &[usize],
+    ellps: &Ellipsoid,
+) -> Result<PolygonMeasure> {
+    let rings = split_rings(operands, ring_offsets)?;
+
+    let mut area = 0.0;
+    let mut perimeter = 0.0;
+    for (i, ring) in rings.iter().enumerate() {
+        let ring_area = ring_area(ring, ellps).abs();
+        if i == 0 {
+            area += ring_area;
+        } else {
+            area -= ring_area;
+        }
+        perimeter += ring_perimeter(ring, ellps);
+    }
+
+    Ok(PolygonMeasure {
+        area: area.max(0.0),
+        perimeter,
+    })
+}
+
+/// Signed area of a single ring, positive when counter-clockwise.
+pub fn ring_area(ring: &[Coor4D], ellps: &Ellipsoid) -> f64 {
+    let authalic = Authalic::new(ellps);
+    let n = ring.len();
+
+    // The spherical excess of each edge against the equator, after
+    // Chamberlain & Duquette (2007), "Some algorithms for polygons on a sphere".
+    let mut excess = 0.0;
+    for i in 0..n {
+        let a = ring[i];
+        let b = ring[(i + 1) % n];
+
+        let t1 = (authalic.latitude(a[1]) / 2.0).tan();
+        let t2 = (authalic.latitude(b[1]) / 2.0).tan();
+        let dlon = normalise_longitude(b[0] - a[0]);
+
+        excess += 2.0 * ((dlon / 2.0).tan() * (t1 + t2)).atan2(1.0 + t1 * t2);
+    }
+
+    excess * authalic.radius_squared
+}
+
+/// Length of a single ring, including the closing edge.
+pub fn ring_perimeter(ring: &[Coor4D], ellps: &Ellipsoid) -> f64 {
+    let n = ring.len();
+    (0..n)
+        .map(|i| ellps.distance(&ring[i], &ring[(i + 1) % n]))
+        .sum()
+}
+
+fn split_rings(operands: &dyn CoordinateSet, ring_offsets: &[usize]) -> Result<Vec<Vec<Coor4D>>> {
+    let length = operands.len();
+    let offsets = if ring_offsets.is_empty() {
+        &[0][..]
+    } else {
+        ring_offsets
+    };
+
+    if offsets[0] != 0 {
+        return Err(Error::Invalid(
+            "The first ring offset must be 0".to_string(),
+        ));
+    }
+
+    let mut rings = Vec::with_capacity(offsets.len());
+    for (i, &start) in offsets.iter().enumerate() {
+        let end = offsets.get(i + 1).copied().unwrap_or(length);
+        if end <= start || end > length {
+            return Err(Error::Invalid(format!(
+                "Ring offsets must be increasing and within the coordinates: {:?}",
+                ring_offsets
+            )));
+        }
+
+        let mut ring: Vec<Coor4D> = (start..end).map(|j| operands.get_coord(j)).collect();
+        // Drop the closing coordinate of closed rings
+        if ring.len() > 1
+            && ring[0][0] == ring[ring.len() - 1][0]
+            && ring[0][1] == ring[ring.len() - 1][1]
+        {
+            ring.pop();
+        }
+
+        if ring.len() < 3 {
+            return Err(Error::Invalid(format!(
+                "Ring {} has fewer than 3 distinct coordinates",
+                i
+            )));
+        }
+        if ring.iter().any(|c| !c[0].is_finite() || !c[1].is_finite()) {
+            return Err(Error::Invalid(format!(
+                "Ring {} contains non-finite coordinates",
+                i
+            )));
+        }
+        rings.push(ring);
+    }
+
+    Ok(rings)
+}
+
+fn normalise_longitude(lon: f64) -> f64 {
+    let lon = (lon + PI).rem_euclid(TAU) - PI;
+    // Keep the antimeridian on the positive side
+    if lon == -PI {
+        PI
+    } else {
+        lon
+    }
+}
+
+/// The authalic sphere of an ellipsoid.
+struct Authalic {
+    e: f64,
+    es: f64,
+    qp: f64,
+    radius_squared: f64,
+}
+
+impl Authalic {
+    fn new(ellps: &Ellipsoid) -> Authalic {
+        let a = ellps.semimajor_axis();
+        let es = ellps.eccentricity_squared();
+        let e = es.sqrt();
+        let mut authalic = Authalic {
+            e,
+            es,
+            qp: 2.0,
+            radius_squared: a * a,
+        };
+        if es > 0.0 {
+            authalic.qp = authalic.q(1.0);
+            authalic.radius_squared = a * a * authalic.qp / 2.0;
+        }
+        authalic
+    }
+
+    fn q(&self, sinphi: f64) -> f64 {
+        let es = self.es;
+        let e = self.e;
+        let esin = e * sinphi;
+        (1.0 - es) * (sinphi / (1.0 - esin * esin) - ((1.0 - esin) / (1.0 + esin)).ln() / (2.0 * e))
+    }
+
+    fn latitude(&self, phi: f64) -> f64 {
+        if self.es == 0.0 {
+            return phi;
+        }
+        if phi.abs() >= FRAC_PI_2 {
+            return phi.signum() * FRAC_PI_2;
+        }
+        (self.q(phi.sin()) / self.qp).clamp(-1.0, 1.0).asin()
+    }
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_eq::assert_float_eq;
+
+    fn square(size: f64, lon: f64, lat: f64) -> Vec<Coor4D> {
+        vec![
+            Coor4D::gis(lon, lat, 0., 0.),
+            Coor4D::gis(lon + size, lat, 0., 0.),
+            Coor4D::gis(lon + size, lat + size, 0., 0.),
+            Coor4D::gis(lon, lat + size, 0., 0.),
+        ]
+    }
+
+    #[test]
+    fn one_degree_square() -> Result<()> {
+        let ellps = Ellipsoid::named("WGS84")?;
+        let polygon = square(1., 0., 0.);
+
+        let measure = polygon_measure(&polygon, &[], &ellps)?;
+        // Reference values from GeographicLib's PolygonArea
+        assert_float_eq!(measure.area, 12_308_778_361.469, rmax <= 1e-6);
+        assert_float_eq!(measure.perimeter, 443_770.917, abs <= 1e-2);
+
+        // Orientation and closing the ring makes no difference
+        let mut closed: Vec<Coor4D> = polygon.iter().rev().copied().collect();
+        closed.push(closed[0]);
+        let reversed = polygon_measure(&closed, &[], &ellps)?;
+        assert_float_eq!(reversed.area, measure.area, rmax <= 1e-12);
+        assert_float_eq!(reversed.perimeter, measure.perimeter, rmax <= 1e-12);
+        Ok(())
+    }
+
+    #[test]
+    fn holes() -> Result<()> {
+        let ellps = Ellipsoid::named("GRS80")?;
+        let mut polygon = square(0.01, -0.1, 51.5);
+        let hole = square(0.002, -0.095, 51.505);
+        let outer = polygon_measure(&polygon, &[], &ellps)?;
+        let inner = polygon_measure(&hole, &[], &ellps)?;
+
+        polygon.extend(hole);
+        let measure = polygon_measure(&polygon, &[0, 4], &ellps)?;
+        assert_float_eq!(measure.area, outer.area - inner.area, rmax <= 1e-12);
+        assert_float_eq!(
+            measure.perimeter,
+            outer.perimeter + inner.perimeter,
+            rmax <= 1e-12
+        );
+
+        // Bad ring layouts are rejected
+        assert!(polygon_measure(&polygon, &[1, 4], &ellps).is_err());
+        assert!(polygon_measure(&polygon, &[0, 6], &ellps).is_err());
+        assert!(polygon_measure(&polygon, &[0, 9], &ellps).is_err());
+        Ok(())
+    }
+}
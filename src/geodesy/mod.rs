@@ -1,5 +1,10 @@
+mod adjustment;
+mod blobs;
 pub mod context;
 pub mod coordinate;
+pub mod ellipsoids;
 mod grids;
+mod helmert;
+pub mod measure;
 mod operators;
 mod wasmcontext;
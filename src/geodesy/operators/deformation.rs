@@ -0,0 +1,208 @@
+//! Deformation
+//! Time dependent displacements from deformation models like NKG and NZGD2000, after the PROJ
+//! `deformation` operator: the east, north and up velocities of three band `grids`, scaled by
+//! the years between the epoch of each coordinate (its 4th value) and `t_epoch`, or by `dt`.
+//! Without `dt` coordinates with an epoch of 0 or NaN fail.
+//! Input and output are earth centred cartesian coordinates on `ellps`.
+use geodesy_rs::authoring::*;
+
+// The years to displace a coordinate by, or `None` without `dt` for coordinates without an
+// epoch, as the 4th value is zero unless set
+fn years(op: &Op, coord: &Coor4D) -> Option<f64> {
+    let dt = op.params.real("dt").ok()?;
+    let years = if dt.is_nan() {
+        if coord[3] == 0. {
+            return None;
+        }
+        coord[3] - op.params.real("t_epoch").ok()?
+    } else {
+        dt
+    };
+    years.is_finite().then_some(years)
+}
+
+// The cartesian displacement per year at a cartesian coordinate, from the first grid covering it
+fn velocity(op: &Op, ctx: &dyn Context, cartesian: &Coor4D) -> Option<[f64; 3]> {
+    let geographic = op.params.ellps(0).geographic(cartesian);
+    let v = op
+        .params
+        .grids
+        .iter()
+        .find_map(|grid| grid.at(Some(ctx), &geographic, 0.))?;
+
+    // Rotate east, north, up to earth centred cartesian
+    let (slam, clam) = geographic[0].sin_cos();
+    let (sphi, cphi) = geographic[1].sin_cos();
+    let (e, n, u) = (v[0], v[1], v[2]);
+    Some([
+        -slam * e - sphi * clam * n + cphi * clam * u,
+        clam * e - sphi * slam * n + cphi * slam * u,
+        cphi * n + sphi * u,
+    ])
+}
+
+// ----- F O R W A R D -----------------------------------------------------------------
+
+fn fwd(op: &Op, ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
+    let mut successes = 0_usize;
+    let length = operands.len();
+    for i in 0..length {
+        let mut coord = operands.get_coord(i);
+        let (Some(years), Some(v)) = (years(op, &coord), velocity(op, ctx, &coord)) else {
+            operands.set_coord(i, &Coor4D::nan());
+            continue;
+        };
+        for axis in 0..3 {
+            coord[axis] += v[axis] * years;
+        }
+        operands.set_coord(i, &coord);
+        successes += 1;
+    }
+
+    successes
+}
+
+// ----- I N V E R S E -----------------------------------------------------------------
+
+fn inv(op: &Op, ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
+    let mut successes = 0_usize;
+    let length = operands.len();
+    'points: for i in 0..length {
+        let coord = operands.get_coord(i);
+        let Some(years) = years(op, &coord) else {
+            operands.set_coord(i, &Coor4D::nan());
+            continue;
+        };
+
+        // The velocities are given at the source position, so iterate towards it
+        let mut result = coord;
+        for _ in 0..10 {
+            let Some(v) = velocity(op, ctx, &result) else {
+                operands.set_coord(i, &Coor4D::nan());
+                continue 'points;
+            };
+            let mut change = 0_f64;
+            for axis in 0..3 {
+                let next = coord[axis] - v[axis] * years;
+                change = change.max((next - result[axis]).abs());
+                result[axis] = next;
+            }
+            if change < 1e-6 {
+                break;
+            }
+        }
+        operands.set_coord(i, &result);
+        successes += 1;
+    }
+
+    successes
+}
+
+// ----- C O N S T R U C T O R ---------------------------------------------------------
+
+#[rustfmt::skip]
+pub const GAMUT: [OpParameter; 5] = [
+    OpParameter::Flag { key: "inv" },
+    OpParameter::Texts { key: "grids", default: None },
+    OpParameter::Real { key: "t_epoch", default: Some(f64::NAN) },
+    OpParameter::Real { key: "dt", default: Some(f64::NAN) },
+    OpParameter::Text { key: "ellps", default: Some("GRS80") },
+];
+
+pub fn new(parameters: &RawParameters, ctx: &dyn Context) -> Result<Op, Error> {
+    let def = &parameters.definition;
+    let mut params = ParsedParameters::new(parameters, &GAMUT)?;
+
+    if params.real("t_epoch")?.is_nan() && params.real("dt")?.is_nan() {
+        return Err(Error::MissingParam("t_epoch or dt".to_string()));
+    }
+
+    // Grids prefixed with `@` are optional
+    for key in params.texts("grids")?.clone() {
+        let optional = key.starts_with('@');
+        let key = key.trim_start_matches('@');
+        match ctx.get_grid(key) {
+            Ok(grid) if grid.bands() == 3 => params.grids.push(grid),
+            Ok(_) => return Err(Error::BadParam("grids".to_string(), key.to_string())),
+            Err(_) if optional => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let descriptor = OpDescriptor::new(def, InnerOp(fwd), Some(InnerOp(inv)));
+    let steps = Vec::<Op>::new();
+    let id = OpHandle::new();
+
+    Ok(Op {
+        descriptor,
+        params,
+        steps,
+        id,
+    })
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geodesy::{grids::tests::register, wasmcontext::WasmContext};
+    use float_eq::assert_float_eq;
+
+    #[test]
+    fn deformation() -> Result<(), Error> {
+        // Uniform velocities of 10 mm/year east, 20 north and 5 up over 54N..56N, 8E..12E
+        let node = "10 20 5 ".repeat(3 * 3);
+        let model = format!("54 56 8 12 1 2\n{}\n", node).into_bytes();
+        let _model = register("deformation-model", &model).unwrap();
+
+        let mut ctx = WasmContext::new();
+        let op = ctx.op("deformation grids=deformation-model t_epoch=2000")?;
+        let ellps = Ellipsoid::named("GRS80")?;
+        let mut site = ellps.cartesian(&Coor4D::geo(55., 10., 100., 0.));
+        site[3] = 2010.;
+
+        // 10 years of velocities, seen in the local frame of the site
+        let mut operands = [site];
+        assert_eq!(ctx.apply(op, Fwd, &mut operands)?, 1);
+        let moved = ellps.geographic(&operands[0]);
+        let north = (moved[1] - 55_f64.to_radians()) * ellps.meridian_radius_of_curvature(moved[1]);
+        assert_float_eq!(north, 0.2, abs <= 1e-3);
+        assert_float_eq!(moved[2], 100.05, abs <= 1e-3);
+        assert_float_eq!(operands[0][3], 2010., abs <= 1e-12);
+
+        // Round trip
+        ctx.apply(op, Inv, &mut operands)?;
+        for axis in 0..3 {
+            assert_float_eq!(operands[0][axis], site[axis], abs <= 1e-6);
+        }
+
+        // Coordinates without an epoch fail rather than move by 2000 years
+        for epoch in [0., f64::NAN] {
+            let mut operands = [site];
+            operands[0][3] = epoch;
+            assert_eq!(ctx.apply(op, Fwd, &mut operands)?, 0);
+            assert!(operands[0][0].is_nan());
+            let mut operands = [site];
+            operands[0][3] = epoch;
+            assert_eq!(ctx.apply(op, Inv, &mut operands)?, 0);
+        }
+
+        // A fixed number of years, failing outside the grid
+        let op = ctx.op("deformation grids=deformation-model dt=-10")?;
+        let mut operands = [site, ellps.cartesian(&Coor4D::geo(60., 10., 0., 0.))];
+        assert_eq!(ctx.apply(op, Fwd, &mut operands)?, 1);
+        let moved = ellps.geographic(&operands[0]);
+        assert_float_eq!(moved[2], 99.95, abs <= 1e-3);
+        assert!(operands[1][0].is_nan());
+        // whatever the epoch
+        let mut operands = [ellps.cartesian(&Coor4D::geo(55., 10., 100., 0.))];
+        assert_eq!(ctx.apply(op, Fwd, &mut operands)?, 1);
+
+        assert!(ctx.op("deformation grids=deformation-model").is_err());
+        assert!(ctx
+            .op("deformation grids=@deformation-missing dt=1")
+            .is_ok());
+        Ok(())
+    }
+}
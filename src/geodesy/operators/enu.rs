@@ -0,0 +1,319 @@
+//! East-North-Up
+//! A local tangent plane (topocentric) frame centred on `lat_0`, `lon_0` (degrees) and `h_0`.
+//! Input is geographic (longitude, latitude, height), or earth centred cartesian with the `cart` flag.
+use crate::{error::WasmResult, geodesy::ellipsoids};
+use geodesy_rs::authoring::*;
+use wasm_bindgen::prelude::*;
+
+// ----- F O R W A R D -----------------------------------------------------------------
+
+fn fwd(op: &Op, _ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
+    let Ok(frame) = Frame::from_params(&op.params) else {
+        return 0;
+    };
+    let cartesian_input = op.params.boolean("cart");
+
+    let mut successes = 0_usize;
+    let length = operands.len();
+    for i in 0..length {
+        let mut coord = operands.get_coord(i);
+        let ecef = if cartesian_input {
+            coord
+        } else {
+            frame.ellps.cartesian(&coord)
+        };
+
+        let enu = frame.to_enu([ecef[0], ecef[1], ecef[2]]);
+        coord[0] = enu[0];
+        coord[1] = enu[1];
+        coord[2] = enu[2];
+
+        operands.set_coord(i, &coord);
+        if enu.iter().all(|v| v.is_finite()) {
+            successes += 1;
+        }
+    }
+
+    successes
+}
+
+// ----- I N V E R S E -----------------------------------------------------------------
+
+fn inv(op: &Op, _ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
+    let Ok(frame) = Frame::from_params(&op.params) else {
+        return 0;
+    };
+    let cartesian_output = op.params.boolean("cart");
+
+    let mut successes = 0_usize;
+    let length = operands.len();
+    for i in 0..length {
+        let mut coord = operands.get_coord(i);
+        let ecef = frame.to_ecef([coord[0], coord[1], coord[2]]);
+
+        let result = if cartesian_output {
+            Coor4D::raw(ecef[0], ecef[1], ecef[2], coord[3])
+        } else {
+            frame
+                .ellps
+                .geographic(&Coor4D::raw(ecef[0], ecef[1], ecef[2], 0.))
+        };
+        coord[0] = result[0];
+        coord[1] = result[1];
+        coord[2] = result[2];
+
+        operands.set_coord(i, &coord);
+        if coord.0[..3].iter().all(|v| v.is_finite()) {
+            successes += 1;
+        }
+    }
+
+    successes
+}
+
+// ----- C O N S T R U C T O R ---------------------------------------------------------
+
+#[rustfmt::skip]
+pub const GAMUT: [OpParameter; 6] = [
+    OpParameter::Flag { key: "inv" },
+    OpParameter::Flag { key: "cart" },
+    OpParameter::Text { key: "ellps",  default: Some("GRS80") },
+
+    OpParameter::Real { key: "lat_0",  default: Some(0_f64) },
+    OpParameter::Real { key: "lon_0",  default: Some(0_f64) },
+    OpParameter::Real { key: "h_0",    default: Some(0_f64) },
+];
+
+pub fn new(parameters: &RawParameters, _ctx: &dyn Context) -> Result<Op, Error> {
+    let def = &parameters.definition;
+    let mut params = ParsedParameters::new(parameters, &GAMUT)?;
+
+    let lat_0 = params.real("lat_0")?;
+    if !(-90.0..=90.0).contains(&lat_0) {
+        return Err(Error::BadParam("lat_0".to_string(), lat_0.to_string()));
+    }
+
+    // Computed once here rather than for every call
+    let frame = Frame::new(
+        *params.ellps(0),
+        params.real("lon_0")?.to_radians(),
+        lat_0.to_radians(),
+        params.real("h_0")?,
+    );
+    frame.store(&mut params);
+
+    let descriptor = OpDescriptor::new(def, InnerOp(fwd), Some(InnerOp(inv)));
+    let steps = Vec::<Op>::new();
+    let id = OpHandle::new();
+
+    Ok(Op {
+        descriptor,
+        params,
+        steps,
+        id,
+    })
+}
+
+// ----- E N U   F R A M E -------------------------------------------------------------
+
+/// The 4x4 matrix taking ENU coordinates around the origin to earth centred cartesian coordinates.
+/// The origin longitude and latitude are in degrees, as `lon_0` and `lat_0` of the `enu` operator,
+/// and the matrix is returned in column-major order, ready for WebGL style renderers.
+///
+/// The ellipsoid defaults to `GRS80`.
+#[wasm_bindgen(js_name = enuToEcefMatrix)]
+pub fn enu_to_ecef_matrix_wasm(
+    lon_0: f64,
+    lat_0: f64,
+    h_0: f64,
+    ellps: Option<String>,
+) -> WasmResult<Vec<f64>> {
+    let ellps = ellipsoids::named(ellps.as_deref().unwrap_or("GRS80"))?;
+    if !(-90.0..=90.0).contains(&lat_0) {
+        return Err(Error::BadParam("lat_0".to_string(), lat_0.to_string()).into());
+    }
+    let frame = Frame::new(ellps, lon_0.to_radians(), lat_0.to_radians(), h_0);
+    Ok(frame.to_ecef_matrix().to_vec())
+}
+
+// The parameters keeping the frame of an `enu` step between calls
+const STORED: [&str; 7] = [
+    "origin_x",
+    "origin_y",
+    "origin_z",
+    "sin_lon_0",
+    "cos_lon_0",
+    "sin_lat_0",
+    "cos_lat_0",
+];
+
+/// The rotation and translation of a topocentric frame
+pub struct Frame {
+    pub ellps: Ellipsoid,
+    /// Earth centred cartesian coordinates of the origin
+    pub origin: [f64; 3],
+    // sin/cos of the origin longitude and latitude
+    slam: f64,
+    clam: f64,
+    sphi: f64,
+    cphi: f64,
+}
+
+impl Frame {
+    /// A frame centred on `lon_0`, `lat_0` (radians) and `h_0` (metres)
+    pub fn new(ellps: Ellipsoid, lon_0: f64, lat_0: f64, h_0: f64) -> Frame {
+        let origin = ellps.cartesian(&Coor4D::raw(lon_0, lat_0, h_0, 0.));
+        Frame {
+            ellps,
+            origin: [origin[0], origin[1], origin[2]],
+            slam: lon_0.sin(),
+            clam: lon_0.cos(),
+            sphi: lat_0.sin(),
+            cphi: lat_0.cos(),
+        }
+    }
+
+    // The precomputed frame of an `enu` step
+    fn from_params(params: &ParsedParameters) -> Result<Frame, Error> {
+        let [x, y, z, slam, clam, sphi, cphi] = STORED.map(|key| params.real(key));
+        Ok(Frame {
+            ellps: *params.ellps(0),
+            origin: [x?, y?, z?],
+            slam: slam?,
+            clam: clam?,
+            sphi: sphi?,
+            cphi: cphi?,
+        })
+    }
+
+    fn store(&self, params: &mut ParsedParameters) {
+        let values = [
+            self.origin[0],
+            self.origin[1],
+            self.origin[2],
+            self.slam,
+            self.clam,
+            self.sphi,
+            self.cphi,
+        ];
+        for (key, value) in STORED.into_iter().zip(values) {
+            params.real.insert(key, value);
+        }
+    }
+
+    pub fn to_enu(&self, ecef: [f64; 3]) -> [f64; 3] {
+        let dx = ecef[0] - self.origin[0];
+        let dy = ecef[1] - self.origin[1];
+        let dz = ecef[2] - self.origin[2];
+        let (slam, clam, sphi, cphi) = (self.slam, self.clam, self.sphi, self.cphi);
+
+        [
+            -slam * dx + clam * dy,
+            -sphi * clam * dx - sphi * slam * dy + cphi * dz,
+            cphi * clam * dx + cphi * slam * dy + sphi * dz,
+        ]
+    }
+
+    pub fn to_ecef(&self, enu: [f64; 3]) -> [f64; 3] {
+        let (e, n, u) = (enu[0], enu[1], enu[2]);
+        let (slam, clam, sphi, cphi) = (self.slam, self.clam, self.sphi, self.cphi);
+
+        [
+            self.origin[0] - slam * e - sphi * clam * n + cphi * clam * u,
+            self.origin[1] + clam * e - sphi * slam * n + cphi * slam * u,
+            self.origin[2] + cphi * n + sphi * u,
+        ]
+    }
+
+    /// Column-major ENU -> ECEF matrix
+    pub fn to_ecef_matrix(&self) -> [f64; 16] {
+        let (slam, clam, sphi, cphi) = (self.slam, self.clam, self.sphi, self.cphi);
+        #[rustfmt::skip]
+        let matrix = [
+            // East
+            -slam,        clam,         0.,   0.,
+            // North
+            -sphi * clam, -sphi * slam, cphi, 0.,
+            // Up
+            cphi * clam,  cphi * slam,  sphi, 0.,
+            // Origin
+            self.origin[0], self.origin[1], self.origin[2], 1.,
+        ];
+        matrix
+    }
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_eq::assert_float_eq;
+
+    fn xyz(coord: &Coor4D) -> [f64; 3] {
+        [coord[0], coord[1], coord[2]]
+    }
+
+    #[test]
+    fn enu() -> Result<(), Error> {
+        let mut ctx = Minimal::default();
+        ctx.register_op("enu", OpConstructor(new));
+        let op = ctx.op("enu lat_0=51.5 lon_0=-0.1 h_0=10")?;
+
+        let site = [
+            Coor4D::geo(51.5, -0.1, 10., 0.),
+            Coor4D::geo(51.5, -0.1, 110., 0.),
+            Coor4D::geo(51.501, -0.099, 30., 0.),
+        ];
+
+        let mut operands = site;
+        assert_eq!(ctx.apply(op, Fwd, &mut operands)?, 3);
+        assert_float_eq!(xyz(&operands[0]), [0., 0., 0.], abs_all <= 1e-8);
+        assert_float_eq!(xyz(&operands[1]), [0., 0., 100.], abs_all <= 1e-8);
+        // Roughly 69m east, 111m north and a little down due to the earth's curvature
+        assert!(operands[2][0] > 69. && operands[2][0] < 70.);
+        assert!(operands[2][1] > 111. && operands[2][1] < 112.);
+        assert!(operands[2][2] > 19. && operands[2][2] < 20.);
+
+        // Roundtrip
+        ctx.apply(op, Inv, &mut operands)?;
+        for i in 0..operands.len() {
+            assert_float_eq!(operands[i][0], site[i][0], abs <= 1e-12);
+            assert_float_eq!(operands[i][1], site[i][1], abs <= 1e-12);
+            assert_float_eq!(operands[i][2], site[i][2], abs <= 1e-6);
+        }
+
+        // Cartesian input
+        let ellps = Ellipsoid::named("GRS80")?;
+        let cart = ctx.op("enu cart lat_0=51.5 lon_0=-0.1 h_0=10")?;
+        let mut operands = [ellps.cartesian(&site[1])];
+        ctx.apply(cart, Fwd, &mut operands)?;
+        assert_float_eq!(xyz(&operands[0]), [0., 0., 100.], abs_all <= 1e-8);
+
+        assert!(ctx.op("enu lat_0=91").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn matrix() -> Result<(), Error> {
+        let ellps = Ellipsoid::named("GRS80")?;
+        let (lon, lat) = ((-0.1_f64).to_radians(), 51.5_f64.to_radians());
+        let frame = Frame::new(ellps, lon, lat, 10.);
+        let m = frame.to_ecef_matrix();
+        // The JS helper takes the origin in degrees, like the operator
+        let js = enu_to_ecef_matrix_wasm(-0.1, 51.5, 10., None).unwrap();
+        assert_float_eq!(js.as_slice(), m.as_slice(), abs_all <= 1e-9);
+        assert!(enu_to_ecef_matrix_wasm(0., 91., 0., None).is_err());
+
+        let enu = [25., -40., 3.];
+        let expected = frame.to_ecef(enu);
+        for row in 0..3 {
+            let value = m[row] * enu[0] + m[4 + row] * enu[1] + m[8 + row] * enu[2] + m[12 + row];
+            assert_float_eq!(value, expected[row], abs <= 1e-6);
+        }
+
+        let roundtrip = frame.to_enu(expected);
+        assert_float_eq!(roundtrip, enu, abs_all <= 1e-6);
+        Ok(())
+    }
+}
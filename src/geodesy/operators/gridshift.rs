@@ -0,0 +1,230 @@
+//! Grid shift
+//! Datum shifts with horizontal offset grids and height conversions with geoid grids.
+//! Replaces the builtin `gridshift`, adding an `interpolation` of
+//! `bilinear` (the default), `biquadratic` or `bicubic`. The grids applied to each
+//! coordinate can be recorded with [record_grids].
+use crate::geodesy::grids::{self, Interpolation};
+use geodesy_rs::authoring::*;
+use std::cell::RefCell;
+
+thread_local! {
+    // The key of the grid applied to each coordinate, while a transformation records them
+    static APPLIED: RefCell<Option<Vec<Option<String>>>> = const { RefCell::new(None) };
+}
+
+/// Run `apply` on `length` coordinates, also returning the key of the grid
+/// the last `gridshift` step applied to each of them
+pub(crate) fn record_grids<T>(
+    length: usize,
+    apply: impl FnOnce() -> T,
+) -> (T, Vec<Option<String>>) {
+    APPLIED.with(|applied| *applied.borrow_mut() = Some(vec![None; length]));
+    let result = apply();
+    let applied = APPLIED.with(|applied| applied.borrow_mut().take());
+    (result, applied.unwrap_or_default())
+}
+
+fn record(op: &Op, index: usize, grid: Option<usize>) {
+    APPLIED.with(|applied| {
+        if let Some(entry) = applied.borrow_mut().as_mut().and_then(|a| a.get_mut(index)) {
+            let keys = op.params.texts.get("grid_keys");
+            *entry = grid.and_then(|grid| keys?.get(grid).cloned());
+        }
+    });
+}
+
+// ----- F O R W A R D -----------------------------------------------------------------
+
+fn fwd(op: &Op, ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
+    let Ok(padding) = op.params.real("padding") else {
+        return 0;
+    };
+
+    let mut successes = 0_usize;
+    let length = operands.len();
+    'points: for i in 0..length {
+        let mut coord = operands.get_coord(i);
+        for margin in [0.0, padding] {
+            for (index, grid) in op.params.grids.iter().enumerate() {
+                if let Some(d) = grid.at(Some(ctx), &coord, margin) {
+                    if grid.bands() == 1 {
+                        // Geoid
+                        coord[2] -= d[0];
+                    } else {
+                        coord[0] += d[1];
+                        coord[1] += d[0];
+                    }
+                    operands.set_coord(i, &coord);
+                    record(op, i, Some(index));
+                    successes += 1;
+                    continue 'points;
+                }
+            }
+        }
+        operands.set_coord(i, &Coor4D::nan());
+        record(op, i, None);
+    }
+
+    successes
+}
+
+// ----- I N V E R S E -----------------------------------------------------------------
+
+fn inv(op: &Op, ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
+    let Ok(padding) = op.params.real("padding") else {
+        return 0;
+    };
+
+    let mut successes = 0_usize;
+    let length = operands.len();
+    'points: for i in 0..length {
+        let coord = operands.get_coord(i);
+        for margin in [0.0, padding] {
+            for (index, grid) in op.params.grids.iter().enumerate() {
+                let Some(d) = grid.at(Some(ctx), &coord, margin) else {
+                    continue;
+                };
+                let mut result = coord;
+                if grid.bands() == 1 {
+                    result[2] += d[0];
+                } else {
+                    // The offsets are given at the source position, so iterate towards it
+                    result[0] -= d[1];
+                    result[1] -= d[0];
+                    for _ in 0..10 {
+                        let Some(d) = grid.at(Some(ctx), &result, margin) else {
+                            break;
+                        };
+                        let dlon = result[0] + d[1] - coord[0];
+                        let dlat = result[1] + d[0] - coord[1];
+                        result[0] -= dlon;
+                        result[1] -= dlat;
+                        if dlon.abs().max(dlat.abs()) < 1e-12 {
+                            break;
+                        }
+                    }
+                }
+                operands.set_coord(i, &result);
+                record(op, i, Some(index));
+                successes += 1;
+                continue 'points;
+            }
+        }
+        operands.set_coord(i, &Coor4D::nan());
+        record(op, i, None);
+    }
+
+    successes
+}
+
+// ----- C O N S T R U C T O R ---------------------------------------------------------
+
+#[rustfmt::skip]
+pub const GAMUT: [OpParameter; 4] = [
+    OpParameter::Flag { key: "inv" },
+    OpParameter::Texts { key: "grids", default: None },
+    OpParameter::Real { key: "padding", default: Some(0.5) },
+    OpParameter::Text { key: "interpolation", default: Some("bilinear") },
+];
+
+pub fn new(parameters: &RawParameters, ctx: &dyn Context) -> Result<Op, Error> {
+    let def = &parameters.definition;
+    let mut params = ParsedParameters::new(parameters, &GAMUT)?;
+
+    let interpolation = params.text("interpolation")?;
+    let interpolation: Interpolation = interpolation
+        .parse()
+        .map_err(|_| Error::BadParam("interpolation".to_string(), interpolation))?;
+
+    // Grids prefixed with `@` are optional. The keys of the ones found are kept for reporting.
+    let mut keys = Vec::new();
+    for key in params.texts("grids")?.clone() {
+        let optional = key.starts_with('@');
+        let key = key.trim_start_matches('@');
+        match grids::get_grid(ctx, key, interpolation) {
+            // Deformation models are for the `deformation` operator
+            Ok(grid) if grid.bands() > 2 => {
+                return Err(Error::BadParam("grids".to_string(), key.to_string()))
+            }
+            Ok(grid) => {
+                params.grids.push(grid);
+                keys.push(key.to_string());
+            }
+            Err(_) if optional => {}
+            Err(e) => return Err(e),
+        }
+    }
+    params.texts.insert("grid_keys", keys);
+
+    let descriptor = OpDescriptor::new(def, InnerOp(fwd), Some(InnerOp(inv)));
+    let steps = Vec::<Op>::new();
+    let id = OpHandle::new();
+
+    Ok(Op {
+        descriptor,
+        params,
+        steps,
+        id,
+    })
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geodesy::{grids::tests::register, wasmcontext::WasmContext};
+    use float_eq::assert_float_eq;
+
+    #[test]
+    fn interpolation() -> Result<(), Error> {
+        // A geoid over 54N..57N, 8E..11E
+        let geoid = b"54 57 8 11 1 1
+            38.91 39.40 40.12 40.57
+            37.85 38.66 39.21 40.03
+            36.72 37.94 38.50 39.18
+            35.60 36.88 37.77 38.41
+        ";
+        let _geoid = register("interpolated-geoid", geoid).unwrap();
+
+        let mut ctx = WasmContext::new();
+        let height = |ctx: &mut WasmContext, definition: &str| -> Result<f64, Error> {
+            let op = ctx.op(definition)?;
+            let mut operands = [Coor4D::gis(9.3, 55.4, 0., 0.)];
+            ctx.apply(op, Fwd, &mut operands)?;
+            Ok(-operands[0][2])
+        };
+        // The values of the polynomials through the nearest 2x2, 3x3 and 4x4 nodes,
+        // found by solving for their coefficients
+        let bilinear = height(&mut ctx, "gridshift grids=interpolated-geoid")?;
+        assert_float_eq!(bilinear, 38.394_799_270_630, abs <= 1e-9);
+        let explicit = height(
+            &mut ctx,
+            "gridshift grids=interpolated-geoid interpolation=bilinear",
+        )?;
+        assert_float_eq!(explicit, bilinear, abs <= 1e-9);
+        let biquadratic = height(
+            &mut ctx,
+            "gridshift grids=interpolated-geoid interpolation=biquadratic",
+        )?;
+        assert_float_eq!(biquadratic, 38.485_020_652_008, abs <= 1e-9);
+        let bicubic = height(
+            &mut ctx,
+            "gridshift grids=interpolated-geoid interpolation=bicubic",
+        )?;
+        assert_float_eq!(bicubic, 38.428_283_468_353, abs <= 1e-9);
+
+        // Round trip
+        let op = ctx.op("gridshift grids=interpolated-geoid interpolation=biquadratic")?;
+        let mut operands = [Coor4D::gis(9.3, 55.4, 0., 0.)];
+        ctx.apply(op, Fwd, &mut operands)?;
+        ctx.apply(op, Inv, &mut operands)?;
+        assert_float_eq!(operands[0][2], 0., abs <= 1e-9);
+
+        assert!(matches!(
+            ctx.op("gridshift grids=interpolated-geoid interpolation=nearest"),
+            Err(Error::BadParam(..))
+        ));
+        Ok(())
+    }
+}
@@ -1,8 +1,19 @@
+mod deformation;
+mod enu;
+mod gridshift;
 mod senmerc;
+mod sitecal;
 
 use geodesy_rs::authoring::*;
 
+pub(crate) use gridshift::record_grids;
+
 #[rustfmt::skip]
-pub const ACCESSORY_OPERATORS: [(&str, OpConstructor); 1] = [
+pub const ACCESSORY_OPERATORS: [(&str, OpConstructor); 5] = [
+  ("deformation", OpConstructor(deformation::new)),
+  ("enu", OpConstructor(enu::new)),
+  // Replaces the builtin `gridshift`, adding interpolation options and grid reports
+  ("gridshift", OpConstructor(gridshift::new)),
   ("senmerc", OpConstructor(senmerc::new)),
+  ("sitecal", OpConstructor(sitecal::new)),
 ];
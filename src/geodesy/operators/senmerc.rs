@@ -1,28 +1,127 @@
 //! Sensat Mercator
-//! A bastardisation for 3D where the Z value is scaled by 1 / cos(lat) in the forward direction
+//! A bastardisation for 3D where the Z value is scaled along with the plane, by the point scale
+//! factor of the projection (1 / cos(lat) on the sphere) in the forward direction.
+//!
+//! Spherical by default, or ellipsoidal with the `ellipsoidal` flag. The plane is offset by
+//! `x_0`, `y_0` and centred on `lon_0`, with true scale at `lat_ts`. `height=none` leaves Z as
+//! is and `height=inverse` divides rather than multiplies it by the scale factor.
 use geodesy_rs::authoring::*;
 use std::f64::consts::FRAC_PI_2;
 use std::f64::consts::FRAC_PI_4;
 
+struct Projection {
+    a: f64,
+    // Eccentricity, zero on the sphere
+    e: f64,
+    k_0: f64,
+    lon_0: f64,
+    x_0: f64,
+    y_0: f64,
+    height: Height,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Height {
+    Scaled,
+    Inverse,
+    Unscaled,
+}
+
+impl Projection {
+    fn from_params(params: &ParsedParameters) -> Result<Projection, Error> {
+        let ellps = params.ellps(0);
+        let e = if params.boolean("ellipsoidal") {
+            ellps.eccentricity()
+        } else {
+            0.
+        };
+        let lat_ts = params.real("lat_ts")?.to_radians();
+        let height = match params.text("height")?.as_str() {
+            "scaled" => Height::Scaled,
+            "inverse" => Height::Inverse,
+            "none" => Height::Unscaled,
+            other => return Err(Error::BadParam("height".to_string(), other.to_string())),
+        };
+
+        Ok(Projection {
+            a: ellps.semimajor_axis(),
+            e,
+            k_0: lat_ts.cos() / (1. - (e * lat_ts.sin()).powi(2)).sqrt(),
+            lon_0: params.real("lon_0")?.to_radians(),
+            x_0: params.real("x_0")?,
+            y_0: params.real("y_0")?,
+            height,
+        })
+    }
+
+    // The point scale factor at a latitude
+    fn scale(&self, lat: f64) -> f64 {
+        self.k_0 * (1. - (self.e * lat.sin()).powi(2)).sqrt() / lat.cos()
+    }
+
+    // Isometric latitude
+    fn psi(&self, lat: f64) -> f64 {
+        let e = self.e;
+        (FRAC_PI_4 + lat / 2.0).tan().ln() - e * (e * lat.sin()).atanh()
+    }
+
+    // Latitude from isometric latitude, iterating on the ellipsoid
+    fn lat(&self, psi: f64) -> f64 {
+        let e = self.e;
+        let mut lat = 2.0 * psi.exp().atan() - FRAC_PI_2;
+        if e == 0. {
+            return lat;
+        }
+        for _ in 0..15 {
+            let esin = e * lat.sin();
+            let next =
+                2.0 * (psi.exp() * ((1. + esin) / (1. - esin)).powf(e / 2.)).atan() - FRAC_PI_2;
+            let change = (next - lat).abs();
+            lat = next;
+            if change < 1e-14 {
+                break;
+            }
+        }
+        lat
+    }
+
+    fn z(&self, z: f64, lat: f64, direction: Direction) -> f64 {
+        let k = self.scale(lat);
+        match (self.height, direction) {
+            (Height::Unscaled, _) => z,
+            (Height::Scaled, Fwd) | (Height::Inverse, Inv) => z * k,
+            (Height::Scaled, Inv) | (Height::Inverse, Fwd) => z / k,
+        }
+    }
+}
+
 // ----- F O R W A R D -----------------------------------------------------------------
 
 fn fwd(op: &Op, _ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
-    let ellps = op.params.ellps(0);
-    let a = ellps.semimajor_axis();
+    let Ok(proj) = Projection::from_params(&op.params) else {
+        return 0;
+    };
+    let ak = proj.a * proj.k_0;
 
     let mut successes = 0_usize;
     let length = operands.len();
     for i in 0..length {
         let mut coord = operands.get_coord(i);
-        // Easting
-        coord[0] *= a;
-
-        // Northing
         let lat = coord[1];
-        coord[1] = a * (FRAC_PI_4 + lat / 2.0).tan().ln();
+        // The poles are infinitely far north and south
+        if lat.is_nan() || lat.abs() >= FRAC_PI_2 {
+            operands.set_coord(i, &Coor4D::nan());
+            continue;
+        }
 
         // Altitude
-        coord[2] *= 1.0 / lat.cos();
+        coord[2] = proj.z(coord[2], lat, Fwd);
+
+        // Easting
+        coord[0] = proj.x_0 + ak * (coord[0] - proj.lon_0);
+
+        // Northing
+        coord[1] = proj.y_0 + ak * proj.psi(lat);
 
         operands.set_coord(i, &coord);
         successes += 1;
@@ -34,8 +133,10 @@ fn fwd(op: &Op, _ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
 // ----- I N V E R S E -----------------------------------------------------------------
 
 fn inv(op: &Op, _ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
-    let ellps = op.params.ellps(0);
-    let a = ellps.semimajor_axis();
+    let Ok(proj) = Projection::from_params(&op.params) else {
+        return 0;
+    };
+    let ak = proj.a * proj.k_0;
 
     let mut successes = 0_usize;
     let length = operands.len();
@@ -43,13 +144,17 @@ fn inv(op: &Op, _ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
         let mut coord = operands.get_coord(i);
 
         // Easting -> Longitude
-        coord[0] /= a;
+        coord[0] = proj.lon_0 + (coord[0] - proj.x_0) / ak;
 
         // Northing -> Latitude
-        coord[1] = FRAC_PI_2 - 2.0 * (-coord[1] / a).exp().atan();
+        coord[1] = proj.lat((coord[1] - proj.y_0) / ak);
+        if !coord[1].is_finite() || coord[1].abs() >= FRAC_PI_2 {
+            operands.set_coord(i, &Coor4D::nan());
+            continue;
+        }
 
         // Altitude
-        coord[2] *= coord[1].cos();
+        coord[2] = proj.z(coord[2], coord[1], Inv);
 
         operands.set_coord(i, &coord);
         successes += 1;
@@ -61,15 +166,29 @@ fn inv(op: &Op, _ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
 // ----- C O N S T R U C T O R ---------------------------------------------------------
 
 #[rustfmt::skip]
-pub const GAMUT: [OpParameter; 2] = [
+pub const GAMUT: [OpParameter; 8] = [
     OpParameter::Flag { key: "inv" },
+    OpParameter::Flag { key: "ellipsoidal" },
     OpParameter::Text { key: "ellps",  default: Some("WGS84") },
+    OpParameter::Text { key: "height", default: Some("scaled") },
+
+    OpParameter::Real { key: "lon_0",  default: Some(0_f64) },
+    OpParameter::Real { key: "lat_ts", default: Some(0_f64) },
+    OpParameter::Real { key: "x_0",    default: Some(0_f64) },
+    OpParameter::Real { key: "y_0",    default: Some(0_f64) },
 ];
 
 pub fn new(parameters: &RawParameters, _ctx: &dyn Context) -> Result<Op, Error> {
     let def = &parameters.definition;
     let params = ParsedParameters::new(parameters, &GAMUT)?;
 
+    // No scale is true at the poles
+    let lat_ts = params.real("lat_ts")?;
+    if lat_ts.is_nan() || lat_ts.abs() >= 90. {
+        return Err(Error::BadParam("lat_ts".to_string(), lat_ts.to_string()));
+    }
+    Projection::from_params(&params)?;
+
     let descriptor = OpDescriptor::new(def, InnerOp(fwd), Some(InnerOp(inv)));
     let steps = Vec::<Op>::new();
     let id = OpHandle::new();
@@ -120,4 +239,67 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn parameters() -> Result<(), Error> {
+        let mut ctx = Minimal::default();
+        ctx.register_op("senmerc", OpConstructor(new));
+
+        // World Mercator, EPSG:3395, at 45N with heights left as they are
+        let op = ctx.op("senmerc ellipsoidal height=none")?;
+        let mut operands = [Coor4D::geo(45., 1., 30., 0.)];
+        ctx.apply(op, Fwd, &mut operands)?;
+        assert_float_eq!(operands[0][0], 111_319.490_793_273_6, abs <= 1e-6);
+        assert_float_eq!(operands[0][1], 5_591_295.918_553_7, abs <= 1e-3);
+        assert_float_eq!(operands[0][2], 30., abs <= 1e-12);
+        ctx.apply(op, Inv, &mut operands)?;
+        assert_float_eq!(
+            operands[0].0,
+            Coor4D::geo(45., 1., 30., 0.).0,
+            abs_all <= 1e-12
+        );
+
+        // True scale at 60N, so heights there are unscaled
+        let op = ctx.op("senmerc lat_ts=60 lon_0=10 x_0=500000 y_0=-100")?;
+        let mut operands = [
+            Coor4D::geo(60., 11., 30., 0.),
+            Coor4D::geo(0., 10., 30., 0.),
+        ];
+        ctx.apply(op, Fwd, &mut operands)?;
+        let a = 6_378_137.;
+        assert_float_eq!(
+            operands[0][0],
+            500_000. + a * 0.5 * 1_f64.to_radians(),
+            abs <= 1e-6
+        );
+        assert_float_eq!(operands[0][2], 30., abs <= 1e-9);
+        assert_float_eq!(operands[1][0], 500_000., abs <= 1e-9);
+        assert_float_eq!(operands[1][1], -100., abs <= 1e-9);
+        assert_float_eq!(operands[1][2], 15., abs <= 1e-9);
+        ctx.apply(op, Inv, &mut operands)?;
+        assert_float_eq!(
+            operands[0].0,
+            Coor4D::geo(60., 11., 30., 0.).0,
+            abs_all <= 1e-9
+        );
+
+        // Inverted height scaling
+        let op = ctx.op("senmerc height=inverse")?;
+        let mut operands = [Coor4D::geo(60., 0., 30., 0.)];
+        ctx.apply(op, Fwd, &mut operands)?;
+        assert_float_eq!(operands[0][2], 15., abs <= 1e-9);
+        ctx.apply(op, Inv, &mut operands)?;
+        assert_float_eq!(operands[0][2], 30., abs <= 1e-9);
+
+        // The poles fail rather than giving infinities
+        let op = ctx.op("senmerc")?;
+        let mut operands = [Coor4D::geo(90., 0., 30., 0.), Coor4D::geo(89., 0., 30., 0.)];
+        assert_eq!(ctx.apply(op, Fwd, &mut operands)?, 1);
+        assert!(operands[0][0].is_nan());
+        assert!(operands[1][1].is_finite());
+
+        assert!(ctx.op("senmerc lat_ts=90").is_err());
+        assert!(ctx.op("senmerc height=doubled").is_err());
+        Ok(())
+    }
 }
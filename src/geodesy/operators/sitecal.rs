@@ -0,0 +1,411 @@
+//! Site calibration
+//! The local grid of a construction site, as a horizontal similarity or affine transformation
+//! and an inclined vertical plane applied to projected coordinates (easting, northing, height).
+//!
+//! Around the origin `x_0`, `y_0` the plane is transformed by the row-major 2x2 `matrix` and
+//! moved by `dx`, `dy`. Heights are raised by `dz` plus `slope_x` and `slope_y` (metres per
+//! metre) times the distance east and north of the origin.
+//!
+//! [fit_site_calibration_wasm] estimates the parameters from control points measured in both
+//! systems.
+use crate::{
+    error::{Error::Invalid, WasmResult},
+    geodesy::{adjustment::Adjustment, coordinate::Coordinates},
+};
+use geodesy_rs::authoring::*;
+use wasm_bindgen::prelude::*;
+
+// ----- F O R W A R D -----------------------------------------------------------------
+
+fn fwd(op: &Op, _ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
+    let Ok(site) = Calibration::from_params(&op.params) else {
+        return 0;
+    };
+
+    let mut successes = 0_usize;
+    let length = operands.len();
+    for i in 0..length {
+        let mut coord = operands.get_coord(i);
+        let [x, y, z] = site.fwd([coord[0], coord[1], coord[2]]);
+        coord[0] = x;
+        coord[1] = y;
+        coord[2] = z;
+        operands.set_coord(i, &coord);
+        successes += 1;
+    }
+
+    successes
+}
+
+// ----- I N V E R S E -----------------------------------------------------------------
+
+fn inv(op: &Op, _ctx: &dyn Context, operands: &mut dyn CoordinateSet) -> usize {
+    let Ok(site) = Calibration::from_params(&op.params) else {
+        return 0;
+    };
+
+    let mut successes = 0_usize;
+    let length = operands.len();
+    for i in 0..length {
+        let mut coord = operands.get_coord(i);
+        let [x, y, z] = site.inv([coord[0], coord[1], coord[2]]);
+        coord[0] = x;
+        coord[1] = y;
+        coord[2] = z;
+        operands.set_coord(i, &coord);
+        successes += 1;
+    }
+
+    successes
+}
+
+// ----- C O N S T R U C T O R ---------------------------------------------------------
+
+#[rustfmt::skip]
+pub const GAMUT: [OpParameter; 9] = [
+    OpParameter::Flag { key: "inv" },
+
+    OpParameter::Real { key: "x_0",     default: Some(0_f64) },
+    OpParameter::Real { key: "y_0",     default: Some(0_f64) },
+    OpParameter::Real { key: "dx",      default: Some(0_f64) },
+    OpParameter::Real { key: "dy",      default: Some(0_f64) },
+    OpParameter::Series { key: "matrix", default: Some("1,0,0,1") },
+
+    OpParameter::Real { key: "dz",      default: Some(0_f64) },
+    OpParameter::Real { key: "slope_x", default: Some(0_f64) },
+    OpParameter::Real { key: "slope_y", default: Some(0_f64) },
+];
+
+pub fn new(parameters: &RawParameters, _ctx: &dyn Context) -> Result<Op, Error> {
+    let def = &parameters.definition;
+    let params = ParsedParameters::new(parameters, &GAMUT)?;
+
+    let matrix = params.series("matrix")?;
+    let invertible = matrix.len() == 4 && {
+        let det = matrix[0] * matrix[3] - matrix[1] * matrix[2];
+        det.is_finite() && det != 0.
+    };
+    if !invertible {
+        let given = matrix.iter().map(f64::to_string).collect::<Vec<_>>();
+        return Err(Error::BadParam("matrix".to_string(), given.join(",")));
+    }
+
+    let descriptor = OpDescriptor::new(def, InnerOp(fwd), Some(InnerOp(inv)));
+    let steps = Vec::<Op>::new();
+    let id = OpHandle::new();
+
+    Ok(Op {
+        descriptor,
+        params,
+        steps,
+        id,
+    })
+}
+
+// ----- C A L I B R A T I O N ---------------------------------------------------------
+
+/// The parameters of a `sitecal` step
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Calibration {
+    origin: [f64; 2],
+    shift: [f64; 2],
+    matrix: [f64; 4],
+    dz: f64,
+    slope: [f64; 2],
+}
+
+impl Calibration {
+    fn from_params(params: &ParsedParameters) -> Result<Calibration, Error> {
+        let matrix = params.series("matrix")?;
+        let Ok(matrix) = matrix.try_into() else {
+            return Err(Error::MissingParam("matrix".to_string()));
+        };
+        Ok(Calibration {
+            origin: [params.real("x_0")?, params.real("y_0")?],
+            shift: [params.real("dx")?, params.real("dy")?],
+            matrix,
+            dz: params.real("dz")?,
+            slope: [params.real("slope_x")?, params.real("slope_y")?],
+        })
+    }
+
+    // The height correction at a point relative to the origin
+    fn lift(&self, dx: f64, dy: f64) -> f64 {
+        self.dz + self.slope[0] * dx + self.slope[1] * dy
+    }
+
+    fn fwd(&self, [x, y, z]: [f64; 3]) -> [f64; 3] {
+        let [x_0, y_0] = self.origin;
+        let [a, b, c, d] = self.matrix;
+        let (dx, dy) = (x - x_0, y - y_0);
+        [
+            x_0 + self.shift[0] + a * dx + b * dy,
+            y_0 + self.shift[1] + c * dx + d * dy,
+            z + self.lift(dx, dy),
+        ]
+    }
+
+    fn inv(&self, [x, y, z]: [f64; 3]) -> [f64; 3] {
+        let [x_0, y_0] = self.origin;
+        let [a, b, c, d] = self.matrix;
+        let det = a * d - b * c;
+        let (u, v) = (x - x_0 - self.shift[0], y - y_0 - self.shift[1]);
+        let (dx, dy) = ((d * u - b * v) / det, (a * v - c * u) / det);
+        [x_0 + dx, y_0 + dy, z - self.lift(dx, dy)]
+    }
+
+    fn definition(&self) -> String {
+        let [a, b, c, d] = self.matrix;
+        format!(
+            "sitecal x_0={} y_0={} dx={} dy={} matrix={},{},{},{} dz={} slope_x={} slope_y={}",
+            self.origin[0],
+            self.origin[1],
+            self.shift[0],
+            self.shift[1],
+            a,
+            b,
+            c,
+            d,
+            self.dz,
+            self.slope[0],
+            self.slope[1]
+        )
+    }
+}
+
+// ----- F I T T I N G -----------------------------------------------------------------
+
+/// A site calibration fitted to control points.
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct SiteCalibration {
+    definition: String,
+    residuals: Vec<f64>,
+    horizontal_rms: f64,
+    vertical_rms: f64,
+}
+
+#[wasm_bindgen]
+impl SiteCalibration {
+    /// The `sitecal` step taking the source coordinates to the site
+    #[wasm_bindgen(getter)]
+    pub fn definition(&self) -> String {
+        self.definition.clone()
+    }
+
+    /// Site minus calibrated coordinates per control point, ordered (easting, northing, height)
+    #[wasm_bindgen(getter)]
+    pub fn residuals(&self) -> Vec<f64> {
+        self.residuals.clone()
+    }
+
+    #[wasm_bindgen(getter, js_name = horizontalRms)]
+    pub fn horizontal_rms(&self) -> f64 {
+        self.horizontal_rms
+    }
+
+    #[wasm_bindgen(getter, js_name = verticalRms)]
+    pub fn vertical_rms(&self) -> f64 {
+        self.vertical_rms
+    }
+}
+
+/// Fits a site calibration to control points given in projected (easting, northing, height)
+/// `source` coordinates, eg GNSS measurements run through a projection, and in `site` coordinates.
+///
+/// The horizontal transformation is a similarity (shift, rotation and scale, from two or more
+/// points) or, when `affine` is true, a general affine transformation (from three or more).
+/// Heights get a constant shift, and are inclined when three or more points are not on a line.
+#[wasm_bindgen(js_name = fitSiteCalibration)]
+pub fn fit_site_calibration_wasm(
+    source: &Coordinates,
+    site: &Coordinates,
+    affine: Option<bool>,
+) -> WasmResult<SiteCalibration> {
+    fit(source, site, affine.unwrap_or(false))
+}
+
+fn fit(
+    source: &dyn CoordinateSet,
+    site: &dyn CoordinateSet,
+    affine: bool,
+) -> WasmResult<SiteCalibration> {
+    let n = source.len();
+    if site.len() != n {
+        return Err(Invalid(format!(
+            "There are {} source and {} site coordinates",
+            n,
+            site.len()
+        )));
+    }
+    let points = |set: &dyn CoordinateSet| -> WasmResult<Vec<[f64; 3]>> {
+        (0..n)
+            .map(|i| {
+                let c = set.get_coord(i);
+                let point = [c[0], c[1], c[2]];
+                match point.iter().all(|v| v.is_finite()) {
+                    true => Ok(point),
+                    false => Err(Invalid(format!("Control point {} is not finite", i))),
+                }
+            })
+            .collect()
+    };
+    let (from, to) = (points(source)?, points(site)?);
+    if n < 2 + affine as usize {
+        return Err(Invalid(format!(
+            "A site calibration needs {} or more control points, not {}",
+            2 + affine as usize,
+            n
+        )));
+    }
+
+    // Centred on the source and site centroids, which the fitted transformation maps onto
+    // each other, to keep the equations well conditioned
+    let centroid =
+        |points: &[[f64; 3]]| [0, 1].map(|k| points.iter().map(|p| p[k]).sum::<f64>() / n as f64);
+    let (origin, target) = (centroid(&from), centroid(&to));
+    let centred = |p: &[f64; 3], c: [f64; 2]| [p[0] - c[0], p[1] - c[1]];
+
+    let matrix = if affine {
+        let mut adjustment = Adjustment::new(4);
+        for (p, q) in from.iter().zip(&to) {
+            let ([x, y], [u, v]) = (centred(p, origin), centred(q, target));
+            adjustment.observe(&[x, y, 0., 0.], u);
+            adjustment.observe(&[0., 0., x, y], v);
+        }
+        adjustment.solve()?
+    } else {
+        // The matrix of a similarity is [a, -b, b, a]
+        let mut adjustment = Adjustment::new(2);
+        for (p, q) in from.iter().zip(&to) {
+            let ([x, y], [u, v]) = (centred(p, origin), centred(q, target));
+            adjustment.observe(&[x, -y], u);
+            adjustment.observe(&[y, x], v);
+        }
+        let [a, b] = adjustment.solve()?[..] else {
+            unreachable!()
+        };
+        vec![a, -b, b, a]
+    };
+
+    // An inclined plane through the height differences, or their mean when it is undetermined
+    let mut plane = Adjustment::new(3);
+    for (p, q) in from.iter().zip(&to) {
+        let [x, y] = centred(p, origin);
+        plane.observe(&[1., x, y], q[2] - p[2]);
+    }
+    let (dz, slope) = match plane.solve() {
+        Ok(x) => (x[0], [x[1], x[2]]),
+        Err(_) => {
+            let mean = from.iter().zip(&to).map(|(p, q)| q[2] - p[2]).sum::<f64>() / n as f64;
+            (mean, [0., 0.])
+        }
+    };
+
+    let calibration = Calibration {
+        origin,
+        shift: [target[0] - origin[0], target[1] - origin[1]],
+        matrix: [matrix[0], matrix[1], matrix[2], matrix[3]],
+        dz,
+        slope,
+    };
+
+    let mut residuals = Vec::with_capacity(3 * n);
+    let (mut horizontal, mut vertical) = (0., 0.);
+    for (p, q) in from.iter().zip(&to) {
+        let fitted = calibration.fwd(*p);
+        let r = [q[0] - fitted[0], q[1] - fitted[1], q[2] - fitted[2]];
+        horizontal += r[0] * r[0] + r[1] * r[1];
+        vertical += r[2] * r[2];
+        residuals.extend(r);
+    }
+
+    Ok(SiteCalibration {
+        definition: calibration.definition(),
+        residuals,
+        horizontal_rms: (horizontal / n as f64).sqrt(),
+        vertical_rms: (vertical / n as f64).sqrt(),
+    })
+}
+
+// ----- T E S T S ---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_eq::assert_float_eq;
+
+    #[test]
+    fn sitecal() -> Result<(), Error> {
+        let mut ctx = Minimal::default();
+        ctx.register_op("sitecal", OpConstructor(new));
+
+        // Rotated by 30 degrees and scaled by 1.0001 around a point of a UTM zone
+        let (s, c) = (
+            1.0001 * 30_f64.to_radians().sin(),
+            1.0001 * 30_f64.to_radians().cos(),
+        );
+        let definition = format!(
+            "sitecal x_0=500000 y_0=6200000 dx=-499000 dy=-6199000 matrix={},{},{},{} dz=-40 slope_x=1e-5 slope_y=-2e-5",
+            c, -s, s, c
+        );
+        let op = ctx.op(&definition)?;
+
+        let gnss = [Coor4D::raw(500_100., 6_200_200., 50., 0.)];
+        let mut operands = gnss;
+        ctx.apply(op, Fwd, &mut operands)?;
+        let (dx, dy) = (100., 200.);
+        assert_float_eq!(operands[0][0], 1000. + c * dx - s * dy, abs <= 1e-9);
+        assert_float_eq!(operands[0][1], 1000. + s * dx + c * dy, abs <= 1e-9);
+        assert_float_eq!(operands[0][2], 10. + 1e-3 - 4e-3, abs <= 1e-9);
+
+        ctx.apply(op, Inv, &mut operands)?;
+        assert_float_eq!(operands[0].0, gnss[0].0, abs_all <= 1e-9);
+
+        assert!(ctx.op("sitecal matrix=1,2,2,4").is_err());
+        assert!(ctx.op("sitecal matrix=1,0,0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn fitting() -> WasmResult<()> {
+        let mut ctx = Minimal::default();
+        ctx.register_op("sitecal", OpConstructor(new));
+        let truth = ctx.op("sitecal x_0=500000 y_0=6200000 dx=-499000 dy=-6199000 matrix=0.9,-0.4,0.3,1.1 dz=-40 slope_x=1e-4 slope_y=-2e-4")?;
+
+        let gnss = [
+            Coor4D::raw(500_000., 6_200_000., 50., 0.),
+            Coor4D::raw(500_300., 6_200_000., 52., 0.),
+            Coor4D::raw(500_300., 6_200_400., 51., 0.),
+            Coor4D::raw(500_000., 6_200_400., 49., 0.),
+            Coor4D::raw(500_150., 6_200_250., 50., 0.),
+        ];
+        let mut site = gnss;
+        ctx.apply(truth, Fwd, &mut site)?;
+
+        // An exact affine fit, whose definition reproduces the site coordinates
+        let fitted = fit(&gnss.to_vec(), &site.to_vec(), true)?;
+        assert!(fitted.horizontal_rms() < 1e-6 && fitted.vertical_rms() < 1e-6);
+        assert_eq!(fitted.residuals().len(), 15);
+        let op = ctx.op(&fitted.definition())?;
+        let mut operands = gnss;
+        ctx.apply(op, Fwd, &mut operands)?;
+        for i in 0..operands.len() {
+            assert_float_eq!(operands[i].0, site[i].0, abs_all <= 1e-6);
+        }
+
+        // A similarity can not absorb the shear, which shows up in the residuals
+        let similarity = fit(&gnss.to_vec(), &site.to_vec(), false)?;
+        assert!(similarity.horizontal_rms() > 1.);
+        assert!(similarity.vertical_rms() < 1e-6);
+
+        // Two points fit a similarity and a level height shift
+        let two = fit(&gnss[..2].to_vec(), &site[..2].to_vec(), false)?;
+        assert!(two.horizontal_rms() < 1e-6);
+        assert!(two.definition().ends_with("slope_x=0 slope_y=0"));
+
+        assert!(fit(&gnss[..2].to_vec(), &site[..2].to_vec(), true).is_err());
+        assert!(fit(&gnss.to_vec(), &site[..4].to_vec(), false).is_err());
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use super::{grids::GRIDS, operators::ACCESSORY_OPERATORS};
+use super::{blobs, ellipsoids::expand_custom_ellipsoids, grids, operators::ACCESSORY_OPERATORS};
 use geodesy_rs::{authoring::*, Error as RgError};
 use std::{collections::BTreeMap, sync::Arc};
 
@@ -11,9 +11,64 @@ pub struct WasmContext {
     resources: BTreeMap<String, String>,
     /// Instantiations of operators
     operators: BTreeMap<OpHandle, Op>,
+    /// Grids looked up before the global registry
+    grids: Option<Arc<grids::Registry>>,
+    /// Blobs looked up before the global ones
+    blobs: Option<Arc<blobs::Blobs>>,
+    /// Grids looked up before all others, eg read by geodesy_rs to compare with
+    #[cfg(test)]
+    reference_grids: BTreeMap<String, Arc<dyn Grid>>,
 }
 
 const BAD_ID_MESSAGE: RgError = RgError::General("WasmContext: Unknown operator id");
+/// The resource kind reported in [RgError::NotFound] for unknown grids
+pub(crate) const GRID_RESOURCE: &str = ": Grid resource";
+
+impl WasmContext {
+    /// Drop an instantiated operator
+    pub(crate) fn remove_op(&mut self, op: OpHandle) {
+        self.operators.remove(&op);
+    }
+
+    /// Look grids up in `grids` before the global registry
+    pub(crate) fn set_grids(&mut self, grids: Arc<grids::Registry>) {
+        self.grids = Some(grids);
+    }
+
+    /// The registry grids are looked up in first, the global one unless set with [WasmContext::set_grids]
+    pub(crate) fn grids(&self) -> &grids::Registry {
+        self.grids.as_deref().unwrap_or_else(|| grids::global())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn add_reference_grid(&mut self, key: &str, grid: Arc<dyn Grid>) {
+        self.reference_grids.insert(key.to_string(), grid);
+    }
+
+    /// Whether the operator `name` is `op` or one of its steps
+    pub(crate) fn has_step(&self, op: OpHandle, name: &str) -> bool {
+        fn has(op: &Op, name: &str) -> bool {
+            op.params.name == name || op.steps.iter().any(|step| has(step, name))
+        }
+        self.operators.get(&op).is_some_and(|op| has(op, name))
+    }
+
+    /// Look blobs up in `blobs` before the global ones
+    pub(crate) fn set_blobs(&mut self, blobs: Arc<blobs::Blobs>) {
+        self.blobs = Some(blobs);
+    }
+
+    /// Changes whenever one of the grids `keys` or a blob is (un)registered where this context
+    /// looks them up
+    pub(crate) fn resource_generation(&self, keys: &[String]) -> usize {
+        let generation = |registry: &grids::Registry| -> usize {
+            keys.iter().map(|key| registry.key_generation(key)).sum()
+        };
+        let local = self.grids.as_deref().map_or(0, generation);
+        let local_blobs = self.blobs.as_deref().map_or(0, blobs::Blobs::generation);
+        generation(grids::global()) + local + blobs::global().generation() + local_blobs
+    }
+}
 
 impl Context for WasmContext {
     fn new() -> WasmContext {
@@ -31,7 +86,13 @@ impl Context for WasmContext {
     }
 
     fn op(&mut self, definition: &str) -> Result<OpHandle, RgError> {
-        let op = Op::new(definition, self)?;
+        let definition = expand_custom_ellipsoids(definition);
+        let op = Op::new(&definition, self)?;
+        log::debug!(
+            "Constructed operator `{}` with {} step(s)",
+            definition,
+            op.descriptor.steps.len()
+        );
         let id = op.id;
         self.operators.insert(id, op);
         assert!(self.operators.contains_key(&id));
@@ -105,24 +166,38 @@ impl Context for WasmContext {
         ))
     }
 
-    fn get_blob(&self, _name: &str) -> Result<Vec<u8>, RgError> {
-        Err(RgError::General(
-            "Blob access by identifier not supported by the Wasm context provider",
+    /// Access blob resources by identifier
+    fn get_blob(&self, name: &str) -> Result<Vec<u8>, RgError> {
+        let local = self.blobs.as_ref().and_then(|blobs| blobs.get(name));
+        if let Some(blob) = local.or_else(|| blobs::global().get(name)) {
+            return Ok(blob);
+        }
+
+        Err(RgError::NotFound(
+            name.to_string(),
+            ": Blob resource".to_string(),
         ))
     }
 
     /// Access grid resources by identifier
     fn get_grid(&self, name: &str) -> Result<Arc<(dyn Grid + 'static)>, RgError> {
-        if let Some(grids) = GRIDS.get() {
-            if let Some(grid) = grids.lock().unwrap().get(name) {
-                // It's a reference clone
-                return Ok(grid.clone());
-            }
+        // Operators may ask for other interpolation than bilinear, see [grids::get_grid]
+        let (key, interpolation) = grids::grid_request(name);
+        #[cfg(test)]
+        if let Some(grid) = self.reference_grids.get(key) {
+            return Ok(grid.clone());
+        }
+        let local = self
+            .grids
+            .as_ref()
+            .and_then(|grids| grids.grid(key, interpolation));
+        if let Some(grid) = local.or_else(|| grids::global().grid(key, interpolation)) {
+            return Ok(grid);
         }
 
         Err(RgError::NotFound(
-            name.to_string(),
-            ": Grid resource".to_string(),
+            key.to_string(),
+            GRID_RESOURCE.to_string(),
         ))
     }
 }
@@ -9,8 +9,5 @@ use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen(js_name = parseProj)]
 pub fn parse_proj_wasm(definition: &str) -> WasmResult<String> {
-    match parse_proj(definition) {
-        Ok(geodesy_def) => Ok(geodesy_def),
-        Err(e) => Err(JsError::from(e)),
-    }
+    Ok(parse_proj(definition)?)
 }
@@ -1,10 +1,35 @@
+use crate::error::{Error, WasmResult};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::{cell::RefCell, str::FromStr};
 use wasm_bindgen::prelude::*;
 
 #[cfg(feature = "console_log")]
 #[wasm_bindgen]
 pub fn init_console_logger() {
-    use log::Level;
-    console_log::init_with_level(Level::Debug).expect("Error initialising logger");
+    init_logger(Some("debug".to_string()), None).expect("Error initialising logger");
+}
+
+/// Initialise logging for geodesy-wasm.
+///
+/// - `level`: one of `off`, `error`, `warn`, `info`, `debug` or `trace`. Defaults to `info`.
+/// - `callback`: an optional function called with a record `{level, target, message}` for each log.
+///   When given, logs are routed to it instead of the console.
+///
+/// May be called again to change the level or the callback.
+#[wasm_bindgen(js_name = initLogger)]
+pub fn init_logger(level: Option<String>, callback: Option<js_sys::Function>) -> WasmResult<()> {
+    let level = match level {
+        Some(level) => LevelFilter::from_str(&level)
+            .map_err(|_| Error::Invalid(format!("Unknown log level: {}", level)))?,
+        None => LevelFilter::Info,
+    };
+
+    // Only the first call installs the logger, later calls just reconfigure it
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+    LOG_CALLBACK.with(|cb| *cb.borrow_mut() = callback);
+
+    Ok(())
 }
 
 #[cfg(feature = "console_error_panic_hook")]
@@ -18,3 +43,55 @@ pub fn set_panic_hook() {
     // https://github.com/rustwasm/console_error_panic_hook#readme
     console_error_panic_hook::set_once();
 }
+
+// ----- L O G G E R -------------------------------------------------------------------
+
+static LOGGER: GeodesyLogger = GeodesyLogger;
+
+thread_local! {
+    // Wasm is single threaded so the callback lives in a thread local rather than the logger
+    static LOG_CALLBACK: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+}
+
+struct GeodesyLogger;
+
+impl Log for GeodesyLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Clone the callback out so a callback calling `initLogger` does not find it borrowed
+        let callback = LOG_CALLBACK.with(|cb| cb.borrow().clone());
+        let handled = match callback {
+            Some(callback) => {
+                let entry = js_sys::Object::new();
+                let message = record.args().to_string();
+                for (key, value) in [
+                    ("level", record.level().as_str()),
+                    ("target", record.target()),
+                    ("message", message.as_str()),
+                ] {
+                    let _ = js_sys::Reflect::set(&entry, &key.into(), &value.into());
+                }
+                // A throwing callback must not take the transformation down with it
+                let _ = callback.call1(&JsValue::NULL, &entry);
+                true
+            }
+            None => false,
+        };
+
+        #[cfg(feature = "console_log")]
+        if !handled {
+            console_log::log(record);
+        }
+        #[cfg(not(feature = "console_log"))]
+        let _ = handled;
+    }
+
+    fn flush(&self) {}
+}